@@ -125,7 +125,7 @@ impl<'a> Bucket<'a> {
             colors.select_nth_unstable_by_key(colors.len() / 2, |color| color[2])
         };
 
-        return (Self::new(left), Self::new(right));
+        (Self::new(left), Self::new(right))
     }
 }
 
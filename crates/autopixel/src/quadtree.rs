@@ -63,7 +63,7 @@ impl Decomposition {
             }
 
             // Check that there's at least 1 pixel of
-            if self.pixels[start..end].iter().any(|c| *c == *color) {
+            if self.pixels[start..end].contains(color) {
                 contains_color = true;
             }
         }
@@ -1,18 +1,35 @@
 //! The blog is composed of a series of markdown files rendered into HTML.
 //!
 //! The pipe line for building the blog is as follows:
-//! - Markdown files are read from the `content` directory.
+//! - Markdown files are read from the collection's directory of `content`, see
+//!   [`collection`](crate::collection).
 //! - Metadata is extracted from front matter and defaults are applied.
 //! - A navigation structure is built from the markdown files.
+//! - Pages are grouped into series, linking each part to the previous and next.
 //! - The markdown content is rendered into HTML and cached.
+//! - Requested pages are composed with their navigation, which depends on who is viewing them and
+//!   which pages have been published.
+//! - Internal links are collected so they can be checked with [`Blog::broken_links`].
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
 
+use chrono::{NaiveDate, Utc};
+use include_dir::{Dir, DirEntry};
+use log::warn;
+use markdown::Link;
 use maud::Markup;
 
 use crate::{
-    page::{parse_page, render_page},
-    SiteNav,
+    collection::{Collection, Visibility},
+    feed::{self, Entry},
+    layouts,
+    links::{self, BrokenLink, Pages},
+    page::{parse_page, render_markdown, render_page, PageMetadata, RenderedPage},
+    series::Series,
+    Publication, SiteNav, Viewer,
 };
 
 #[derive(Debug, serde::Deserialize)]
@@ -22,16 +39,48 @@ pub struct MarkdownFrontMatter {
     #[serde(default)]
     pub hidden: bool,
     pub order: Option<i32>,
+    /// When the page was last meaningfully updated, overrides the date from git history
+    pub updated: Option<NaiveDate>,
+    /// The series this page is a part of, see [`series`](crate::series)
+    pub series: Option<String>,
+    pub part: Option<u32>,
+    /// Drafts and scheduled pages, see [`publish`](crate::publish)
+    #[serde(flatten)]
+    pub publication: Publication,
+    /// Summary shown by search engines and link previews
+    pub description: Option<String>,
+    /// Image shown by link previews, relative to the site or absolute
+    pub image: Option<String>,
+    /// Canonical URL, for pages that are also published elsewhere
+    pub canonical: Option<String>,
+    /// The layout of this page, see [`layouts`](crate::layouts)
+    pub layout: Option<String>,
+    /// The default layout for the pages of this directory, only read from `home.md`
+    #[serde(rename = "directory-layout")]
+    pub directory_layout: Option<String>,
 }
 
 /// The blog is a map from location to rendered HTML content
 #[derive(Debug)]
 pub struct Blog {
-    rendered: HashMap<String, Markup>,
+    nav: SiteNav,
+    /// Page metadata and rendered markdown, keyed by path
+    rendered: HashMap<String, (PageMetadata, Markup)>,
+    series: HashMap<String, Series>,
+    /// Paths of hidden pages, they are not rendered but can still be linked to
+    hidden: HashSet<String>,
+    /// Every link found on a rendered page, paired with the page's path
+    links: Vec<(String, Link)>,
+    /// Where the site is hosted, used to build absolute URLs
+    site_url: String,
+    /// Where the pages are mounted and who can see them
+    collection: Collection,
+    /// The directory and prefix of every collection of the site, for links into the content
+    collections: Vec<(String, String)>,
 }
 
 impl Blog {
-    /// Create a new blog from a runtime file system path
+    // Create a new blog from a runtime file system path
     //    pub fn from_fs_path(path: impl AsRef<Path>) -> Self {
     //        let mut pages: HashMap<String, String> = HashMap::new();
     //        let base_path = path.as_ref();
@@ -59,50 +108,446 @@ impl Blog {
     //    }
 
     /// Create a new blog from an [`include_dir::Dir`]
-    pub fn from_include_dir(dir: &include_dir::Dir) -> Self {
-        let navbar = SiteNav::new(dir);
+    ///
+    /// `updated` maps paths to the date they were last changed, typically taken from git history,
+    /// and is used for pages that don't set `updated` in their front matter.
+    pub fn from_include_dir(dir: &include_dir::Dir, updated: &HashMap<String, NaiveDate>) -> Self {
+        let collection = Collection::default();
+        let collections = [collection.mount_point()];
+        Self::mount(dir, collection, &[], &collections, updated)
+    }
+
+    /// Create the blog of a collection rooted at `root`, leaving out the `excluded` subdirectories
+    /// which are collections of their own. `collections` are the mount points of every
+    /// collection, see [`Collection::mount_point`].
+    ///
+    /// Paths in `updated` are relative to the content rather than the collection.
+    pub(crate) fn mount(
+        root: &Dir,
+        collection: Collection,
+        excluded: &[String],
+        collections: &[(String, String)],
+        updated: &HashMap<String, NaiveDate>,
+    ) -> Self {
+        let navbar = SiteNav::mount(root, collection.prefix(), excluded);
         let mut pages: HashMap<String, String> = HashMap::new();
+        let mut dates = HashMap::new();
 
-        let mut stack = vec![dir];
+        let mut stack = vec![root];
         while let Some(path) = stack.pop() {
             for entry in path.entries() {
                 match entry {
-                    include_dir::DirEntry::Dir(dir) => stack.push(dir),
-                    include_dir::DirEntry::File(file) => {
-                        let relative_path = file.path().to_string_lossy().to_string();
+                    DirEntry::Dir(dir) if excluded.iter().any(|e| Path::new(e) == dir.path()) => {}
+                    DirEntry::Dir(dir) => stack.push(dir),
+                    DirEntry::File(file) => {
+                        let relative_path = file
+                            .path()
+                            .strip_prefix(root.path())
+                            .unwrap()
+                            .to_string_lossy()
+                            .to_string();
+                        if let Some(date) = updated.get(file.path().to_string_lossy().as_ref()) {
+                            dates.insert(relative_path.clone(), *date);
+                        }
                         pages.insert(relative_path, file.contents_utf8().unwrap().to_string());
                     }
                 }
             }
         }
 
-        Self::new(pages, navbar)
+        Self::new(pages, navbar, collection, collections, &dates)
     }
 
     /// Create a new blog from a map of un-rendered markdown content
     ///
     /// Takes as input a map from relative path to markdown content
-    fn new(pages: HashMap<String, String>, nav: SiteNav) -> Self {
+    fn new(
+        pages: HashMap<String, String>,
+        nav: SiteNav,
+        collection: Collection,
+        collections: &[(String, String)],
+        updated: &HashMap<String, NaiveDate>,
+    ) -> Self {
         let mut parsed = HashMap::new();
+        let mut hidden = HashSet::new();
+
+        // Directory default layouts are declared by their home page, hidden or not
+        let mut directory_layouts = HashMap::new();
 
         for (path, content) in pages.iter() {
-            let (metadata, md) = parse_page(path.as_str(), content);
+            let (mut metadata, md) = parse_page(path.as_str(), content);
+            metadata.updated = metadata.updated.or_else(|| updated.get(path).copied());
+
+            if let (Some(dir), Some(layout)) = (
+                path.strip_suffix("home.md"),
+                metadata.directory_layout.clone(),
+            ) {
+                directory_layouts.insert(dir.to_string(), layout);
+            }
 
             if !metadata.hidden {
                 parsed.insert(path.clone(), (metadata, md));
+            } else {
+                hidden.insert(path.clone());
             }
         }
 
+        // Pages without a layout use the default of the closest directory that declares one
+        for (path, (metadata, _)) in parsed.iter_mut() {
+            if metadata.layout.is_none() {
+                metadata.layout = path
+                    .match_indices('/')
+                    .map(|(i, _)| &path[..=i])
+                    .rev()
+                    .chain([""])
+                    .find_map(|dir| directory_layouts.get(dir).cloned());
+            }
+
+            if let Some(layout) = metadata.layout.as_deref().filter(|l| !layouts::exists(l)) {
+                warn!("Unknown layout {:?} on {}, using the default", layout, path);
+            }
+        }
+
+        let series = Series::collect(
+            parsed.values().map(|(metadata, _)| metadata),
+            collection.prefix(),
+        );
+
         let mut rendered = HashMap::new();
-        for (path, (metadata, md)) in parsed.iter() {
-            rendered.insert(path.clone(), render_page(&nav, path, metadata, md));
+        let mut links = vec![];
+        for (path, (mut metadata, md)) in parsed {
+            let (body, page_links) = render_markdown(&mut metadata, &md, collections);
+            links.extend(page_links.into_iter().map(|link| (path.clone(), link)));
+            rendered.insert(path, (metadata, body));
+        }
+
+        Self {
+            nav,
+            rendered,
+            series,
+            hidden,
+            links,
+            site_url: String::new(),
+            collection,
+            collections: collections.to_vec(),
+        }
+    }
+
+    /// The collection this blog serves
+    pub fn collection(&self) -> &Collection {
+        &self.collection
+    }
+
+    /// The URI of a page, `/m/home.md`
+    pub fn uri(&self, path: &str) -> String {
+        self.nav.uri(path)
+    }
+
+    /// Sets the URL the site is hosted at, `https://example.com`, used for canonical URLs and
+    /// link previews. Without it those tags are left out.
+    pub fn with_site_url(mut self, site_url: &str) -> Self {
+        self.site_url = site_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// The paths of every published page along with when they were last updated
+    pub fn published(&self) -> Vec<(&str, Option<NaiveDate>)> {
+        let now = Utc::now();
+        let mut pages = self
+            .rendered
+            .iter()
+            .filter(|(_, (metadata, _))| metadata.publication.visible(Viewer::Public, now))
+            .map(|(path, (metadata, _))| (path.as_str(), metadata.updated))
+            .collect::<Vec<_>>();
+
+        pages.sort();
+        pages
+    }
+
+    /// Gets the rendered HTML for a given path, as seen by `viewer`.
+    ///
+    /// Returns `None` for pages that don't exist or that the viewer can't see yet, and for every
+    /// page of a collection the viewer can't read.
+    pub fn get(&self, path: &str, viewer: Viewer) -> Option<Markup> {
+        let now = Utc::now();
+        let (metadata, body) = self.rendered.get(path)?;
+        if !self.collection.visibility.allows(viewer) || !metadata.publication.visible(viewer, now)
+        {
+            return None;
+        }
+
+        let series = metadata
+            .series
+            .as_ref()
+            .and_then(|name| self.series.get(name));
+
+        let page = RenderedPage {
+            nav: &self.nav,
+            site_url: &self.site_url,
+            path,
+            metadata,
+            series,
+            body,
+        };
+        Some(render_page(&page, viewer, now))
+    }
+
+    /// Renders the navigation bar as seen from `path`, for pages that aren't part of the blog
+    pub fn nav(&self, path: &str, viewer: Viewer) -> Markup {
+        self.nav.render(path, viewer, Utc::now())
+    }
+
+    /// Suggests the published page closest to a path that doesn't exist, if any is close enough.
+    /// Private collections don't make suggestions.
+    pub fn suggest(&self, path: &str) -> Option<&str> {
+        if !self.collection.visibility.allows(Viewer::Public) {
+            return None;
+        }
+
+        let now = Utc::now();
+        let path = path.to_lowercase();
+
+        self.rendered
+            .iter()
+            .filter(|(_, (metadata, _))| metadata.publication.visible(Viewer::Public, now))
+            .map(|(page, _)| (levenshtein(&path, &page.to_lowercase()), page.as_str()))
+            .filter(|(distance, page)| *distance <= (page.len() / 3).max(2))
+            .min()
+            .map(|(_, page)| page)
+    }
+
+    /// Renders the Atom feed of the published pages, see [`feed`](crate::feed).
+    ///
+    /// Returns `None` if the collection doesn't have a feed, private collections never do.
+    pub fn feed(&self) -> Option<Markup> {
+        if !self.collection.feed || self.collection.visibility == Visibility::Private {
+            return None;
         }
 
-        Self { rendered }
+        let now = Utc::now();
+        let entries = self
+            .rendered
+            .iter()
+            .filter(|(path, (metadata, _))| {
+                !path.ends_with("home.md") && metadata.publication.visible(Viewer::Public, now)
+            })
+            .map(|(path, (metadata, body))| Entry {
+                url: format!("{}{}", self.site_url, self.uri(path)),
+                metadata,
+                body,
+            })
+            .collect();
+
+        let title = self
+            .collection
+            .title
+            .as_deref()
+            .unwrap_or(&self.collection.name);
+        let url = format!("{}{}", self.site_url, self.collection.prefix());
+
+        Some(feed::atom(title, &url, entries))
+    }
+
+    /// Checks every internal link and image against the content tree and the baked `static`
+    /// directory, returning the broken ones sorted by page and line. Pages are reported by their
+    /// path in the content rather than the collection.
+    pub fn broken_links(&self, statics: &Dir) -> Vec<BrokenLink> {
+        self.broken_links_among(&[self.pages()], statics)
+    }
+
+    /// The pages links can lead to
+    pub(crate) fn pages(&self) -> Pages<'_> {
+        Pages {
+            prefix: self.collection.prefix(),
+            rendered: self.rendered.keys().map(String::as_str).collect(),
+            hidden: self.hidden.iter().map(String::as_str).collect(),
+        }
+    }
+
+    /// Like [`Blog::broken_links`], checking links into other collections against their `pages`
+    pub(crate) fn broken_links_among(&self, pages: &[Pages], statics: &Dir) -> Vec<BrokenLink> {
+        let prefix = self.collection.prefix();
+
+        let mut broken = self
+            .links
+            .iter()
+            .filter_map(|(page, link)| {
+                links::check(page, link, prefix, &self.collections, pages, statics)
+            })
+            .map(|mut link| {
+                if !self.collection.root().is_empty() {
+                    link.page = format!("{}/{}", self.collection.root(), link.page);
+                }
+                link
+            })
+            .collect::<Vec<_>>();
+
+        broken.sort_by(|a, b| a.page.cmp(&b.page).then(a.line.cmp(&b.line)));
+        broken
+    }
+}
+
+/// The number of single character edits needed to turn `a` into `b`
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use include_dir::{DirEntry, File};
+
+    use crate::page::absolute_url;
+
+    use super::*;
+
+    const CONTENT: Dir = Dir::new(
+        "",
+        &[
+            DirEntry::File(File::new("home.md", b"# Home")),
+            DirEntry::File(File::new("draft.md", b"---\ndraft: true\n---\n# Draft")),
+            DirEntry::File(File::new(
+                "future.md",
+                b"---\npublish_at: 2999-01-01\n---\n# Future",
+            )),
+            DirEntry::File(File::new(
+                "past.md",
+                b"---\npublish_at: 2000-01-01\ndescription: Long ago\nimage: /static/img/old.png\n---\n# Past",
+            )),
+        ],
+    );
+
+    #[test]
+    fn drafts() {
+        let blog = Blog::from_include_dir(&CONTENT, &HashMap::new());
+
+        assert!(blog.get("draft.md", Viewer::Public).is_none());
+        assert!(blog.get("future.md", Viewer::Public).is_none());
+        assert!(blog.get("past.md", Viewer::Public).is_some());
+
+        let draft = blog.get("draft.md", Viewer::Admin).unwrap().into_string();
+        assert!(draft.contains(r#"<p class="draft-banner">Draft"#));
+        let future = blog.get("future.md", Viewer::Admin).unwrap().into_string();
+        assert!(future.contains("Scheduled, publishes January 1, 2999"));
+
+        // Unpublished pages are only in the admin's navigation
+        let home = blog.get("home.md", Viewer::Public).unwrap().into_string();
+        assert!(home.contains("/m/past.md") && !home.contains("/m/draft.md"));
+        let home = blog.get("home.md", Viewer::Admin).unwrap().into_string();
+        assert!(home.contains("/m/draft.md") && home.contains("/m/future.md"));
     }
 
-    /// Gets the rendered HTML for a given path
-    pub fn get(&self, path: &str) -> Option<&Markup> {
-        self.rendered.get(path)
+    #[test]
+    fn suggest() {
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+
+        let blog = Blog::from_include_dir(&CONTENT, &HashMap::new());
+        assert_eq!(blog.suggest("Past.md"), Some("past.md"));
+        assert_eq!(blog.suggest("hom.md"), Some("home.md"));
+        // Unpublished pages aren't given away
+        assert_eq!(blog.suggest("draft.md"), None);
+        assert_eq!(blog.suggest("robotics/controls.md"), None);
+    }
+
+    #[test]
+    fn seo() {
+        let blog = Blog::from_include_dir(&CONTENT, &HashMap::new());
+        let past = blog.get("past.md", Viewer::Public).unwrap().into_string();
+        assert!(past.contains(r#"<meta name="description" content="Long ago">"#));
+        assert!(!past.contains("canonical"));
+
+        let blog = blog.with_site_url("https://example.com/");
+        let past = blog.get("past.md", Viewer::Public).unwrap().into_string();
+        assert!(past.contains(r#"<link rel="canonical" href="https://example.com/m/past.md">"#));
+        assert!(past.contains(r#"content="https://example.com/s/img/old.png""#));
+
+        // Images relative to the site don't need the leading slash
+        let site = "https://example.com";
+        for (url, absolute) in [
+            ("static/img/new.png", "https://example.com/s/img/new.png"),
+            ("img/new.png", "https://example.com/img/new.png"),
+            (
+                "https://cdn.example.com/a.png",
+                "https://cdn.example.com/a.png",
+            ),
+        ] {
+            assert_eq!(absolute_url(site, url).as_deref(), Some(absolute));
+        }
+        assert_eq!(absolute_url("", "/static/img/new.png"), None);
+
+        let published = blog.published();
+        let paths = published.iter().map(|(path, _)| *path).collect::<Vec<_>>();
+        assert_eq!(paths, ["home.md", "past.md"]);
+    }
+
+    #[test]
+    fn feed() {
+        let blog =
+            Blog::from_include_dir(&CONTENT, &HashMap::new()).with_site_url("https://example.com");
+        let feed = blog.feed().unwrap().into_string();
+
+        assert!(feed.starts_with(r#"<?xml version="1.0" encoding="utf-8"?><feed"#));
+        assert!(feed.contains(r#"<link rel="self" href="https://example.com/m/feed.xml"></link>"#));
+        assert!(feed.contains("<id>https://example.com/m/past.md</id>"));
+        assert!(feed.contains("<summary>Long ago</summary>"));
+        assert!(feed.contains("<content type=\"html\">&lt;h1"));
+        // Unpublished pages and pages without a date are left out
+        assert_eq!(feed.matches("<entry>").count(), 1);
+    }
+
+    #[test]
+    fn layouts() {
+        const CONTENT: Dir = Dir::new(
+            "",
+            &[
+                DirEntry::File(File::new("home.md", b"---\nlayout: landing\n---\n# Home")),
+                DirEntry::Dir(Dir::new(
+                    "notes",
+                    &[
+                        DirEntry::File(File::new(
+                            "notes/home.md",
+                            b"---\ndirectory-layout: sidebar\n---\n# Notes",
+                        )),
+                        DirEntry::File(File::new("notes/a.md", b"# A\n\n## Setup")),
+                        DirEntry::File(File::new(
+                            "notes/b.md",
+                            b"---\nlayout: default\n---\n# B\n\n## Setup",
+                        )),
+                    ],
+                )),
+            ],
+        );
+
+        let blog = Blog::from_include_dir(&CONTENT, &HashMap::new());
+        let home = blog.get("home.md", Viewer::Public).unwrap().into_string();
+        assert!(home.contains("layout-landing"));
+
+        // Pages inherit their directory's layout unless they pick their own
+        let a = blog
+            .get("notes/a.md", Viewer::Public)
+            .unwrap()
+            .into_string();
+        assert!(a.contains(r##"<a href="#setup">Setup</a>"##));
+        assert!(a.contains(r#"id="setup""#));
+        let b = blog
+            .get("notes/b.md", Viewer::Public)
+            .unwrap()
+            .into_string();
+        assert!(!b.contains("layout-sidebar"));
+        // Headings only get ids when the layout links to them
+        assert!(!b.contains(r#"id="setup""#));
     }
 }
@@ -0,0 +1,348 @@
+//! Content collections.
+//!
+//! The content directory can be split into collections, each mounted at its own prefix with its
+//! own navigation, visibility and feed. A collection holds the pages of a directory, except for
+//! subdirectories mounted as collections of their own:
+//!
+//! ```toml
+//! [[collections]]
+//! name = "blog"
+//! prefix = "/m"
+//!
+//! [[collections]]
+//! name = "notes"
+//! prefix = "/notes"
+//! dir = "notes"
+//! visibility = "private"
+//! ```
+
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use include_dir::Dir;
+use serde::{Deserialize, Serialize};
+
+use crate::{Blog, BrokenLink, Viewer};
+
+/// Who can read a collection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Anyone, and it's listed in the sitemap
+    #[default]
+    Public,
+    /// Anyone with a link, it's left out of the sitemap and crawlers are asked to stay away
+    Unlisted,
+    /// Only the admin, it has no feed
+    Private,
+}
+
+impl Visibility {
+    /// Returns true if `viewer` can read collections with this visibility
+    pub fn allows(&self, viewer: Viewer) -> bool {
+        *self != Visibility::Private || viewer == Viewer::Admin
+    }
+}
+
+/// A directory of content mounted at a prefix
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Collection {
+    pub name: String,
+    /// Title of the feed, defaults to the name
+    pub title: Option<String>,
+    /// Where the collection is served, `/notes`
+    pub prefix: String,
+    /// The directory of the content holding the collection, the whole content by default
+    #[serde(default)]
+    pub dir: String,
+    #[serde(default)]
+    pub visibility: Visibility,
+    /// Publish an Atom feed at `{prefix}/feed.xml`
+    #[serde(default = "default_feed")]
+    pub feed: bool,
+}
+
+fn default_feed() -> bool {
+    true
+}
+
+impl Default for Collection {
+    /// The whole content mounted at `/m`
+    fn default() -> Self {
+        Collection {
+            name: "blog".to_string(),
+            title: None,
+            prefix: "/m".to_string(),
+            dir: String::new(),
+            visibility: Visibility::Public,
+            feed: true,
+        }
+    }
+}
+
+impl Collection {
+    /// The directory of the collection within the content, without slashes
+    pub(crate) fn root(&self) -> &str {
+        self.dir.trim_matches('/')
+    }
+
+    /// The directory of the collection and its prefix, as [`RenderOptions::collections`] takes
+    /// them
+    ///
+    /// [`RenderOptions::collections`]: markdown::RenderOptions::collections
+    pub(crate) fn mount_point(&self) -> (String, String) {
+        (self.root().to_string(), self.prefix().to_string())
+    }
+
+    /// The prefix of the collection, without a trailing slash
+    pub fn prefix(&self) -> &str {
+        self.prefix.trim_end_matches('/')
+    }
+
+    /// Checks the prefix can be mounted, it must start with a slash and not be the site root
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.prefix.starts_with('/') || self.prefix().is_empty() {
+            return Err(format!(
+                "Collection {:?} has an invalid prefix {:?}, expected something like \"/notes\"",
+                self.name, self.prefix
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Every collection of the site
+#[derive(Debug)]
+pub struct Collections {
+    blogs: Vec<Blog>,
+}
+
+impl Collections {
+    /// Builds each collection from its directory of `content`.
+    ///
+    /// `updated` maps paths of the content to the date they were last changed, see
+    /// [`Blog::from_include_dir`].
+    ///
+    /// # Panics
+    ///
+    /// If a collection's directory doesn't exist, or its prefix is invalid or already taken.
+    pub fn from_include_dir(
+        content: &Dir,
+        collections: &[Collection],
+        updated: &HashMap<String, NaiveDate>,
+    ) -> Self {
+        let mut blogs: Vec<Blog> = vec![];
+        let mount_points = collections
+            .iter()
+            .map(Collection::mount_point)
+            .collect::<Vec<_>>();
+
+        for collection in collections {
+            if let Err(e) = collection.validate() {
+                panic!("{}", e);
+            }
+            if let Some(other) = blogs
+                .iter()
+                .find(|blog| blog.collection().prefix() == collection.prefix())
+            {
+                panic!(
+                    "Collections {:?} and {:?} are both mounted at {}",
+                    other.collection().name,
+                    collection.name,
+                    collection.prefix
+                );
+            }
+
+            let root = match collection.root() {
+                "" => content,
+                dir => content.get_dir(dir).unwrap_or_else(|| {
+                    panic!(
+                        "Collection {:?} directory {:?} does not exist",
+                        collection.name, dir
+                    )
+                }),
+            };
+
+            // Subdirectories belong to the deepest collection containing them
+            let excluded = collections
+                .iter()
+                .map(Collection::root)
+                .filter(|dir| match collection.root() {
+                    "" => !dir.is_empty(),
+                    root => dir.strip_prefix(root).is_some_and(|d| d.starts_with('/')),
+                })
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+
+            blogs.push(Blog::mount(
+                root,
+                collection.clone(),
+                &excluded,
+                &mount_points,
+                updated,
+            ));
+        }
+
+        Collections { blogs }
+    }
+
+    /// Sets the URL the site is hosted at for every collection, see [`Blog::with_site_url`]
+    pub fn with_site_url(self, site_url: &str) -> Self {
+        Collections {
+            blogs: self
+                .blogs
+                .into_iter()
+                .map(|blog| blog.with_site_url(site_url))
+                .collect(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Blog> {
+        self.blogs.iter()
+    }
+
+    /// The first collection, used for pages that don't belong to any
+    pub fn main(&self) -> Option<&Blog> {
+        self.blogs.first()
+    }
+
+    /// Finds the collection serving `uri`, returning it along with the path of the page within it
+    pub fn find<'a>(&self, uri: &'a str) -> Option<(&Blog, &'a str)> {
+        self.blogs
+            .iter()
+            .filter_map(|blog| {
+                let rest = uri.strip_prefix(blog.collection().prefix())?;
+                match rest {
+                    "" => Some((blog, rest)),
+                    rest => rest.strip_prefix('/').map(|path| (blog, path)),
+                }
+            })
+            .max_by_key(|(blog, _)| blog.collection().prefix().len())
+    }
+
+    /// The URIs of every published page of the public collections, with when they were updated
+    pub fn sitemap(&self) -> Vec<(String, Option<NaiveDate>)> {
+        self.blogs
+            .iter()
+            .filter(|blog| blog.collection().visibility == Visibility::Public)
+            .flat_map(|blog| {
+                blog.published()
+                    .into_iter()
+                    .map(|(path, updated)| (blog.uri(path), updated))
+            })
+            .collect()
+    }
+
+    /// The broken links of every collection, see [`Blog::broken_links`]
+    pub fn broken_links(&self, statics: &Dir) -> Vec<BrokenLink> {
+        let pages = self.blogs.iter().map(Blog::pages).collect::<Vec<_>>();
+        self.blogs
+            .iter()
+            .flat_map(|blog| blog.broken_links_among(&pages, statics))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use include_dir::{DirEntry, File};
+
+    use super::*;
+
+    const CONTENT: Dir = Dir::new(
+        "",
+        &[
+            DirEntry::File(File::new("home.md", b"# Home")),
+            DirEntry::File(File::new(
+                "post.md",
+                b"# Post\n\nHad an [idea](/content/notes/idea.md)",
+            )),
+            DirEntry::Dir(Dir::new(
+                "notes",
+                &[
+                    DirEntry::File(File::new("notes/home.md", b"# Notes")),
+                    DirEntry::File(File::new(
+                        "notes/idea.md",
+                        b"# Idea\n\nFrom the [post](/content/post.md), not [here](/m/notes/home.md)",
+                    )),
+                ],
+            )),
+        ],
+    );
+
+    fn notes() -> Collection {
+        Collection {
+            name: "notes".to_string(),
+            title: None,
+            prefix: "/notes/".to_string(),
+            dir: "notes".to_string(),
+            visibility: Visibility::Private,
+            feed: true,
+        }
+    }
+
+    #[test]
+    fn collections() {
+        let collections = Collections::from_include_dir(
+            &CONTENT,
+            &[Collection::default(), notes()],
+            &HashMap::new(),
+        );
+
+        // Mounted directories are left out of their parent collection
+        let (blog, path) = collections.find("/m/notes/idea.md").unwrap();
+        assert_eq!(blog.collection().name, "blog");
+        assert!(blog.get(path, Viewer::Admin).is_none());
+        let home = blog.get("home.md", Viewer::Public).unwrap().into_string();
+        assert!(home.contains("/m/post.md") && !home.contains("notes"));
+
+        let (notes, path) = collections.find("/notes/idea.md").unwrap();
+        assert_eq!(
+            (notes.collection().name.as_str(), path),
+            ("notes", "idea.md")
+        );
+        assert!(notes.get(path, Viewer::Public).is_none());
+        let idea = notes.get(path, Viewer::Admin).unwrap().into_string();
+        assert!(idea.contains(r#"href="/notes/home.md""#));
+        assert!(notes.suggest("ideas.md").is_none());
+        assert!(notes.feed().is_none());
+
+        assert!(collections.find("/notesy/idea.md").is_none());
+
+        // Links into the content go to the collection holding the page, and are checked there
+        let (blog, _) = collections.find("/m/post.md").unwrap();
+        let post = blog.get("post.md", Viewer::Public).unwrap().into_string();
+        assert!(post.contains(r#"href="/notes/idea.md""#));
+        assert!(idea.contains(r#"href="/m/post.md""#));
+        let broken = collections
+            .broken_links(&Dir::new("", &[]))
+            .into_iter()
+            .map(|link| (link.page, link.url))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            broken,
+            [("notes/idea.md".to_string(), "/m/notes/home.md".to_string())]
+        );
+
+        let sitemap = collections.sitemap();
+        let uris = sitemap
+            .iter()
+            .map(|(uri, _)| uri.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(uris, ["/m/home.md", "/m/post.md"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "are both mounted at")]
+    fn duplicate_prefix() {
+        let collection = Collection {
+            dir: "notes".to_string(),
+            ..Collection::default()
+        };
+        Collections::from_include_dir(
+            &CONTENT,
+            &[Collection::default(), collection],
+            &HashMap::new(),
+        );
+    }
+}
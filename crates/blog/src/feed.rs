@@ -0,0 +1,69 @@
+//! Atom feeds of collections.
+//!
+//! A collection's feed lists its published pages that have a date, either `publish_at` or when
+//! they were last updated, newest first. Directory home pages are left out.
+
+use chrono::{DateTime, Utc};
+use maud::{html, Markup, PreEscaped};
+
+use crate::page::PageMetadata;
+
+/// A page in a feed
+pub(crate) struct Entry<'a> {
+    /// Absolute URL of the page
+    pub url: String,
+    pub metadata: &'a PageMetadata,
+    pub body: &'a Markup,
+}
+
+impl Entry<'_> {
+    /// When the page was last updated or published, whichever is later
+    fn updated(&self) -> Option<DateTime<Utc>> {
+        let updated = self
+            .metadata
+            .updated
+            .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+
+        updated.max(self.metadata.publication.publish_at)
+    }
+}
+
+/// Renders an Atom feed, `url` is the absolute URL of the collection
+pub(crate) fn atom(title: &str, url: &str, entries: Vec<Entry>) -> Markup {
+    let mut entries = entries
+        .into_iter()
+        .filter_map(|entry| Some((entry.updated()?, entry)))
+        .collect::<Vec<_>>();
+    entries.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    let updated = entries
+        .first()
+        .map(|(updated, _)| *updated)
+        .unwrap_or_else(Utc::now);
+
+    html! {
+        (PreEscaped(r#"<?xml version="1.0" encoding="utf-8"?>"#))
+        feed xmlns="http://www.w3.org/2005/Atom" {
+            title { (title) }
+            id { (url) "/" }
+            link rel="self" href=(format!("{}/feed.xml", url)) {}
+            link href=(format!("{}/home.md", url)) {}
+            updated { (updated.to_rfc3339()) }
+            @for (updated, entry) in &entries {
+                entry {
+                    title { (entry.metadata.title) }
+                    id { (entry.url) }
+                    link href=(entry.url) {}
+                    updated { (updated.to_rfc3339()) }
+                    @if let Some(published) = entry.metadata.publication.publish_at {
+                        published { (published.to_rfc3339()) }
+                    }
+                    @if let Some(description) = &entry.metadata.description {
+                        summary { (description) }
+                    }
+                    content type="html" { (entry.body.0) }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Page layouts.
+//!
+//! A layout arranges the pieces of a page (navigation, metadata, content, ...) into the final
+//! document. Pages pick one with the `layout` front matter key, and a directory's `home.md` can set
+//! the default for every page below it with `directory-layout`:
+//!
+//! ```yaml
+//! directory-layout: sidebar
+//! ```
+//!
+//! The built in layouts are `default`, `sidebar` (with an outline of the page's sections) and
+//! `landing` (without the page metadata). More can be added with [`register`].
+//!
+//! Headings are only given ids on pages with a layout linking to them from an outline, so every
+//! other page keeps the same markup.
+
+use std::{collections::HashMap, sync::RwLock};
+
+use markdown::Heading;
+use maud::{html, Markup, DOCTYPE};
+use once_cell::sync::Lazy;
+
+use crate::Head;
+
+/// Everything a layout needs to render a page
+pub struct LayoutContext<'a> {
+    pub head: Head<'a>,
+    pub nav: Markup,
+    /// Shown on drafts and scheduled pages
+    pub banner: Option<Markup>,
+    /// Last updated date, reading time and word count
+    pub meta: Markup,
+    pub series_header: Option<Markup>,
+    pub series_footer: Option<Markup>,
+    /// The headings of the page, in order
+    pub outline: &'a [Heading],
+    /// The rendered markdown
+    pub body: &'a Markup,
+}
+
+/// A layout renders a complete page
+pub type Layout = fn(&LayoutContext) -> Markup;
+
+/// The layout used when a page doesn't pick one, or picks one that doesn't exist
+pub const DEFAULT: &str = "default";
+
+/// The built in layouts with an outline of the page's sections
+const OUTLINED: &[&str] = &["sidebar"];
+
+static LAYOUTS: Lazy<RwLock<HashMap<String, Layout>>> = Lazy::new(|| {
+    let builtins: [(&str, Layout); 3] = [
+        (DEFAULT, default),
+        ("sidebar", sidebar),
+        ("landing", landing),
+    ];
+
+    RwLock::new(
+        builtins
+            .into_iter()
+            .map(|(name, layout)| (name.to_string(), layout))
+            .collect(),
+    )
+});
+
+/// Registers a layout under `name`, replacing any layout with the same name
+pub fn register(name: &str, layout: Layout) {
+    LAYOUTS.write().unwrap().insert(name.to_string(), layout);
+}
+
+/// Returns true if a layout is registered under `name`
+pub fn exists(name: &str) -> bool {
+    LAYOUTS.read().unwrap().contains_key(name)
+}
+
+/// Returns true if pages with the layout `name` link to their headings
+pub(crate) fn has_outline(name: &str) -> bool {
+    OUTLINED.contains(&name)
+}
+
+/// Renders a page with the layout registered under `name`, falling back to the default layout
+pub(crate) fn render(name: &str, context: &LayoutContext) -> Markup {
+    let layouts = LAYOUTS.read().unwrap();
+    let layout = layouts
+        .get(name)
+        .or_else(|| layouts.get(DEFAULT))
+        .copied()
+        .unwrap_or(default);
+    drop(layouts);
+
+    layout(context)
+}
+
+/// Header, navigation and content
+pub fn default(context: &LayoutContext) -> Markup {
+    html! {
+        (DOCTYPE)
+        (context.head)
+        main {
+            (context.nav)
+            (article(context))
+        }
+    }
+}
+
+/// The default layout with an outline of the page's sections beside the content
+pub fn sidebar(context: &LayoutContext) -> Markup {
+    let outline = context
+        .outline
+        .iter()
+        .filter(|heading| (2..=3).contains(&heading.level))
+        .collect::<Vec<_>>();
+
+    html! {
+        (DOCTYPE)
+        (context.head)
+        main.layout-sidebar {
+            (context.nav)
+            div.sidebar-columns {
+                @if !outline.is_empty() {
+                    aside.outline {
+                        p.outline-title { "On this page" }
+                        ul {
+                            @for heading in outline {
+                                li class=(format!("outline-h{}", heading.level)) {
+                                    a href=(format!("#{}", heading.id)) { (heading.text) }
+                                }
+                            }
+                        }
+                    }
+                }
+                div.sidebar-content {
+                    (article(context))
+                }
+            }
+        }
+    }
+}
+
+/// Navigation and content only, for pages introducing the site or a section
+pub fn landing(context: &LayoutContext) -> Markup {
+    html! {
+        (DOCTYPE)
+        (context.head)
+        main.layout-landing {
+            (context.nav)
+            @if let Some(banner) = &context.banner {
+                (banner)
+            }
+            (context.body)
+        }
+    }
+}
+
+/// The banner, metadata, series links and content shared by most layouts
+fn article(context: &LayoutContext) -> Markup {
+    html! {
+        @if let Some(banner) = &context.banner {
+            (banner)
+        }
+        (context.meta)
+        @if let Some(header) = &context.series_header {
+            (header)
+        }
+        (context.body)
+        @if let Some(footer) = &context.series_footer {
+            (footer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maud::PreEscaped;
+
+    use super::*;
+
+    fn context<'a>(outline: &'a [Heading], body: &'a Markup) -> LayoutContext<'a> {
+        LayoutContext {
+            head: Head::new("Test"),
+            nav: html! { nav {} },
+            banner: None,
+            meta: html! { p.page-meta { "1 min read" } },
+            series_header: None,
+            series_footer: None,
+            outline,
+            body,
+        }
+    }
+
+    #[test]
+    fn layouts() {
+        let outline = [
+            Heading {
+                level: 1,
+                text: "Title".to_string(),
+                id: "title".to_string(),
+            },
+            Heading {
+                level: 2,
+                text: "Setup".to_string(),
+                id: "setup".to_string(),
+            },
+        ];
+        let body = PreEscaped("<p>Hi</p>".to_string());
+        let context = context(&outline, &body);
+
+        let html = render("sidebar", &context).into_string();
+        assert!(html.contains(r##"<li class="outline-h2"><a href="#setup">Setup</a></li>"##));
+        assert!(!html.contains("#title"));
+
+        let html = render("landing", &context).into_string();
+        assert!(!html.contains("page-meta"));
+
+        // Unknown layouts fall back to the default
+        assert_eq!(
+            render("nope", &context).into_string(),
+            default(&context).into_string()
+        );
+
+        register("bare", |context| context.body.clone());
+        assert_eq!(render("bare", &context).into_string(), "<p>Hi</p>");
+    }
+}
@@ -1,22 +1,79 @@
 mod blog;
+pub mod collection;
+mod feed;
+pub mod layouts;
+mod links;
 mod navbar;
 mod page;
+mod publish;
+mod series;
 
-use maud::{html, Markup};
+use maud::{html, Markup, Render};
 
 pub use blog::{Blog, MarkdownFrontMatter};
+pub use collection::{Collection, Collections, Visibility};
+pub use links::{BrokenLink, Reason};
 pub use navbar::SiteNav;
 pub use page::Page;
+pub use publish::{Publication, Status, Viewer};
 
-pub fn header(title: &str) -> Markup {
-    html! {
-        head {
-            meta charset="utf-8";
-            meta name="viewport" content="width=device-width, initial-scale=1.0";
-            link rel="stylesheet" href="/s/water.css";
-            link rel="stylesheet" href="/s/style.css";
-
-            title { (title) }
+/// The `<head>` of a page, along with its search engine and social media metadata
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Head<'a> {
+    pub title: &'a str,
+    pub description: Option<&'a str>,
+    /// Absolute URL of the image shown in link previews
+    pub image: Option<&'a str>,
+    /// Absolute canonical URL of the page
+    pub canonical: Option<&'a str>,
+}
+
+impl<'a> Head<'a> {
+    pub fn new(title: &'a str) -> Self {
+        Head {
+            title,
+            ..Head::default()
+        }
+    }
+}
+
+impl Render for Head<'_> {
+    fn render(&self) -> Markup {
+        html! {
+            head {
+                meta charset="utf-8";
+                meta name="viewport" content="width=device-width, initial-scale=1.0";
+                link rel="stylesheet" href="/s/water.css";
+                link rel="stylesheet" href="/s/style.css";
+                link rel="stylesheet" href="/s/highlight/light.css" media="(prefers-color-scheme: light)";
+                link rel="stylesheet" href="/s/highlight/dark.css" media="not all and (prefers-color-scheme: light)";
+
+                title { (self.title) }
+
+                @if let Some(description) = self.description {
+                    meta name="description" content=(description);
+                    meta property="og:description" content=(description);
+                }
+                @if let Some(canonical) = self.canonical {
+                    link rel="canonical" href=(canonical);
+                    meta property="og:url" content=(canonical);
+                }
+                @if self.canonical.is_some() || self.description.is_some() {
+                    meta property="og:type" content="article";
+                    meta property="og:title" content=(self.title);
+                }
+                @if let Some(image) = self.image {
+                    meta property="og:image" content=(image);
+                    meta name="twitter:card" content="summary_large_image";
+                    meta name="twitter:image" content=(image);
+                } @else if self.canonical.is_some() {
+                    meta name="twitter:card" content="summary";
+                }
+            }
         }
     }
 }
+
+pub fn header(title: &str) -> Markup {
+    Head::new(title).render()
+}
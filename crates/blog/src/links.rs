@@ -0,0 +1,162 @@
+//! Internal link checking.
+//!
+//! Links are collected from every page while the [`Blog`](crate::Blog) is built and resolved
+//! against the pages of every collection and the baked static files. Links to other services
+//! (`/pixel`, `/f/`, ...) and external websites are not checked.
+
+use std::{collections::HashSet, fmt};
+
+use include_dir::Dir;
+use markdown::{content_uri, Link};
+
+/// Why a link is considered broken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// The target does not exist
+    Missing,
+    /// The target exists but is hidden, so it can't be visited yet
+    Hidden,
+}
+
+/// A link that doesn't lead anywhere
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenLink {
+    /// The page containing the link
+    pub page: String,
+    /// The line of the page the link is on
+    pub line: usize,
+    /// The destination as written in the markdown
+    pub url: String,
+    pub reason: Reason,
+}
+
+impl fmt::Display for BrokenLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.reason {
+            Reason::Missing => "missing",
+            Reason::Hidden => "hidden",
+        };
+        write!(f, "{}:{}: {} ({})", self.page, self.line, self.url, reason)
+    }
+}
+
+/// The pages of a collection that links can lead to
+pub(crate) struct Pages<'a> {
+    /// Where the collection is mounted, without a trailing slash
+    pub prefix: &'a str,
+    pub rendered: HashSet<&'a str>,
+    pub hidden: HashSet<&'a str>,
+}
+
+/// A link target resolved to the tree it lives in
+enum Target<'a> {
+    Page(&'a Pages<'a>, String),
+    Static(String),
+}
+
+/// Checks a single link found on `page` of the collection mounted at `prefix`, returning why it
+/// is broken if it is.
+///
+/// `collections` are the directories of the content mounted as collections along with their
+/// prefix, and `pages` the pages of each.
+pub(crate) fn check(
+    page: &str,
+    link: &Link,
+    prefix: &str,
+    collections: &[(String, String)],
+    pages: &[Pages],
+    statics: &Dir,
+) -> Option<BrokenLink> {
+    let reason = match resolve(page, &link.url, prefix, collections, pages) {
+        None => return None,
+        Some(Err(())) => Reason::Missing,
+        Some(Ok(Target::Page(pages, path))) if pages.hidden.contains(path.as_str()) => {
+            Reason::Hidden
+        }
+        Some(Ok(Target::Page(pages, path))) if pages.rendered.contains(path.as_str()) => {
+            return None
+        }
+        Some(Ok(Target::Static(path))) if statics.get_file(&path).is_some() => return None,
+        Some(Ok(_)) => Reason::Missing,
+    };
+
+    Some(BrokenLink {
+        page: page.to_string(),
+        line: link.line,
+        url: link.url.clone(),
+        reason,
+    })
+}
+
+/// Resolves a link to its target.
+///
+/// Returns `None` for links that aren't checked, and `Some(Err(()))` for relative links that
+/// escape the content directory.
+fn resolve<'a>(
+    page: &str,
+    url: &str,
+    prefix: &str,
+    collections: &[(String, String)],
+    pages: &'a [Pages<'a>],
+) -> Option<Result<Target<'a>, ()>> {
+    // Fragments and queries don't change the file being linked to
+    let url = url.split(['#', '?']).next().unwrap_or_default();
+
+    // Same page anchors, external websites and other schemes (`mailto:`, `file:`, ...)
+    if url.is_empty() || url.starts_with("//") || url.split('/').next()?.contains(':') {
+        return None;
+    }
+
+    // Links into the content directory are served by the collection holding them
+    let content = url
+        .strip_prefix("/content/")
+        .map(|path| content_uri(collections, path));
+    let url = content.as_deref().unwrap_or(url);
+
+    // The page of the collection with the longest prefix
+    if let Some((pages, path)) = pages
+        .iter()
+        .filter_map(|pages| {
+            let path = url.strip_prefix(pages.prefix)?.strip_prefix('/')?;
+            Some((pages, path))
+        })
+        .max_by_key(|(pages, _)| pages.prefix.len())
+    {
+        return Some(normalize(path).map(|path| Target::Page(pages, path)));
+    }
+
+    for prefix in ["/static/", "/s/"] {
+        if let Some(path) = url.strip_prefix(prefix) {
+            return Some(normalize(path).map(Target::Static));
+        }
+    }
+
+    // Some other service
+    if url.starts_with('/') {
+        return None;
+    }
+
+    // Relative links are relative to the page's directory
+    let own = pages.iter().find(|pages| pages.prefix == prefix)?;
+    let dir = page
+        .rsplit_once('/')
+        .map(|(dir, _)| dir)
+        .unwrap_or_default();
+    Some(normalize(&format!("{}/{}", dir, url)).map(|path| Target::Page(own, path)))
+}
+
+/// Normalizes `.` and `..` components, failing if the path escapes the root
+fn normalize(path: &str) -> Result<String, ()> {
+    let mut components = vec![];
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop().ok_or(())?;
+            }
+            component => components.push(component),
+        }
+    }
+
+    Ok(components.join("/"))
+}
@@ -6,13 +6,38 @@ use std::{
     path::Path,
 };
 
+use chrono::{DateTime, Utc};
 use include_dir::{Dir, DirEntry, File};
 
 use super::MarkdownFrontMatter;
+use crate::{Publication, Viewer};
+
+/// Where a tree of pages is served from
+struct Mount<'a> {
+    /// The directory the tree is rooted at
+    root: &'a Path,
+    /// The prefix pages are served under, `/m`
+    prefix: &'a str,
+    /// Subdirectories mounted as their own trees
+    excluded: &'a [String],
+}
+
+impl Mount<'_> {
+    /// The URI of a file or directory of the tree
+    fn uri(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(self.root).unwrap_or(path);
+        format!("{}/{}", self.prefix, relative.to_str().unwrap())
+    }
+
+    /// Returns true if a subdirectory is part of this tree
+    fn contains(&self, dir: &Dir) -> bool {
+        !self.excluded.iter().any(|e| Path::new(e) == dir.path())
+    }
+}
 
-/// Get nav info returns the title, order, and path of a file
-fn get_nav_info(page: &File) -> Option<(String, i32, String)> {
-    let path = format!("/m/{}", page.path().to_str().unwrap());
+/// Get nav info returns the title, order, path and publication state of a file
+fn get_nav_info(page: &File, mount: &Mount) -> Option<(String, i32, String, Publication)> {
+    let path = mount.uri(page.path());
 
     let content = std::str::from_utf8(page.contents()).unwrap();
     let markdown = Markdown::new(content);
@@ -42,7 +67,12 @@ fn get_nav_info(page: &File) -> Option<(String, i32, String)> {
                 .to_title_case()
         });
 
-    Some((title, order, path))
+    let publication = front_matter
+        .as_ref()
+        .map(|f| f.publication)
+        .unwrap_or_default();
+
+    Some((title, order, path, publication))
 }
 
 #[derive(Debug, Clone)]
@@ -50,30 +80,37 @@ struct NavItem {
     title: String,
     uri: String,
     order: i32,
+    publication: Publication,
 }
 
 impl NavItem {
-    fn new(title: String, uri: String, order: i32) -> Self {
-        NavItem { title, uri, order }
+    fn new(title: String, uri: String, order: i32, publication: Publication) -> Self {
+        NavItem {
+            title,
+            uri,
+            order,
+            publication,
+        }
     }
 
-    fn from_file(file: &File) -> Option<Self> {
-        get_nav_info(file).map(|(title, order, uri)| NavItem::new(title, uri, order))
+    fn from_file(file: &File, mount: &Mount) -> Option<Self> {
+        get_nav_info(file, mount)
+            .map(|(title, order, uri, publication)| NavItem::new(title, uri, order, publication))
     }
 
-    fn from_dir(dir: &Dir) -> Option<Self> {
+    fn from_dir(dir: &Dir, mount: &Mount) -> Option<Self> {
         // Find the file in this directory that ends with "home.md"
         let home = dir
             .files()
             .find(|f| f.path().file_name().unwrap().to_str().unwrap() == "home.md");
 
-        Self::from_file(home?)
+        Self::from_file(home?, mount)
     }
 
-    fn from_dir_entry(entry: &DirEntry) -> Option<Self> {
+    fn from_dir_entry(entry: &DirEntry, mount: &Mount) -> Option<Self> {
         match entry {
-            DirEntry::File(f) => Self::from_file(f),
-            DirEntry::Dir(d) => Self::from_dir(d),
+            DirEntry::File(f) => Self::from_file(f, mount),
+            DirEntry::Dir(d) => Self::from_dir(d, mount),
         }
     }
 }
@@ -108,20 +145,24 @@ struct NavBar {
 }
 
 impl NavBar {
-    fn new(breadcrumbs: &[NavItem], dir: &Dir) -> Option<Self> {
+    fn new(breadcrumbs: &[NavItem], dir: &Dir, mount: &Mount) -> Option<Self> {
         let mut children = dir
             .entries()
             .iter()
-            .filter_map(|entry| NavItem::from_dir_entry(entry))
+            .filter(|entry| match entry {
+                DirEntry::Dir(dir) => mount.contains(dir),
+                DirEntry::File(_) => true,
+            })
+            .filter_map(|entry| NavItem::from_dir_entry(entry, mount))
             .collect::<Vec<_>>();
 
         // Remove this directories home page from the children list, moving it to the breadcrumbs
         let home = dir
             .files()
-            .map(|f| f.path().to_str().unwrap())
-            .find(|p| p.ends_with("home.md"))?;
+            .map(|f| f.path())
+            .find(|p| p.to_str().unwrap().ends_with("home.md"))?;
 
-        let home = format!("/m/{}", home);
+        let home = mount.uri(home);
         let home = children.iter().position(|c| c.uri == home)?;
         let home = children.swap_remove(home);
 
@@ -143,29 +184,43 @@ impl NavBar {
 pub struct SiteNav {
     /// Maps directory uri to the navigation bar for that directory
     tree: HashMap<String, NavBar>,
+    /// The prefix pages are served under
+    prefix: String,
 }
 
 impl SiteNav {
     /// Creates the site's navigation tree from an [`include_dir::Dir`]
     pub fn new(dir: &Dir) -> Self {
+        Self::mount(dir, "/m", &[])
+    }
+
+    /// Creates the navigation tree of a directory served under `prefix`, leaving out the
+    /// `excluded` subdirectories
+    pub(crate) fn mount(root: &Dir, prefix: &str, excluded: &[String]) -> Self {
+        let mount = Mount {
+            root: root.path(),
+            prefix,
+            excluded,
+        };
         let mut tree = HashMap::new();
 
         let mut queue = VecDeque::new();
-        queue.push_back((vec![], dir));
+        queue.push_back((vec![], root));
 
         while let Some((breadcrumbs, dir)) = queue.pop_front() {
             // Build the navigation bar for this directory
-            let nav = NavBar::new(&breadcrumbs, dir).expect("Failed to create navigation bar");
+            let nav =
+                NavBar::new(&breadcrumbs, dir, &mount).expect("Failed to create navigation bar");
 
             // Add this directory to the breadcrumbs for its children
             let breadcrumbs = nav.breadcrumbs.clone();
 
             // Add the children to the queue
-            for dir in dir.dirs() {
+            for dir in dir.dirs().filter(|dir| mount.contains(dir)) {
                 queue.push_back((breadcrumbs.clone(), dir));
             }
 
-            let mut path = format!("/m/{}", dir.path().to_str().unwrap());
+            let mut path = mount.uri(dir.path());
             if !path.ends_with('/') {
                 path.push('/');
             }
@@ -173,11 +228,20 @@ impl SiteNav {
             tree.insert(path, nav);
         }
 
-        Self { tree }
+        Self {
+            tree,
+            prefix: prefix.to_string(),
+        }
     }
 
-    /// Renders the navigation bar from the perspective of the current page
-    pub fn try_render(&self, current: &str) -> Option<Markup> {
+    /// The URI of a page, its path under the prefix
+    pub fn uri(&self, path: &str) -> String {
+        format!("{}/{}", self.prefix, path)
+    }
+
+    /// Renders the navigation bar from the perspective of the current page, leaving out pages the
+    /// viewer can't see yet
+    pub fn try_render(&self, current: &str, viewer: Viewer, now: DateTime<Utc>) -> Option<Markup> {
         // Find the directory of the current page, unlike the old version we can't assume the
         // length of the path
         let path: &Path = current.as_ref();
@@ -190,16 +254,18 @@ impl SiteNav {
         // Get the navigation bar for this directory
         let nav = self.tree.get(&dir?)?;
 
+        let visible = |item: &&NavItem| item.publication.visible(viewer, now);
+
         // Render the navigation bar
         Some(html! {
             nav {
-                @for item in &nav.breadcrumbs {
+                @for item in nav.breadcrumbs.iter().filter(visible) {
                     a href=(item.uri) style=(if *item.uri == *current { "text-decoration: underline" } else { "" }) { (item.title) }
                 }
 
                 div style="flex-grow: 1;" {};
 
-                @for item in &nav.children {
+                @for item in nav.children.iter().filter(visible) {
                     a href=(item.uri) style=(if *item.uri == *current { "text-decoration: underline" } else { "" }) { (item.title) }
                 }
             }
@@ -208,9 +274,8 @@ impl SiteNav {
     }
 
     /// Renders the navigation bar from the perspective of the current page, or fallback to rendering the home page's version
-    pub fn render(&self, current: &str) -> Markup {
-        let p = format!("/m/{}", current);
-        self.try_render(&p).unwrap()
+    pub fn render(&self, current: &str, viewer: Viewer, now: DateTime<Utc>) -> Markup {
+        self.try_render(&self.uri(current), viewer, now).unwrap()
     }
 }
 
@@ -1,8 +1,14 @@
+use chrono::{DateTime, NaiveDate, Utc};
 use inflector::Inflector;
-use markdown::Markdown;
-use maud::{html, Markup, Render, DOCTYPE};
+use markdown::{Heading, Link, Markdown, RenderOptions};
+use maud::{html, Markup, Render};
 
-use crate::{MarkdownFrontMatter, SiteNav};
+use crate::{
+    layouts::{self, LayoutContext},
+    publish,
+    series::Series,
+    Head, MarkdownFrontMatter, Publication, SiteNav, Viewer,
+};
 
 /// A portfolio/blog post page.
 pub struct Page<'a> {
@@ -16,14 +22,18 @@ pub struct Page<'a> {
 
 impl Render for Page<'_> {
     fn render(&self) -> maud::Markup {
-        html! {
-            (DOCTYPE)
-            (crate::header(self.title))
-            (self.nav.render(self.uri))
-            main {
-                (&self.content)
-            }
-        }
+        let body = self.content.render();
+
+        layouts::default(&LayoutContext {
+            head: Head::new(self.title),
+            nav: self.nav.render(self.uri, Viewer::Public, Utc::now()),
+            banner: None,
+            meta: html! {},
+            series_header: None,
+            series_footer: None,
+            outline: &[],
+            body: &body,
+        })
     }
 }
 
@@ -35,6 +45,47 @@ pub struct PageMetadata {
     pub title: String,
     pub hidden: bool,
     pub order: i32,
+    pub updated: Option<NaiveDate>,
+    /// Words of prose, excluding code blocks, counted when the page is rendered
+    pub words: usize,
+    /// Estimated reading time in minutes, at least 1 once the page is rendered
+    pub minutes: u64,
+    pub series: Option<String>,
+    pub part: Option<u32>,
+    #[serde(flatten)]
+    pub publication: Publication,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub canonical: Option<String>,
+    /// The layout to render with, resolved against directory defaults by the blog
+    pub layout: Option<String>,
+    pub directory_layout: Option<String>,
+    /// The page's headings, for layouts with an outline, collected when the page is rendered
+    #[serde(skip)]
+    pub outline: Vec<Heading>,
+}
+
+/// Renders the markdown of a page, filling in what's counted from its content. Links into the
+/// content go to the `collections` holding them, see [`RenderOptions::collections`].
+pub fn render_markdown(
+    metadata: &mut PageMetadata,
+    md: &Markdown,
+    collections: &[(String, String)],
+) -> (Markup, Vec<Link>) {
+    let options = RenderOptions {
+        heading_ids: layouts::has_outline(metadata.layout.as_deref().unwrap_or(layouts::DEFAULT)),
+        collections: collections.to_vec(),
+        ..RenderOptions::trusted()
+    };
+    let document = md.document(&options);
+
+    metadata.words = document.words;
+    metadata.minutes = (document.reading_time().as_secs_f32() / 60.0)
+        .ceil()
+        .max(1.0) as u64;
+    metadata.outline = document.headings;
+
+    (document.html, document.links)
 }
 
 pub fn parse_page<'a>(path: &str, content: &'a str) -> (PageMetadata, Markdown<'a>) {
@@ -49,7 +100,7 @@ pub fn parse_page<'a>(path: &str, content: &'a str) -> (PageMetadata, Markdown<'
         .and_then(|f| f.order)
         .unwrap_or(i32::MIN);
 
-    let file_name = path.split('/').last().unwrap();
+    let file_name = path.rsplit('/').next().unwrap();
 
     let title = front_matter
         .as_ref()
@@ -61,24 +112,114 @@ pub fn parse_page<'a>(path: &str, content: &'a str) -> (PageMetadata, Markdown<'
         title,
         hidden: front_matter.as_ref().map(|f| f.hidden).unwrap_or(false),
         order,
+        updated: front_matter.as_ref().and_then(|f| f.updated),
+        words: 0,
+        minutes: 0,
+        series: front_matter.as_ref().and_then(|f| f.series.to_owned()),
+        part: front_matter.as_ref().and_then(|f| f.part),
+        publication: front_matter
+            .as_ref()
+            .map(|f| f.publication)
+            .unwrap_or_default(),
+        description: front_matter.as_ref().and_then(|f| f.description.to_owned()),
+        image: front_matter.as_ref().and_then(|f| f.image.to_owned()),
+        canonical: front_matter.as_ref().and_then(|f| f.canonical.to_owned()),
+        layout: front_matter.as_ref().and_then(|f| f.layout.to_owned()),
+        directory_layout: front_matter
+            .as_ref()
+            .and_then(|f| f.directory_layout.to_owned()),
+        outline: vec![],
     };
 
     (metadata, md)
 }
 
-/// Renders a page using the given metadata and markdown content
-pub fn render_page<'a>(
-    nav: &SiteNav,
-    path: &'a str,
-    metadata: &PageMetadata,
-    md: &Markdown<'a>,
-) -> Markup {
+/// A page of a blog along with everything it's rendered with
+pub struct RenderedPage<'a> {
+    pub nav: &'a SiteNav,
+    /// Where the site is hosted, empty if that isn't known
+    pub site_url: &'a str,
+    pub path: &'a str,
+    pub metadata: &'a PageMetadata,
+    pub series: Option<&'a Series>,
+    /// The rendered markdown
+    pub body: &'a Markup,
+}
+
+/// Makes a URL relative to the site absolute, mapping static files to where they're served.
+///
+/// Absolute URLs can only be built once we know where the site is hosted.
+pub(crate) fn absolute_url(site_url: &str, url: &str) -> Option<String> {
+    if url.contains("://") {
+        return Some(url.to_string());
+    }
+    if site_url.is_empty() {
+        return None;
+    }
+
+    let path = url.trim_start_matches('/');
+    let path = match path.strip_prefix("static/") {
+        Some(file) => format!("s/{}", file),
+        None => path.to_string(),
+    };
+    Some(format!("{}/{}", site_url.trim_end_matches('/'), path))
+}
+
+/// Renders a page with its layout, as seen by `viewer` at `now`.
+///
+/// The navigation bar and series links depend on which pages are visible, so they're composed
+/// for each request rather than when the blog is built.
+pub fn render_page(page: &RenderedPage, viewer: Viewer, now: DateTime<Utc>) -> Markup {
+    let RenderedPage {
+        nav,
+        site_url,
+        path,
+        metadata,
+        series,
+        body,
+    } = *page;
+
+    let absolute = |url: &str| absolute_url(site_url, url);
+    let canonical = match &metadata.canonical {
+        Some(canonical) => absolute(canonical),
+        None => absolute(&nav.uri(path)),
+    };
+    let image = metadata.image.as_deref().and_then(absolute);
+
+    let head = Head {
+        title: &metadata.title,
+        description: metadata.description.as_deref(),
+        image: image.as_deref(),
+        canonical: canonical.as_deref(),
+    };
+
+    let context = LayoutContext {
+        head,
+        nav: nav.render(path, viewer, now),
+        banner: publish::banner(metadata.publication.status(now)),
+        meta: page_meta(metadata),
+        series_header: series.and_then(|s| s.header(path, viewer, now)),
+        series_footer: series.and_then(|s| s.footer(path, viewer, now)),
+        outline: &metadata.outline,
+        body,
+    };
+
+    layouts::render(
+        metadata.layout.as_deref().unwrap_or(layouts::DEFAULT),
+        &context,
+    )
+}
+
+/// The last updated date, reading time and word count shown above a page
+fn page_meta(metadata: &PageMetadata) -> Markup {
     html! {
-        (DOCTYPE)
-        (crate::header(&metadata.title))
-        main {
-            (nav.render(path))
-            (md)
+        p.page-meta {
+            @if let Some(updated) = metadata.updated {
+                "Updated "
+                time datetime=(updated) { (updated.format("%B %-d, %Y")) }
+                " \u{00B7} "
+            }
+            (metadata.minutes) " min read \u{00B7} " (metadata.words) " words"
         }
     }
 }
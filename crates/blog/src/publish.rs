@@ -0,0 +1,126 @@
+//! Drafts and scheduled publishing.
+//!
+//! Pages marked `draft: true` are only shown to the admin, as are pages with a `publish_at` date
+//! in the future. Scheduled pages appear to everyone once the date passes, visibility is decided
+//! when a page is requested so this doesn't require a rebuild.
+//!
+//! ```yaml
+//! draft: true
+//! publish_at: 2024-09-01T12:00:00Z
+//! ```
+//!
+//! `publish_at` also accepts a plain date, `2024-09-01`, meaning midnight UTC.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use maud::{html, Markup};
+use serde::{Deserialize, Deserializer};
+
+/// Who is requesting a page
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Viewer {
+    /// Anyone, published pages only
+    #[default]
+    Public,
+    /// The authenticated admin, drafts and scheduled pages are shown with a banner
+    Admin,
+}
+
+/// The publication state of a page at some point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Published,
+    Draft,
+    Scheduled(DateTime<Utc>),
+}
+
+/// The publishing front matter of a page
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct Publication {
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default, deserialize_with = "deserialize_publish_at")]
+    pub publish_at: Option<DateTime<Utc>>,
+}
+
+impl Publication {
+    /// The status of the page at `now`, drafts stay drafts even once their date passes
+    pub fn status(&self, now: DateTime<Utc>) -> Status {
+        match self.publish_at {
+            _ if self.draft => Status::Draft,
+            Some(at) if at > now => Status::Scheduled(at),
+            _ => Status::Published,
+        }
+    }
+
+    /// Returns true if the page can be seen by `viewer` at `now`
+    pub fn visible(&self, viewer: Viewer, now: DateTime<Utc>) -> bool {
+        viewer == Viewer::Admin || self.status(now) == Status::Published
+    }
+}
+
+/// Renders the banner shown above unpublished pages
+pub(crate) fn banner(status: Status) -> Option<Markup> {
+    let message = match status {
+        Status::Published => return None,
+        Status::Draft => "Draft, only visible to you".to_string(),
+        Status::Scheduled(at) => {
+            format!(
+                "Scheduled, publishes {}",
+                at.format("%B %-d, %Y at %H:%M UTC")
+            )
+        }
+    };
+
+    Some(html! {
+        p.draft-banner { (message) }
+    })
+}
+
+/// Accepts an RFC 3339 timestamp or a plain date
+fn deserialize_publish_at<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(value) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    if let Ok(at) = DateTime::parse_from_rfc3339(&value) {
+        return Ok(Some(at.with_timezone(&Utc)));
+    }
+
+    value
+        .parse::<NaiveDate>()
+        .map(|date| Some(date.and_hms_opt(0, 0, 0).unwrap().and_utc()))
+        .map_err(|_| {
+            serde::de::Error::custom(format!("invalid publish_at {:?}, expected a date", value))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status() {
+        let publication: Publication = serde_yaml::from_str("publish_at: 2024-09-01").unwrap();
+        let before = "2024-08-31T23:59:59Z".parse().unwrap();
+        let after = "2024-09-01T00:00:00Z".parse().unwrap();
+
+        assert!(matches!(publication.status(before), Status::Scheduled(_)));
+        assert!(!publication.visible(Viewer::Public, before));
+        assert!(publication.visible(Viewer::Admin, before));
+        assert_eq!(publication.status(after), Status::Published);
+        assert!(publication.visible(Viewer::Public, after));
+
+        let draft: Publication =
+            serde_yaml::from_str("draft: true\npublish_at: 2024-09-01T12:00:00+02:00").unwrap();
+        assert_eq!(draft.status(after), Status::Draft);
+        assert_eq!(
+            draft.publish_at.unwrap(),
+            "2024-09-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        assert!(serde_yaml::from_str::<Publication>("publish_at: soon").is_err());
+    }
+}
@@ -0,0 +1,226 @@
+//! Multi-part series of pages.
+//!
+//! Pages join a series with the `series` front matter key and are ordered by `part`:
+//!
+//! ```yaml
+//! series: FRC Programming
+//! part: 2
+//! ```
+//!
+//! Each part is rendered with a "Part 2 of 5" header, an index of the whole series, and links to
+//! the previous and next parts. Parts the viewer can't see yet are skipped.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use maud::{html, Markup};
+
+use crate::{page::PageMetadata, Publication, Viewer};
+
+/// A page in a series
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Part {
+    pub path: String,
+    /// Where the part is served, the path under the collection's prefix
+    pub uri: String,
+    pub title: String,
+    pub publication: Publication,
+}
+
+/// A named, ordered list of pages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Series {
+    pub name: String,
+    pub parts: Vec<Part>,
+}
+
+impl Series {
+    /// Groups pages into their series, pages without a `part` come last. Parts are linked to under
+    /// `prefix`.
+    pub fn collect<'a>(
+        pages: impl IntoIterator<Item = &'a PageMetadata>,
+        prefix: &str,
+    ) -> HashMap<String, Self> {
+        let mut grouped: HashMap<&str, Vec<&PageMetadata>> = HashMap::new();
+        for page in pages {
+            if let Some(series) = &page.series {
+                grouped.entry(series).or_default().push(page);
+            }
+        }
+
+        grouped
+            .into_iter()
+            .map(|(name, mut pages)| {
+                pages.sort_by(|a, b| {
+                    (a.part.is_none(), a.part, &a.title).cmp(&(b.part.is_none(), b.part, &b.title))
+                });
+
+                let parts = pages
+                    .into_iter()
+                    .map(|page| Part {
+                        path: page.path.clone(),
+                        uri: format!("{}/{}", prefix, page.path),
+                        title: page.title.clone(),
+                        publication: page.publication,
+                    })
+                    .collect();
+
+                (
+                    name.to_string(),
+                    Series {
+                        name: name.to_string(),
+                        parts,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// The parts visible to `viewer`, along with the position of `path` among them
+    fn visible(
+        &self,
+        path: &str,
+        viewer: Viewer,
+        now: DateTime<Utc>,
+    ) -> (Vec<&Part>, Option<usize>) {
+        let parts = self
+            .parts
+            .iter()
+            .filter(|part| part.publication.visible(viewer, now))
+            .collect::<Vec<_>>();
+        let index = parts.iter().position(|part| part.path == path);
+
+        (parts, index)
+    }
+
+    /// Renders the "Part N of M" header and the series index, shown above the page
+    pub fn header(&self, path: &str, viewer: Viewer, now: DateTime<Utc>) -> Option<Markup> {
+        let (parts, index) = self.visible(path, viewer, now);
+        let index = index?;
+
+        Some(html! {
+            details.series {
+                summary {
+                    "Part " (index + 1) " of " (parts.len()) " in "
+                    strong { (self.name) }
+                }
+                ol {
+                    @for (i, part) in parts.iter().enumerate() {
+                        li {
+                            @if i == index {
+                                strong { (part.title) }
+                            } @else {
+                                a href=(part.uri) { (part.title) }
+                            }
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// Renders the previous and next links, shown below the page
+    pub fn footer(&self, path: &str, viewer: Viewer, now: DateTime<Utc>) -> Option<Markup> {
+        let (parts, index) = self.visible(path, viewer, now);
+        let index = index?;
+
+        let previous = index.checked_sub(1).and_then(|i| parts.get(i));
+        let next = parts.get(index + 1);
+
+        Some(html! {
+            nav.series-nav {
+                @if let Some(previous) = previous {
+                    a.series-previous href=(previous.uri) {
+                        "\u{2190} " (previous.title)
+                    }
+                }
+                @if let Some(next) = next {
+                    a.series-next href=(next.uri) {
+                        (next.title) " \u{2192}"
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(path: &str, series: Option<&str>, part: Option<u32>) -> PageMetadata {
+        PageMetadata {
+            path: path.to_string(),
+            title: path.trim_end_matches(".md").to_string(),
+            series: series.map(str::to_string),
+            part,
+            ..PageMetadata::default()
+        }
+    }
+
+    #[test]
+    fn collect() {
+        let pages = [
+            page("frc/controls.md", Some("FRC"), Some(3)),
+            page("frc/appendix.md", Some("FRC"), None),
+            page("frc/java.md", Some("FRC"), Some(1)),
+            page("frc/abstraction.md", Some("FRC"), Some(2)),
+            page("home.md", None, None),
+        ];
+
+        let series = Series::collect(&pages, "/m");
+        assert_eq!(series.len(), 1);
+
+        let frc = &series["FRC"];
+        let paths = frc
+            .parts
+            .iter()
+            .map(|p| p.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "frc/java.md",
+                "frc/abstraction.md",
+                "frc/controls.md",
+                "frc/appendix.md"
+            ]
+        );
+
+        let now = Utc::now();
+        let header = frc.header("frc/abstraction.md", Viewer::Public, now);
+        assert!(header.unwrap().into_string().contains("Part 2 of 4"));
+
+        let footer = frc.footer("frc/abstraction.md", Viewer::Public, now);
+        let footer = footer.unwrap().into_string();
+        assert!(footer.contains(r#"href="/m/frc/java.md">← frc/java"#));
+        assert!(footer.contains(r#"href="/m/frc/controls.md">frc/controls →"#));
+
+        let footer = frc.footer("frc/java.md", Viewer::Public, now);
+        assert!(!footer.unwrap().into_string().contains("series-previous"));
+    }
+
+    #[test]
+    fn unpublished_parts() {
+        let mut pages = [
+            page("frc/java.md", Some("FRC"), Some(1)),
+            page("frc/abstraction.md", Some("FRC"), Some(2)),
+            page("frc/controls.md", Some("FRC"), Some(3)),
+        ];
+        pages[1].publication.draft = true;
+
+        let series = Series::collect(&pages, "/m");
+        let frc = &series["FRC"];
+        let now = Utc::now();
+
+        // The public skip straight over the draft
+        let footer = frc.footer("frc/java.md", Viewer::Public, now).unwrap();
+        assert!(footer.into_string().contains("frc/controls →"));
+        assert!(frc
+            .header("frc/abstraction.md", Viewer::Public, now)
+            .is_none());
+
+        let header = frc.header("frc/controls.md", Viewer::Admin, now).unwrap();
+        assert!(header.into_string().contains("Part 3 of 3"));
+    }
+}
@@ -0,0 +1,507 @@
+//! Server side rendering of diagram code blocks into inline SVG.
+//!
+//! Supported code block languages:
+//!
+//! - `dot`: Graphviz DOT, laid out by `layout-rs`
+//! - `flowchart`: a tiny mermaid-like flowchart syntax that is translated to DOT
+//! - `sequence`: a tiny sequence diagram syntax that is drawn directly
+//!
+//! Rendered diagrams are cached by a hash of their language and source, so a diagram shared by
+//! several pages (or rendered again after a reload) is only laid out once. The cache holds the
+//! [`CACHE_SIZE`] most recently added diagrams, so edited diagrams don't pile up across reloads.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    hash::Hasher,
+    panic::{self, AssertUnwindSafe},
+    sync::RwLock,
+};
+
+use layout::{
+    backends::svg::SVGWriter,
+    gv::{DotParser, GraphBuilder},
+};
+use once_cell::sync::Lazy;
+use twox_hash::XxHash64;
+
+/// How many rendered diagrams are kept
+const CACHE_SIZE: usize = 256;
+
+/// Rendered diagrams by hash, and the order they were added in to evict the oldest
+#[derive(Default)]
+struct Cache {
+    svgs: HashMap<u64, String>,
+    order: VecDeque<u64>,
+}
+
+impl Cache {
+    /// Removes the oldest diagram if the cache is full
+    fn insert(&mut self, hash: u64, svg: String) {
+        if self.svgs.insert(hash, svg).is_some() {
+            return;
+        }
+        if self.order.len() >= CACHE_SIZE {
+            if let Some(oldest) = self.order.pop_front() {
+                self.svgs.remove(&oldest);
+            }
+        }
+        self.order.push_back(hash);
+    }
+}
+
+static CACHE: Lazy<RwLock<Cache>> = Lazy::new(Default::default);
+
+/// Returns true if code blocks tagged with `lang` are rendered as diagrams
+pub fn is_diagram(lang: &str) -> bool {
+    matches!(lang, "dot" | "flowchart" | "sequence")
+}
+
+/// Renders a diagram code block into an inline `<svg>` element.
+///
+/// Returns an error describing the problem if the source could not be parsed, the caller should
+/// then fall back to rendering the block as code.
+pub fn render(lang: &str, source: &str) -> Result<String, String> {
+    let mut hasher = XxHash64::default();
+    hasher.write(lang.as_bytes());
+    hasher.write(source.as_bytes());
+    let hash = hasher.finish();
+
+    if let Some(svg) = CACHE.read().unwrap().svgs.get(&hash) {
+        return Ok(svg.clone());
+    }
+
+    let svg = match lang {
+        "dot" => render_dot(source, hash)?,
+        "flowchart" => render_dot(&flowchart_to_dot(source)?, hash)?,
+        "sequence" => render_sequence(source, hash)?,
+        _ => return Err(format!("{:?} is not a diagram language", lang)),
+    };
+
+    CACHE.write().unwrap().insert(hash, svg.clone());
+    Ok(svg)
+}
+
+/// Lays out a DOT graph and returns it as an inline SVG element
+fn render_dot(source: &str, hash: u64) -> Result<String, String> {
+    let graph = DotParser::new(source).process()?;
+
+    // `layout-rs` asserts on some malformed graphs, a bad diagram should not take the site down
+    let svg = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut builder = GraphBuilder::new();
+        builder.visit_graph(&graph);
+
+        let mut svg = SVGWriter::new();
+        builder.get().do_it(false, false, false, &mut svg);
+        svg.finalize()
+    }))
+    .map_err(|_| "Failed to lay out graph".to_string())?;
+
+    // Strip the XML prolog, it isn't valid inside of an HTML document
+    let start = svg.find("<svg").ok_or("Layout produced no SVG")?;
+    let mut svg = svg[start..].replacen("<svg", r#"<svg class="graph" font-family="serif""#, 1);
+
+    // The `<style>` element would be escaped by comrak's tag filter, font size classes become
+    // attributes instead
+    if let (Some(start), Some(end)) = (svg.find("<style>"), svg.find("</style>")) {
+        svg.replace_range(start..end + "</style>".len(), "");
+    }
+
+    let mut out = String::with_capacity(svg.len());
+    let mut rest = svg.as_str();
+    while let Some(start) = rest.find(r#"class="a"#) {
+        let (before, after) = rest.split_at(start);
+        let after = &after[r#"class="a"#.len()..];
+        let end = after.find('"').ok_or("Unterminated class attribute")?;
+
+        let _ = write!(out, r#"{}font-size="{}""#, before, &after[..end]);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+
+    // Edge ids are only unique within a single graph, prefix them so pages can hold several
+    let prefix = format!("graph-{:x}-arrow", hash);
+    let out = out
+        .replace(r#"id="arrow"#, &format!(r#"id="{}"#, prefix))
+        .replace(r##"href="#arrow"##, &format!(r##"href="#{}"##, prefix));
+
+    Ok(out)
+}
+
+/// The node shapes supported by the flowchart syntax
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shape {
+    Box,
+    Circle,
+    DoubleCircle,
+}
+
+impl Shape {
+    fn dot(&self) -> &'static str {
+        match self {
+            Shape::Box => "box",
+            Shape::Circle => "circle",
+            Shape::DoubleCircle => "doublecircle",
+        }
+    }
+}
+
+/// Translates the flowchart syntax into DOT.
+///
+/// ```text
+/// flowchart LR
+/// A[Read sensors] --> B(Compute) -->|output| C((Motors))
+/// B -.-> D[Log]
+/// C --- A
+/// ```
+///
+/// - The optional header picks the direction, `TD`/`TB` (default) or `LR`
+/// - `id[label]` is a box, `id(label)` a circle and `id((label))` a double circle
+/// - `-->` is an arrow, `---` a line and `-.->` a dashed arrow
+/// - Edges are labelled with `-->|label|` or `-- label -->`
+fn flowchart_to_dot(source: &str) -> Result<String, String> {
+    let mut dot = String::from("digraph {\nnode [shape=box];\n");
+
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("%%") {
+            continue;
+        }
+
+        if let Some(direction) = line
+            .strip_prefix("flowchart")
+            .or_else(|| line.strip_prefix("graph"))
+        {
+            if direction.trim() == "LR" {
+                dot.push_str("rankdir=LR;\n");
+            }
+            continue;
+        }
+
+        let error = |e: &str| format!("line {}: {}", number + 1, e);
+
+        let (mut from, mut rest) = parse_node(line).ok_or_else(|| error("expected a node"))?;
+        write_node(&mut dot, &from);
+
+        while !rest.is_empty() {
+            let (edge, after_edge) = parse_edge(rest).ok_or_else(|| error("expected an edge"))?;
+            let (to, after_node) =
+                parse_node(after_edge).ok_or_else(|| error("expected a node"))?;
+            write_node(&mut dot, &to);
+
+            let operator = if edge.arrow { "->" } else { "--" };
+            let _ = write!(dot, "{} {} {} [", quote(from.id), operator, quote(to.id));
+            if let Some(label) = edge.label {
+                let _ = write!(dot, "label={} ", quote(label));
+            }
+            if edge.dashed {
+                dot.push_str("style=dashed ");
+            }
+            dot.push_str("];\n");
+
+            from = to;
+            rest = after_node;
+        }
+    }
+
+    dot.push('}');
+    Ok(dot)
+}
+
+struct Node<'a> {
+    id: &'a str,
+    label: Option<(&'a str, Shape)>,
+}
+
+struct Edge<'a> {
+    label: Option<&'a str>,
+    arrow: bool,
+    dashed: bool,
+}
+
+/// Parses `id`, `id[label]`, `id(label)` or `id((label))`, returning the node and the remaining input
+fn parse_node(input: &str) -> Option<(Node<'_>, &str)> {
+    let input = input.trim_start();
+    let end = input
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(input.len());
+    if end == 0 {
+        return None;
+    }
+
+    let (id, rest) = input.split_at(end);
+    let shapes = [
+        ("((", "))", Shape::DoubleCircle),
+        ("[", "]", Shape::Box),
+        ("(", ")", Shape::Circle),
+    ];
+
+    for (open, close, shape) in shapes {
+        if let Some(rest) = rest.strip_prefix(open) {
+            let (label, rest) = rest.split_once(close)?;
+            let node = Node {
+                id,
+                label: Some((label.trim(), shape)),
+            };
+            return Some((node, rest.trim_start()));
+        }
+    }
+
+    Some((Node { id, label: None }, rest.trim_start()))
+}
+
+/// Parses `-->`, `---`, `-.->`, `-->|label|` or `-- label -->`, returning the edge and the remaining input
+fn parse_edge(input: &str) -> Option<(Edge<'_>, &str)> {
+    let operators = [
+        ("-.->", true, true),
+        ("-->", true, false),
+        ("---", false, false),
+    ];
+
+    for (operator, arrow, dashed) in operators {
+        if let Some(rest) = input.strip_prefix(operator) {
+            let mut edge = Edge {
+                label: None,
+                arrow,
+                dashed,
+            };
+
+            let rest = match rest.strip_prefix('|') {
+                Some(rest) => {
+                    let (label, rest) = rest.split_once('|')?;
+                    edge.label = Some(label.trim());
+                    rest
+                }
+                None => rest,
+            };
+
+            return Some((edge, rest));
+        }
+    }
+
+    // `-- label -->`
+    let rest = input.strip_prefix("--")?;
+    let (label, rest) = rest.split_once("-->")?;
+    let edge = Edge {
+        label: Some(label.trim()),
+        arrow: true,
+        dashed: false,
+    };
+    Some((edge, rest))
+}
+
+/// Declares a node's label and shape, nodes without a label keep any earlier declaration
+fn write_node(dot: &mut String, node: &Node) {
+    if let Some((label, shape)) = node.label {
+        let _ = writeln!(
+            dot,
+            "{} [label={} shape={}];",
+            quote(node.id),
+            quote(label),
+            shape.dot()
+        );
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+const COLUMN_WIDTH: usize = 150;
+const ROW_HEIGHT: usize = 40;
+const BOX_HEIGHT: usize = 30;
+const MARGIN: usize = 10;
+
+struct Message<'a> {
+    from: usize,
+    to: usize,
+    text: &'a str,
+    dashed: bool,
+}
+
+/// Draws the sequence diagram syntax directly to SVG.
+///
+/// ```text
+/// participant Driver Station
+/// participant Robot
+/// Driver Station->Robot: enable
+/// Robot-->Driver Station: status
+/// ```
+///
+/// - `participant Name` declares a participant, otherwise they appear in order of first use
+/// - `A->B: text` is a solid message and `A-->B: text` a dashed reply
+fn render_sequence(source: &str, hash: u64) -> Result<String, String> {
+    let mut participants: Vec<&str> = vec![];
+    let mut messages = vec![];
+
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line == "sequence" {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix("participant ") {
+            participant(&mut participants, name);
+            continue;
+        }
+
+        let (arrow, text) = line.split_once(':').unwrap_or((line, ""));
+        let (from, to, dashed) = match arrow.split_once("-->") {
+            Some((from, to)) => (from, to, true),
+            None => match arrow.split_once("->") {
+                Some((from, to)) => (from, to, false),
+                None => return Err(format!("line {}: expected a message", number + 1)),
+            },
+        };
+
+        messages.push(Message {
+            from: participant(&mut participants, from),
+            to: participant(&mut participants, to),
+            text: text.trim(),
+            dashed,
+        });
+    }
+
+    if participants.is_empty() {
+        return Err("sequence diagram has no participants".to_string());
+    }
+
+    let width = participants.len() * COLUMN_WIDTH;
+    let lifeline_top = MARGIN + BOX_HEIGHT;
+    let lifeline_bottom = lifeline_top + (messages.len() + 1) * ROW_HEIGHT;
+    let height = lifeline_bottom + BOX_HEIGHT + MARGIN;
+    let center = |i: usize| i * COLUMN_WIDTH + COLUMN_WIDTH / 2;
+    let marker = format!("sequence-arrow-{:x}", hash);
+
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        r#"<svg width="{width}" height="{height}" viewBox="0 0 {width} {height}" xmlns="http://www.w3.org/2000/svg" font-family="sans-serif" font-size="14" stroke="currentColor" fill="currentColor">"#
+    );
+    let _ = write!(
+        svg,
+        r#"<defs><marker id="{marker}" markerWidth="10" markerHeight="7" refX="10" refY="3.5" orient="auto"><polygon points="0 0, 10 3.5, 0 7" /></marker></defs>"#
+    );
+
+    for (i, name) in participants.iter().enumerate() {
+        let x = center(i);
+        let _ = write!(
+            svg,
+            r#"<line x1="{x}" y1="{lifeline_top}" x2="{x}" y2="{lifeline_bottom}" stroke-dasharray="4" />"#
+        );
+        for y in [MARGIN, lifeline_bottom] {
+            let _ = write!(
+                svg,
+                r#"<rect x="{}" y="{y}" width="{}" height="{BOX_HEIGHT}" rx="4" fill="none" /><text x="{x}" y="{}" text-anchor="middle" stroke="none">{}</text>"#,
+                x - COLUMN_WIDTH / 2 + MARGIN,
+                COLUMN_WIDTH - 2 * MARGIN,
+                y + BOX_HEIGHT / 2 + 5,
+                escape(name)
+            );
+        }
+    }
+
+    for (row, message) in messages.iter().enumerate() {
+        let y = lifeline_top + (row + 1) * ROW_HEIGHT;
+        let (x1, x2) = (center(message.from), center(message.to));
+        let dash = if message.dashed {
+            r#" stroke-dasharray="6 3""#
+        } else {
+            ""
+        };
+
+        if message.from == message.to {
+            // Messages to self loop out to the right of the lifeline
+            let _ = write!(
+                svg,
+                r#"<path d="M {x1} {} h 30 v 15 h -30" fill="none" marker-end="url(#{marker})"{dash} />"#,
+                y - 10
+            );
+        } else {
+            let _ = write!(
+                svg,
+                r#"<line x1="{x1}" y1="{y}" x2="{x2}" y2="{y}" marker-end="url(#{marker})"{dash} />"#
+            );
+        }
+
+        let _ = write!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="middle" stroke="none">{}</text>"#,
+            (x1 + x2) / 2 + if x1 == x2 { 40 } else { 0 },
+            y - 6,
+            escape(message.text)
+        );
+    }
+
+    svg.push_str("</svg>");
+    Ok(svg)
+}
+
+/// Returns the index of a participant, adding them if this is their first appearance
+fn participant<'a>(participants: &mut Vec<&'a str>, name: &'a str) -> usize {
+    let name = name.trim();
+    participants
+        .iter()
+        .position(|p| *p == name)
+        .unwrap_or_else(|| {
+            participants.push(name);
+            participants.len() - 1
+        })
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flowchart() {
+        let dot =
+            flowchart_to_dot("flowchart LR\nA[Start] -->|go| B((End)) --- A\nB -.-> C").unwrap();
+
+        assert!(dot.contains("rankdir=LR;"));
+        assert!(dot.contains(r#""A" [label="Start" shape=box];"#));
+        assert!(dot.contains(r#""A" -> "B" [label="go" ];"#));
+        assert!(dot.contains(r#""B" -- "A" [];"#));
+        assert!(dot.contains(r#""B" -> "C" [style=dashed ];"#));
+        assert!(render("flowchart", "A --> B").unwrap().starts_with("<svg"));
+    }
+
+    #[test]
+    fn sequence() {
+        let svg = render(
+            "sequence",
+            "participant Robot\nDriver->Robot: enable\nRobot-->Driver: ok",
+        )
+        .unwrap();
+
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(">Robot</text>"));
+        assert!(svg.contains(">enable</text>"));
+        assert!(render("sequence", "Driver enables the robot").is_err());
+    }
+
+    #[test]
+    fn invalid_dot() {
+        assert!(render("dot", "not a graph").is_err());
+    }
+
+    #[test]
+    fn cache_evicts_oldest() {
+        let mut cache = Cache::default();
+        for hash in 0..CACHE_SIZE as u64 + 1 {
+            cache.insert(hash, String::new());
+        }
+        cache.insert(1, String::new());
+
+        assert_eq!(cache.svgs.len(), CACHE_SIZE);
+        assert!(!cache.svgs.contains_key(&0));
+        assert!(cache.svgs.contains_key(&CACHE_SIZE.try_into().unwrap()));
+    }
+}
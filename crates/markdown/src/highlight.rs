@@ -0,0 +1,266 @@
+//! Class based syntax highlighting (syntect).
+//!
+//! Code is highlighted into `<span>`s with CSS classes rather than inline styles, the colors come
+//! from a theme stylesheet generated by [`stylesheet`]. This keeps pages small and lets the site
+//! switch themes with `prefers-color-scheme`.
+//!
+//! On top of syntect's default syntaxes, the `.sublime-syntax` definitions in the `syntaxes`
+//! directory are embedded at build time (Kotlin, TOML, properties files and pseudo code).
+//!
+//! # Code block extras
+//!
+//! The info string of a fenced code block accepts a few extras after the language:
+//!
+//! ````markdown
+//! ```rust {3,5-7} title="src/main.rs" linenos
+//! ```
+//! ````
+//!
+//! - `{3,5-7}` highlights lines 3 and 5 through 7
+//! - `title="..."` (or `filename="..."`) adds a caption above the block
+//! - `linenos` numbers the lines
+
+use std::ops::RangeInclusive;
+
+use include_dir::{include_dir, Dir};
+use maud::html;
+use once_cell::sync::Lazy;
+use syntect::{
+    highlighting::ThemeSet,
+    html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator},
+    parsing::{SyntaxDefinition, SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+/// Every class is prefixed to avoid collisions with the site's own styles
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+const SYNTAXES: Dir = include_dir!("$CARGO_MANIFEST_DIR/syntaxes");
+
+pub(crate) static PS: Lazy<SyntaxSet> = Lazy::new(|| {
+    let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+
+    for file in SYNTAXES.files() {
+        let name = file.path().to_string_lossy();
+        let source = file.contents_utf8().expect("Syntax definitions are UTF-8");
+
+        match SyntaxDefinition::load_from_str(source, true, Some(&name)) {
+            Ok(syntax) => builder.add(syntax),
+            Err(e) => log::error!("Failed to load syntax {}: {}", name, e),
+        }
+    }
+
+    builder.build()
+});
+static TS: Lazy<ThemeSet> = Lazy::new(ThemeSet::load_defaults);
+
+/// Returns the names of the available themes
+pub fn themes() -> impl Iterator<Item = &'static str> {
+    TS.themes.keys().map(String::as_str)
+}
+
+/// Generates the stylesheet for a theme, returns `None` if the theme doesn't exist
+pub fn stylesheet(theme: &str) -> Option<String> {
+    let theme = TS.themes.get(theme)?;
+
+    match css_for_theme_with_class_style(theme, CLASS_STYLE) {
+        Ok(css) => Some(css),
+        Err(e) => {
+            log::error!("Error generating stylesheet for {:?}: {}", theme.name, e);
+            None
+        }
+    }
+}
+
+/// A parsed code block info string, see the [module documentation](self)
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct CodeInfo<'a> {
+    pub lang: &'a str,
+    pub highlighted: Vec<RangeInclusive<usize>>,
+    pub title: Option<&'a str>,
+    pub line_numbers: bool,
+}
+
+impl<'a> CodeInfo<'a> {
+    pub fn parse(info: &'a str) -> Self {
+        let info = info.trim();
+        let end = info.find([' ', '\t', '{']).unwrap_or(info.len());
+        let (lang, mut rest) = info.split_at(end);
+
+        let mut code_info = CodeInfo {
+            lang,
+            ..CodeInfo::default()
+        };
+
+        loop {
+            rest = rest.trim_start();
+
+            if let Some(ranges) = rest.strip_prefix('{') {
+                let (ranges, after) = ranges.split_once('}').unwrap_or((ranges, ""));
+                code_info
+                    .highlighted
+                    .extend(ranges.split(',').filter_map(parse_range));
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("linenos") {
+                code_info.line_numbers = true;
+                rest = after;
+            } else if let Some((key, after)) = rest.split_once('=') {
+                let (value, after) = match after.strip_prefix('"') {
+                    Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+                    None => after.split_once(' ').unwrap_or((after, "")),
+                };
+
+                if matches!(key.trim(), "title" | "filename") {
+                    code_info.title = Some(value);
+                }
+                rest = after;
+            } else {
+                break;
+            }
+        }
+
+        code_info
+    }
+
+    /// Returns true if any extras beyond the language were given
+    pub fn has_extras(&self) -> bool {
+        !self.highlighted.is_empty() || self.title.is_some() || self.line_numbers
+    }
+
+    fn is_highlighted(&self, line: usize) -> bool {
+        self.highlighted.iter().any(|range| range.contains(&line))
+    }
+}
+
+/// Parses `3` or `5-7`
+fn parse_range(range: &str) -> Option<RangeInclusive<usize>> {
+    let range = range.trim();
+    match range.split_once('-') {
+        Some((start, end)) => Some(start.trim().parse().ok()?..=end.trim().parse().ok()?),
+        None => range.parse().ok().map(|line| line..=line),
+    }
+}
+
+/// Highlights a code block, returning the `<pre>` element (in a `<figure>` when it has a title)
+pub(crate) fn highlight(
+    code: &str,
+    syntax: &SyntaxReference,
+    info: &CodeInfo,
+) -> Result<String, syntect::Error> {
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, &PS, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        generator.parse_html_for_line_which_includes_newline(line)?;
+    }
+    let html = generator.finalize();
+
+    // A trailing newline leaves a final "line" holding only closing tags, which is dropped
+    let lines = LinesWithEndings::from(code).count();
+
+    let mut code = String::new();
+    for (number, line) in split_lines(&html).iter().take(lines).enumerate() {
+        let class = if info.is_highlighted(number + 1) {
+            "line highlighted"
+        } else {
+            "line"
+        };
+        code.push_str(&format!("<span class=\"{}\">{}</span>\n", class, line));
+    }
+
+    let class = if info.line_numbers {
+        "code hl-code line-numbers"
+    } else {
+        "code hl-code"
+    };
+    let pre = format!("<pre class=\"{}\"><code>{}</code></pre>", class, code);
+
+    Ok(match info.title {
+        Some(title) => html! {
+            figure.code-block {
+                figcaption.code-title { (title) }
+                (maud::PreEscaped(pre))
+            }
+        }
+        .into_string(),
+        None => pre,
+    } + "\n")
+}
+
+/// Splits highlighted HTML into lines, closing the spans open at the end of each line and
+/// reopening them at the start of the next so every line is well formed on its own.
+fn split_lines(html: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut open: Vec<&str> = vec![];
+
+    for line in html.split('\n') {
+        let mut out = open.concat();
+        out.push_str(line);
+
+        // Syntect only emits `<span ...>` and `</span>`, text is escaped
+        let mut rest = line;
+        while let Some(start) = rest.find('<') {
+            let end = rest[start..]
+                .find('>')
+                .map_or(rest.len(), |end| start + end + 1);
+            let tag = &rest[start..end];
+            if tag.starts_with("</") {
+                open.pop();
+            } else {
+                open.push(tag);
+            }
+            rest = &rest[end..];
+        }
+
+        out.push_str(&"</span>".repeat(open.len()));
+        lines.push(out);
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_info() {
+        let info = CodeInfo::parse(r#"rust {3,5-7} title="src/main.rs" linenos"#);
+        assert_eq!(
+            info,
+            CodeInfo {
+                lang: "rust",
+                highlighted: vec![3..=3, 5..=7],
+                title: Some("src/main.rs"),
+                line_numbers: true,
+            }
+        );
+        assert!(info.is_highlighted(6) && !info.is_highlighted(4));
+
+        assert_eq!(CodeInfo::parse("kotlin").lang, "kotlin");
+        assert!(!CodeInfo::parse("kotlin").has_extras());
+        assert_eq!(CodeInfo::parse("toml{1}").highlighted, vec![1..=1]);
+        assert_eq!(
+            CodeInfo::parse("ini filename=robot.ini").title,
+            Some("robot.ini")
+        );
+    }
+
+    #[test]
+    fn custom_syntaxes() {
+        for token in ["kotlin", "kt", "toml", "properties", "ini", "pseudo"] {
+            assert!(PS.find_syntax_by_token(token).is_some(), "{}", token);
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let lines = split_lines("<span class=\"a\">x\ny</span>\n");
+        assert_eq!(
+            lines,
+            vec![
+                "<span class=\"a\">x</span>",
+                "<span class=\"a\">y</span>",
+                "",
+            ]
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! Responsive images for markdown content.
+//!
+//! An [`ImageIndex`] is built from the site's static files. When rendering, `<img>` tags pointing at
+//! an indexed image are rewritten into a `<picture>` offering resized variants, along with the
+//! original dimensions so the browser can reserve space before the image loads.
+//!
+//! Variants are addressed by a hash of the original image's content, `/s/img/{hash}-{width}.{ext}`,
+//! and are generated (then cached) the first time they're requested.
+
+use std::{
+    collections::HashMap,
+    hash::Hasher,
+    io::Cursor,
+    sync::{Arc, RwLock},
+};
+
+use image::{imageops::FilterType, ImageFormat, ImageReader};
+use include_dir::Dir;
+use once_cell::sync::OnceCell;
+use twox_hash::XxHash64;
+
+/// Widths of the generated variants, only widths smaller than the original are offered
+pub const WIDTHS: [u32; 3] = [480, 800, 1200];
+
+/// The `sizes` attribute, water.css limits the body to 800px
+const SIZES: &str = "(max-width: 800px) 100vw, 800px";
+
+static INDEX: OnceCell<ImageIndex> = OnceCell::new();
+
+/// Generated variants, keyed by hash and width
+type Variants = HashMap<(u64, u32), Arc<[u8]>>;
+
+/// Builds the global image index from a directory of static files served under `prefix`.
+///
+/// The index is only built once, later calls return the existing index.
+pub fn init(dir: &'static Dir<'static>, prefix: &str) -> &'static ImageIndex {
+    INDEX.get_or_init(|| ImageIndex::new(dir, prefix))
+}
+
+/// Returns the global image index, if it has been built
+pub fn index() -> Option<&'static ImageIndex> {
+    INDEX.get()
+}
+
+struct Image {
+    hash: u64,
+    width: u32,
+    height: u32,
+    format: ImageFormat,
+    extension: &'static str,
+    contents: &'static [u8],
+}
+
+/// An index of the PNG and JPEG images in a directory
+pub struct ImageIndex {
+    prefix: String,
+    /// Maps original urls to their image
+    by_url: HashMap<String, Image>,
+    /// Maps content hashes to their original url
+    by_hash: HashMap<u64, String>,
+    variants: RwLock<Variants>,
+}
+
+impl ImageIndex {
+    fn new(dir: &'static Dir<'static>, prefix: &str) -> Self {
+        let mut by_url = HashMap::new();
+        let mut by_hash = HashMap::new();
+
+        let mut stack = vec![dir];
+        while let Some(dir) = stack.pop() {
+            stack.extend(dir.dirs());
+
+            for file in dir.files() {
+                let Some(extension) = file.path().extension().and_then(|e| e.to_str()) else {
+                    continue;
+                };
+
+                let format = match ImageFormat::from_extension(extension) {
+                    Some(format @ (ImageFormat::Png | ImageFormat::Jpeg)) => format,
+                    _ => continue,
+                };
+
+                let reader = ImageReader::with_format(Cursor::new(file.contents()), format);
+                let (width, height) = match reader.into_dimensions() {
+                    Ok(dimensions) => dimensions,
+                    Err(e) => {
+                        log::warn!("Failed to read {:?}: {}", file.path(), e);
+                        continue;
+                    }
+                };
+
+                let mut hasher = XxHash64::default();
+                hasher.write(file.contents());
+                let hash = hasher.finish();
+
+                let url = format!("{}{}", prefix, file.path().to_string_lossy());
+                by_hash.insert(hash, url.clone());
+                by_url.insert(
+                    url,
+                    Image {
+                        hash,
+                        width,
+                        height,
+                        format,
+                        extension: if format == ImageFormat::Png {
+                            "png"
+                        } else {
+                            "jpg"
+                        },
+                        contents: file.contents(),
+                    },
+                );
+            }
+        }
+
+        ImageIndex {
+            prefix: prefix.to_string(),
+            by_url,
+            by_hash,
+            variants: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Builds the `srcset` for the image at `url`, listing the variants and the original
+    pub fn srcset(&self, url: &str) -> Option<String> {
+        let image = self.by_url.get(url)?;
+
+        let mut srcset = WIDTHS
+            .iter()
+            .filter(|width| **width < image.width)
+            .map(|width| {
+                format!(
+                    "{}img/{:016x}-{}.{} {}w",
+                    self.prefix, image.hash, width, image.extension, width
+                )
+            })
+            .collect::<Vec<_>>();
+        srcset.push(format!("{} {}w", url, image.width));
+
+        Some(srcset.join(", "))
+    }
+
+    /// Rewrites an `<img>` element into a responsive `<picture>`.
+    ///
+    /// Images that aren't indexed are only lazy loaded.
+    pub fn rewrite(&self, el: &mut lol_html::html_content::Element) -> lol_html::HandlerResult {
+        el.set_attribute("loading", "lazy")?;
+        el.set_attribute("decoding", "async")?;
+
+        let Some(src) = el.get_attribute("src") else {
+            return Ok(());
+        };
+        let (Some(image), Some(srcset)) = (self.by_url.get(&src), self.srcset(&src)) else {
+            return Ok(());
+        };
+
+        el.set_attribute("width", &image.width.to_string())?;
+        el.set_attribute("height", &image.height.to_string())?;
+
+        let source = maud::html! {
+            source type=(image.format.to_mime_type()) srcset=(srcset) sizes=(SIZES);
+        };
+        el.before(
+            &format!("<picture>{}", source.into_string()),
+            lol_html::html_content::ContentType::Html,
+        );
+        el.after("</picture>", lol_html::html_content::ContentType::Html);
+
+        Ok(())
+    }
+
+    /// Returns a resized variant of an image along with its mime type.
+    ///
+    /// Only the widths in [`WIDTHS`] that are smaller than the original can be generated, with the
+    /// extension of the original's format.
+    pub fn variant(
+        &self,
+        hash: u64,
+        width: u32,
+        extension: &str,
+    ) -> Option<(Arc<[u8]>, &'static str)> {
+        let image = self.by_url.get(self.by_hash.get(&hash)?)?;
+        if !WIDTHS.contains(&width) || width >= image.width || extension != image.extension {
+            return None;
+        }
+
+        let mime = image.format.to_mime_type();
+        if let Some(bytes) = self.variants.read().unwrap().get(&(hash, width)) {
+            return Some((bytes.clone(), mime));
+        }
+
+        let resized = image::load_from_memory_with_format(image.contents, image.format)
+            .map(|original| original.resize(width, u32::MAX, FilterType::Lanczos3))
+            .and_then(|resized| {
+                let mut bytes = vec![];
+                resized.write_to(&mut Cursor::new(&mut bytes), image.format)?;
+                Ok(bytes)
+            });
+
+        let bytes: Arc<[u8]> = match resized {
+            Ok(bytes) => bytes.into(),
+            Err(e) => {
+                log::error!("Failed to resize {:016x} to {}px: {}", hash, width, e);
+                return None;
+            }
+        };
+
+        self.variants
+            .write()
+            .unwrap()
+            .insert((hash, width), bytes.clone());
+        Some((bytes, mime))
+    }
+}
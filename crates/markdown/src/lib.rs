@@ -1,3 +1,8 @@
+mod diagram;
+pub mod highlight;
+pub mod images;
 mod markdown;
+mod sanitize;
+pub mod shortcodes;
 
-pub use markdown::Markdown;
+pub use markdown::{content_uri, Document, Heading, Link, Markdown, Profile, RenderOptions};
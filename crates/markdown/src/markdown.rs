@@ -1,20 +1,20 @@
 use std::{cell::RefCell, time::Duration};
 
+use crate::{
+    diagram,
+    highlight::{self, CodeInfo, PS},
+    images, sanitize,
+    shortcodes::{self, Args},
+};
 use comrak::{
     arena_tree::Node,
     nodes::{Ast, AstNode, NodeHtmlBlock, NodeValue},
-    Arena, Options,
+    Anchorizer, Arena, Options,
 };
 use lol_html::{element, Settings};
 use maud::{html, Render};
-use once_cell::sync::Lazy;
-use syntect::{highlighting::ThemeSet, parsing::SyntaxSet};
 
 const FRONT_MATTER_DELIMITER: &str = "---";
-const THEME: &str = "base16-eighties.dark";
-
-static PS: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
-static TS: Lazy<ThemeSet> = Lazy::new(ThemeSet::load_defaults);
 
 /// Wrapper around markdown content, implementing the [`Render`](maud::Render) trait.
 ///
@@ -22,15 +22,111 @@ static TS: Lazy<ThemeSet> = Lazy::new(ThemeSet::load_defaults);
 ///
 /// - Render markdown content to HTML (comrak)
 /// - Front Matter Parsing (serde_yaml)
-/// - Syntax Highlighting (syntect)
+/// - Syntax Highlighting (syntect, styled by a [`highlight::stylesheet`]) with line numbers,
+///   highlighted lines and captions
+/// - Diagrams rendered to inline SVG (`dot`, `flowchart` and `sequence` code blocks)
+/// - Shortcodes (`{{< figure src="..." >}}`, see [`shortcodes`](crate::shortcodes))
+/// - GitHub style callouts (`> [!NOTE]`, `> [!TIP]`, `> [!WARNING]`, ...)
 /// - Post processing (lol_html)
+/// - Responsive images (see [`images`](crate::images))
 /// - Reading time estimation
 ///
 /// # Warning
 ///
-/// HTML is not sanitized by this component
+/// HTML is not sanitized by the [`Render`] implementation, which uses the [`Profile::Trusted`]
+/// profile. Untrusted markdown must be rendered with [`Markdown::render_with`] and
+/// [`RenderOptions::sanitized`].
 pub struct Markdown<'a>(pub &'a str);
 
+/// How far the markdown being rendered is trusted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// Our own content, raw HTML is passed through untouched
+    #[default]
+    Trusted,
+    /// User supplied content, HTML is filtered through an allowlist
+    Sanitized,
+}
+
+/// Options for [`Markdown::render_with`]
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    pub profile: Profile,
+    /// Whether headings are given the ids of their [`Heading`], so they can be linked to
+    pub heading_ids: bool,
+    /// Where directories of the content are mounted, `("notes", "/notes")`, see [`content_uri`]
+    pub collections: Vec<(String, String)>,
+}
+
+impl RenderOptions {
+    /// Options for rendering our own content
+    pub fn trusted() -> Self {
+        RenderOptions {
+            profile: Profile::Trusted,
+            ..RenderOptions::default()
+        }
+    }
+
+    /// Options for rendering user supplied content
+    pub fn sanitized() -> Self {
+        RenderOptions {
+            profile: Profile::Sanitized,
+            ..RenderOptions::default()
+        }
+    }
+}
+
+/// The URI a path of the content directory is served at, `notes/idea.md` becomes
+/// `/notes/idea.md`.
+///
+/// The path is served by the deepest of the `collections` mounting a directory containing it, as
+/// `(directory, prefix)`, and from `/m/` if there are none.
+///
+/// # Example
+///
+/// ```
+/// use markdown::content_uri;
+///
+/// let collections = [("".to_string(), "/m".to_string()), ("notes".to_string(), "/notes/".to_string())];
+/// assert_eq!(content_uri(&collections, "notes/idea.md"), "/notes/idea.md");
+/// assert_eq!(content_uri(&collections, "notesy/idea.md"), "/m/notesy/idea.md");
+/// assert_eq!(content_uri(&[], "robotics/java.md"), "/m/robotics/java.md");
+/// ```
+pub fn content_uri(collections: &[(String, String)], path: &str) -> String {
+    collections
+        .iter()
+        .filter_map(|(dir, prefix)| {
+            let dir = dir.trim_matches('/');
+            let rest = match dir {
+                "" => path,
+                dir => match path.strip_prefix(dir)? {
+                    "" => "",
+                    rest => rest.strip_prefix('/')?,
+                },
+            };
+            Some((dir.len(), prefix.trim_end_matches('/'), rest))
+        })
+        .max_by_key(|(depth, _, _)| *depth)
+        .map(|(_, prefix, rest)| format!("{}/{}", prefix, rest))
+        .unwrap_or_else(|| format!("/m/{}", path))
+}
+
+/// Markdown content rendered along with what was found in it, from a single parse
+pub struct Document {
+    pub html: maud::Markup,
+    pub headings: Vec<Heading>,
+    pub links: Vec<Link>,
+    /// See [`Markdown::word_count`]
+    pub words: usize,
+}
+
+impl Document {
+    /// See [`Markdown::reading_time`]
+    pub fn reading_time(&self) -> Duration {
+        reading_time(self.words)
+    }
+}
+
 impl<'a> Markdown<'a> {
     pub fn new(s: &'a str) -> Self {
         Markdown(s)
@@ -107,13 +203,258 @@ impl<'a> Markdown<'a> {
         Some(serde_yaml::from_str(&front_matter))
     }
 
+    /// Collects every link and image destination in the markdown content, along with the line it
+    /// was found on. This includes shortcode arguments and the `href` and `src` attributes of raw
+    /// HTML.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use markdown::Markdown;
+    ///
+    /// let links = Markdown("# Hello\n\nSee [Java](/content/robotics/java.md)").links();
+    /// assert_eq!(links[0].url, "/content/robotics/java.md");
+    /// assert_eq!(links[0].line, 3);
+    /// ```
+    pub fn links(&self) -> Vec<Link> {
+        let arena = Arena::new();
+        let ast = comrak::parse_document(&arena, self.0, &get_comrak_options());
+        collect_links(ast, self.0)
+    }
+
+    /// Collects the headings of the markdown content in order, along with the ids they're given
+    /// when rendered with [`RenderOptions::heading_ids`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use markdown::Markdown;
+    ///
+    /// let headings = Markdown("# Hello `World`\n\n## Setup\n\n## Setup").headings();
+    /// assert_eq!(headings[0].text, "Hello World");
+    /// assert_eq!(headings[0].id, "hello-world");
+    /// assert_eq!(headings[2].level, 2);
+    /// assert_eq!(headings[2].id, "setup-1");
+    /// ```
+    pub fn headings(&self) -> Vec<Heading> {
+        let arena = Arena::new();
+        let ast = comrak::parse_document(&arena, self.0, &get_comrak_options());
+        collect_headings(ast)
+    }
+
+    /// Counts the words of the markdown content, excluding front matter, code blocks and raw HTML.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use markdown::Markdown;
+    ///
+    /// let markdown = Markdown("---\norder: 1\n---\n# Hello World\n\n```rust\nfn main() {}\n```\n");
+    /// assert_eq!(markdown.word_count(), 2);
+    /// ```
+    pub fn word_count(&self) -> usize {
+        let arena = Arena::new();
+        let ast = comrak::parse_document(&arena, self.0, &get_comrak_options());
+        count_words(ast)
+    }
+
     /// Estimates the reading time of the markdown content.
     ///
-    /// The reading time is calculated based on the average reading speed of 200 words per minute.
+    /// The reading time is calculated from the [`word_count`](Self::word_count) based on the
+    /// average reading speed of 200 words per minute.
     pub fn reading_time(&self) -> Duration {
-        let words = self.0.split_whitespace().count();
-        Duration::from_secs_f32(words as f32 / 200.0)
+        reading_time(self.word_count())
+    }
+
+    /// Renders the markdown content along with its headings, links and word count, parsing it
+    /// only once.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use markdown::{Markdown, RenderOptions};
+    ///
+    /// let options = RenderOptions {
+    ///     heading_ids: true,
+    ///     ..RenderOptions::trusted()
+    /// };
+    /// let document = Markdown("# Hello\n\nSee [Java](java.md)").document(&options);
+    /// assert!(document.html.into_string().contains(r#"id="hello""#));
+    /// assert_eq!(document.headings[0].id, "hello");
+    /// assert_eq!(document.links[0].url, "java.md");
+    /// assert_eq!(document.words, 3);
+    /// ```
+    pub fn document(&self, options: &RenderOptions) -> Document {
+        let arena = Arena::new();
+        let ast = comrak::parse_document(&arena, self.0, &get_comrak_options());
+
+        // Collected before rendering transforms the AST
+        let headings = collect_headings(ast);
+        let links = collect_links(ast, self.0);
+        let words = count_words(ast);
+
+        Document {
+            html: render_ast(&arena, ast, self.0, options),
+            headings,
+            links,
+            words,
+        }
+    }
+}
+
+/// At an average reading speed of 200 words per minute
+fn reading_time(words: usize) -> Duration {
+    Duration::from_secs_f32(words as f32 / 200.0)
+}
+
+fn collect_links<'a>(ast: &'a AstNode<'a>, source: &str) -> Vec<Link> {
+    // Shortcodes may point at images and files too, their inlines are skipped so autolinked
+    // arguments aren't counted twice
+    let mut links = vec![];
+    let shortcodes = NodeIter::new(ast)
+        .filter_map(|node| shortcode(node, source).map(|shortcode| (node, shortcode)))
+        .collect::<Vec<_>>();
+    for (_, (line, args)) in &shortcodes {
+        for (key, value) in &args.named {
+            if key != "src" && key != "href" {
+                continue;
+            }
+
+            links.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|url| !url.is_empty())
+                    .map(|url| Link {
+                        url: url.to_string(),
+                        line: *line,
+                        image: key == "src",
+                    }),
+            );
+        }
     }
+
+    let in_shortcode = |node: &'a AstNode<'a>| {
+        node.ancestors()
+            .any(|ancestor| shortcodes.iter().any(|(s, _)| std::ptr::eq(*s, ancestor)))
+    };
+    for node in NodeIter::new(ast).filter(|node| !in_shortcode(node)) {
+        let data = node.data.borrow();
+        let line = data.sourcepos.start.line;
+        let (url, image) = match &data.value {
+            NodeValue::Link(link) => (link.url.clone(), false),
+            NodeValue::Image(link) => (link.url.clone(), true),
+            NodeValue::HtmlBlock(NodeHtmlBlock { literal, .. })
+            | NodeValue::HtmlInline(literal) => {
+                links.extend(html_links(literal, line));
+                continue;
+            }
+            _ => continue,
+        };
+
+        links.push(Link { url, line, image });
+    }
+
+    links.sort_by_key(|link| link.line);
+    links
+}
+
+fn collect_headings<'a>(ast: &'a AstNode<'a>) -> Vec<Heading> {
+    let mut anchorizer = Anchorizer::new();
+    ast.descendants()
+        .filter_map(|node| match node.data.borrow().value {
+            NodeValue::Heading(heading) => Some((node, heading.level)),
+            _ => None,
+        })
+        .map(|(node, level)| {
+            let mut text = String::new();
+            collect_text(node, &mut text);
+
+            Heading {
+                level,
+                id: anchorizer.anchorize(text.clone()),
+                text,
+            }
+        })
+        .collect()
+}
+
+fn count_words<'a>(ast: &'a AstNode<'a>) -> usize {
+    NodeIter::new(ast)
+        .map(|node| match &node.data.borrow().value {
+            NodeValue::Text(text) => text.split_whitespace().count(),
+            NodeValue::Code(code) => code.literal.split_whitespace().count(),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// A heading found in markdown content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heading {
+    /// 1 through 6
+    pub level: u8,
+    pub text: String,
+    /// The `id` of the heading's anchor
+    pub id: String,
+}
+
+/// Collects the text of a node the same way comrak does when generating heading ids
+fn collect_text<'a>(node: &'a AstNode<'a>, output: &mut String) {
+    match &node.data.borrow().value {
+        NodeValue::Text(literal) => output.push_str(literal),
+        NodeValue::Code(code) => output.push_str(&code.literal),
+        NodeValue::Math(math) => output.push_str(&math.literal),
+        NodeValue::LineBreak | NodeValue::SoftBreak => output.push(' '),
+        _ => {
+            for child in node.children() {
+                collect_text(child, output);
+            }
+        }
+    }
+}
+
+/// A link or image found in markdown content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// The destination as written in the markdown
+    pub url: String,
+    /// The line the link starts on, counting from 1
+    pub line: usize,
+    /// Whether this is an image (`src`) rather than a link (`href`)
+    pub image: bool,
+}
+
+/// Collects the `href` and `src` attributes of raw HTML starting on `line`
+fn html_links(html: &str, line: usize) -> Vec<Link> {
+    let mut links = vec![];
+    // Attributes are found in order, so each is on the line of its next occurrence
+    let mut searched = 0;
+
+    lol_html::rewrite_str(
+        html,
+        Settings {
+            element_content_handlers: vec![element!("[href], [src]", |el| {
+                for (name, image) in [("href", false), ("src", true)] {
+                    let Some(url) = el.get_attribute(name).filter(|url| !url.is_empty()) else {
+                        continue;
+                    };
+
+                    searched += html[searched..].find(&url).unwrap_or_default();
+                    links.push(Link {
+                        line: line + html[..searched].matches('\n').count(),
+                        url,
+                        image,
+                    });
+                }
+                Ok(())
+            })],
+            ..Settings::default()
+        },
+    )
+    .expect("html link collection failed");
+
+    links
 }
 
 /// Returns my chosen comrak options
@@ -142,6 +483,201 @@ fn get_comrak_options() -> Options<'static> {
     options
 }
 
+/// Parses a paragraph consisting of only a shortcode, returning the line it starts on.
+///
+/// The source is used rather than the paragraph's inlines, which may have been turned into links
+/// or emphasis.
+fn shortcode<'a>(node: &'a AstNode<'a>, source: &str) -> Option<(usize, Args)> {
+    let node = node.data.borrow();
+    if !matches!(node.value, NodeValue::Paragraph) {
+        return None;
+    }
+
+    let (start, end) = (node.sourcepos.start, node.sourcepos.end);
+    let text = source
+        .lines()
+        .enumerate()
+        .skip(start.line - 1)
+        .take(end.line + 1 - start.line)
+        .map(|(i, line)| {
+            let line = if i + 1 == end.line {
+                line.get(..end.column).unwrap_or(line)
+            } else {
+                line
+            };
+            let line = if i + 1 == start.line {
+                line.get(start.column - 1..).unwrap_or(line)
+            } else {
+                line
+            };
+            line.trim()
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    Args::parse(&text).map(|args| (start.line, args))
+}
+
+/// Replaces shortcode paragraphs with their rendered components
+fn render_shortcodes<'a>(ast: &'a AstNode<'a>, source: &str) {
+    let paragraphs = NodeIter::new(ast)
+        .filter_map(|node| shortcode(node, source).map(|(_, args)| (node, args)))
+        .collect::<Vec<_>>();
+
+    for (node, args) in paragraphs {
+        // On failure the shortcode is left alone, so it's visible on the page
+        let html = match shortcodes::render(&args) {
+            Ok(html) => html,
+            Err(e) => {
+                log::error!("Error rendering shortcode: {}", e);
+                continue;
+            }
+        };
+
+        while let Some(child) = node.first_child() {
+            child.detach();
+        }
+        node.data.borrow_mut().value = NodeValue::HtmlBlock(NodeHtmlBlock {
+            block_type: 0,
+            literal: html,
+        });
+    }
+}
+
+/// Supported callout kinds with their default title and icon
+const CALLOUTS: [(&str, &str, &str); 5] = [
+    ("note", "Note", "\u{2139}"),
+    ("tip", "Tip", "\u{1F4A1}"),
+    ("important", "Important", "\u{2757}"),
+    ("warning", "Warning", "\u{26A0}"),
+    ("caution", "Caution", "\u{26D4}"),
+];
+
+/// Transforms block quotes starting with `[!KIND]` into `<aside>` callouts.
+///
+/// Text following the marker replaces the default title. Unknown kinds are left as block quotes.
+fn transform_callouts<'a>(arena: &'a Arena<AstNode<'a>>, ast: &'a AstNode<'a>) {
+    let quotes = NodeIter::new(ast)
+        .filter(|node| matches!(node.data.borrow().value, NodeValue::BlockQuote))
+        .collect::<Vec<_>>();
+
+    for quote in quotes {
+        let Some(paragraph) = quote
+            .first_child()
+            .filter(|p| matches!(p.data.borrow().value, NodeValue::Paragraph))
+        else {
+            continue;
+        };
+
+        let Some(marker) = paragraph.first_child() else {
+            continue;
+        };
+
+        let callout = match &marker.data.borrow().value {
+            NodeValue::Text(text) => text
+                .strip_prefix("[!")
+                .and_then(|text| text.split_once(']'))
+                .and_then(|(kind, title)| {
+                    CALLOUTS
+                        .iter()
+                        .find(|(k, _, _)| k.eq_ignore_ascii_case(kind))
+                        .map(|callout| (callout, title.trim().to_string()))
+                }),
+            _ => None,
+        };
+
+        let Some(((kind, default_title, icon), title)) = callout else {
+            continue;
+        };
+
+        // Remove the marker, and the line break following it
+        marker.detach();
+        if let Some(next) = paragraph.first_child() {
+            if matches!(
+                next.data.borrow().value,
+                NodeValue::SoftBreak | NodeValue::LineBreak
+            ) {
+                next.detach();
+            }
+        }
+        if paragraph.first_child().is_none() {
+            paragraph.detach();
+        }
+
+        let title = if title.is_empty() {
+            *default_title
+        } else {
+            title.as_str()
+        };
+        let heading = html! {
+            p.callout-title {
+                span.callout-icon aria-hidden="true" { (icon) }
+                (title)
+            }
+        };
+
+        // Replace the block quote with its children, wrapped by the opening and closing tags
+        let start = quote.data.borrow().sourcepos.start;
+        let html_block = |literal: String| {
+            arena.alloc(AstNode::new(RefCell::new(Ast::new(
+                NodeValue::HtmlBlock(NodeHtmlBlock {
+                    block_type: 0,
+                    literal,
+                }),
+                start,
+            ))))
+        };
+
+        quote.insert_before(html_block(format!(
+            "<aside class=\"callout callout-{}\">{}\n",
+            kind,
+            heading.into_string()
+        )));
+        while let Some(child) = quote.first_child() {
+            child.detach();
+            quote.insert_before(child);
+        }
+        quote.insert_before(html_block("</aside>\n".to_string()));
+        quote.detach();
+    }
+}
+
+fn render_diagrams<'a>(ast: &'a Node<'a, RefCell<Ast>>) {
+    let iter = NodeIter::new(ast);
+    for mut node in iter
+        .map(|node| node.data.borrow_mut())
+        .filter(|node| matches!(node.value, NodeValue::CodeBlock(_)))
+    {
+        let code_block = match &mut node.value {
+            NodeValue::CodeBlock(code_block) => code_block,
+            _ => unreachable!(),
+        };
+
+        let lang = code_block
+            .info
+            .split_whitespace()
+            .next()
+            .unwrap_or_default();
+        if !diagram::is_diagram(lang) {
+            continue;
+        }
+
+        // On failure the block is left alone and is rendered as code instead
+        let svg = match diagram::render(lang, &code_block.literal) {
+            Ok(svg) => svg,
+            Err(e) => {
+                log::error!("Error rendering {} diagram: {}", lang, e);
+                continue;
+            }
+        };
+
+        node.value = NodeValue::HtmlBlock(NodeHtmlBlock {
+            block_type: 0,
+            literal: format!("<figure class=\"diagram\">{}</figure>\n", svg),
+        });
+    }
+}
+
 fn perform_syntax_highlighting<'a>(ast: &'a Node<'a, RefCell<Ast>>) {
     let iter = NodeIter::new(ast);
     for mut node in iter
@@ -154,25 +690,21 @@ fn perform_syntax_highlighting<'a>(ast: &'a Node<'a, RefCell<Ast>>) {
             _ => unreachable!(),
         };
 
-        // Find supported syntax
-        let syntax = match PS.find_syntax_by_token(&code_block.info) {
+        // Find supported syntax, blocks with extras are still worth rendering as plain text
+        let info = CodeInfo::parse(&code_block.info);
+        let syntax = match PS.find_syntax_by_token(info.lang) {
             Some(syntax) => syntax,
+            None if info.has_extras() => PS.find_syntax_plain_text(),
             None => {
-                log::warn!(
-                    "Language {:?} is not supported in code block",
-                    code_block.info
-                );
+                if !info.lang.is_empty() {
+                    log::warn!("Language {:?} is not supported in code block", info.lang);
+                }
                 continue;
             }
         };
 
-        // Preform syntax highlighting with the THEME
-        let highlighted = match syntect::html::highlighted_html_for_string(
-            &code_block.literal,
-            &PS,
-            syntax,
-            &TS.themes[THEME],
-        ) {
+        // Highlight into classed spans, the colors come from the theme stylesheets
+        let html = match highlight::highlight(&code_block.literal, syntax, &info) {
             Ok(html) => html,
             Err(e) => {
                 log::error!("Error highlighting code block: {}", e);
@@ -180,26 +712,6 @@ fn perform_syntax_highlighting<'a>(ast: &'a Node<'a, RefCell<Ast>>) {
             }
         };
 
-        // Add the `code` class to the new `pre` element
-        let post_processed = lol_html::rewrite_str(
-            &highlighted,
-            Settings {
-                element_content_handlers: vec![element!("pre", |el| {
-                    el.set_attribute("class", "code")?;
-                    Ok(())
-                })],
-                ..Settings::default()
-            },
-        );
-
-        let html = match post_processed {
-            Ok(html) => html,
-            Err(e) => {
-                log::error!("Error adding code class: {}", e);
-                continue;
-            }
-        };
-
         // Finally we can edit the AST, replacing the code block with a raw HTML block
         node.value = NodeValue::HtmlBlock(NodeHtmlBlock {
             block_type: 0,
@@ -208,10 +720,17 @@ fn perform_syntax_highlighting<'a>(ast: &'a Node<'a, RefCell<Ast>>) {
     }
 }
 
-const DIRECTS: [(&'static str, &'static str); 2] = [("/static/", "/s/"), ("/content/", "/m/")];
+/// Where a link into the repository is served, `None` if it isn't one
+fn direct(url: &str, collections: &[(String, String)]) -> Option<String> {
+    if let Some(path) = url.strip_prefix("/static/") {
+        return Some(format!("/s/{}", path));
+    }
+    url.strip_prefix("/content/")
+        .map(|path| content_uri(collections, path))
+}
 
 // Adds `target="_blank"` and `rel="noopener"` to all links that lead to external websites
-fn post_process_links(html: &str) -> String {
+fn post_process_links(html: &str, collections: &[(String, String)]) -> String {
     lol_html::rewrite_str(
         html,
         Settings {
@@ -227,20 +746,16 @@ fn post_process_links(html: &str) -> String {
                 }),
                 element!("img", |el| {
                     if let Some(src) = el.get_attribute("src") {
-                        for (from, to) in &DIRECTS {
-                            if src.starts_with(from) {
-                                el.set_attribute("src", &src.replace(from, to))?;
-                            }
+                        if let Some(src) = direct(&src, collections) {
+                            el.set_attribute("src", &src)?;
                         }
                     }
                     Ok(())
                 }),
                 element!("a", |el| {
                     if let Some(href) = el.get_attribute("href") {
-                        for (from, to) in &DIRECTS {
-                            if href.starts_with(from) {
-                                el.set_attribute("href", &href.replace(from, to))?;
-                            }
+                        if let Some(href) = direct(&href, collections) {
+                            el.set_attribute("href", &href)?;
                         }
                     }
                     Ok(())
@@ -252,25 +767,80 @@ fn post_process_links(html: &str) -> String {
     .expect("a tag rewriting failed")
 }
 
-impl Render for Markdown<'_> {
-    fn render(&self) -> maud::Markup {
-        let arena = Arena::new();
-        let ast = comrak::parse_document(&arena, self.0, &get_comrak_options());
+// Rewrites images into responsive `<picture>` elements, once the image index has been built
+fn post_process_images(html: &str) -> String {
+    let Some(index) = images::index() else {
+        return html.to_string();
+    };
 
-        // Preform transformations on the AST
-        perform_syntax_highlighting(ast);
+    lol_html::rewrite_str(
+        html,
+        Settings {
+            element_content_handlers: vec![element!("img", |el| index.rewrite(el))],
+            ..Settings::default()
+        },
+    )
+    .expect("img tag rewriting failed")
+}
 
-        // Render the AST to HTML
-        let mut html = vec![];
-        comrak::format_html(ast, &get_comrak_options(), &mut html).unwrap();
-        let html = String::from_utf8_lossy(&html);
+impl Markdown<'_> {
+    /// Renders the markdown content to HTML with the given options.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use markdown::{Markdown, RenderOptions};
+    ///
+    /// let markdown = Markdown(r#"<b onclick="alert(1)">Hello</b> [world](javascript:alert(2))"#);
+    /// let html = markdown.render_with(&RenderOptions::sanitized()).into_string();
+    ///
+    /// assert_eq!(html, "<p><b>Hello</b> <a>world</a></p>\n");
+    /// ```
+    pub fn render_with(&self, options: &RenderOptions) -> maud::Markup {
+        let arena = Arena::new();
+        let ast = comrak::parse_document(&arena, self.0, &get_comrak_options());
+        render_ast(&arena, ast, self.0, options)
+    }
+}
 
-        // Post processing
-        let html = post_process_links(&html);
+/// Renders a parsed document, transforming its AST
+fn render_ast<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    ast: &'a AstNode<'a>,
+    source: &str,
+    options: &RenderOptions,
+) -> maud::Markup {
+    // Preform transformations on the AST
+    render_shortcodes(ast, source);
+    transform_callouts(arena, ast);
+    render_diagrams(ast);
+    perform_syntax_highlighting(ast);
+
+    // Render the AST to HTML
+    let mut comrak_options = get_comrak_options();
+    if options.heading_ids {
+        comrak_options.extension.header_ids = Some(String::new());
+    }
+    let mut html = vec![];
+    comrak::format_html(ast, &comrak_options, &mut html).unwrap();
+    let html = String::from_utf8_lossy(&html);
+
+    // Post processing
+    let html = post_process_links(&html, &options.collections);
+    let html = post_process_images(&html);
+    let html = match options.profile {
+        Profile::Trusted => html,
+        Profile::Sanitized => sanitize::sanitize(&html),
+    };
+
+    html! {
+        (maud::PreEscaped(&html))
+    }
+}
 
-        html! {
-            (maud::PreEscaped(&html))
-        }
+impl Render for Markdown<'_> {
+    fn render(&self) -> maud::Markup {
+        self.render_with(&RenderOptions::default())
     }
 }
 
@@ -296,3 +866,87 @@ impl<'a> Iterator for NodeIter<'a> {
         Some(node)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn callouts() {
+        let html = Markdown("> [!WARNING]\n> Hot *glue*\n\n> [!FOO]\n> plain")
+            .render()
+            .into_string();
+
+        assert!(html.contains(r#"<aside class="callout callout-warning">"#));
+        assert!(html.contains("Warning</p>\n<p>Hot <em>glue</em></p>\n</aside>"));
+        assert!(html.contains("<blockquote>\n<p>[!FOO]"));
+    }
+
+    #[test]
+    fn shortcodes() {
+        let markdown = Markdown(concat!(
+            "---\ntitle: Gallery\n---\n\n",
+            "{{< figure src=\"/static/img/gantt.png\" caption=\"The *plan*\" >}}\n\n",
+            "- {{< download href=https://example.com/robot_code.zip >}}\n\n",
+            "Inline {{< figure >}} is left alone\n",
+        ));
+
+        let html = markdown.render().into_string();
+        assert!(html.contains(r#"<figure><img src="/s/img/gantt.png" alt="The *plan*""#));
+        assert!(html.contains(r#"<a class="download" href="https://example.com/robot_code.zip""#));
+        assert!(html.contains("<p>Inline {{&lt; figure &gt;}} is left alone</p>"));
+
+        let links = markdown.links();
+        assert_eq!(links[0].url, "/static/img/gantt.png");
+        assert_eq!(links[0].line, 5);
+        assert!(links[0].image);
+        assert_eq!(links[1].url, "https://example.com/robot_code.zip");
+        assert_eq!(links.len(), 2);
+    }
+
+    #[test]
+    fn content_links() {
+        let markdown = Markdown(concat!(
+            "[Idea](/content/notes/idea.md) and [Java](/content/robotics/java.md)\n\n",
+            "<img src=\"/static/img/gantt.png\">\n",
+        ));
+
+        let html = markdown.render().into_string();
+        assert!(html.contains(r#"href="/m/notes/idea.md""#));
+
+        let options = RenderOptions {
+            collections: vec![
+                (String::new(), "/m".to_string()),
+                ("notes".to_string(), "/notes".to_string()),
+            ],
+            ..RenderOptions::trusted()
+        };
+        let html = markdown.render_with(&options).into_string();
+        assert!(html.contains(r#"href="/notes/idea.md""#));
+        assert!(html.contains(r#"href="/m/robotics/java.md""#));
+        assert!(html.contains(r#"src="/s/img/gantt.png""#));
+    }
+
+    #[test]
+    fn html_links() {
+        let links = Markdown(concat!(
+            "Some <a href=\"/content/robotics/java.md\">inline</a> html\n\n",
+            "<div>\n<img src=\"/static/img/gantt.png\">\n\n",
+            "<a href=\"#top\">Top</a>\n</div>\n",
+        ))
+        .links();
+
+        let found = links
+            .iter()
+            .map(|link| (link.url.as_str(), link.line, link.image))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                ("/content/robotics/java.md", 1, false),
+                ("/static/img/gantt.png", 4, true),
+                ("#top", 6, false),
+            ]
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! Allowlist based HTML sanitization, used when rendering untrusted markdown.
+//!
+//! Elements and attributes not on the allowlist are removed. Dangerous elements (`script`,
+//! `style`, `iframe`, ...) are removed along with their content while any other unknown element is
+//! unwrapped, keeping its content. URLs are restricted to relative paths and a handful of schemes.
+//! Element ids are prefixed with [`ID_PREFIX`] so user content can't clobber the page's own ids.
+
+use lol_html::{comments, element, html_content::Element, HandlerResult, Settings};
+
+/// Elements that are removed along with everything inside of them
+#[rustfmt::skip]
+const DROPPED: &[&str] = &[
+    "script", "style", "iframe", "frame", "frameset", "object", "embed", "applet", "noscript",
+    "template", "form", "textarea", "select", "button", "link", "meta", "base",
+];
+
+/// Elements that are kept
+#[rustfmt::skip]
+const ELEMENTS: &[&str] = &[
+    "a", "abbr", "aside", "b", "blockquote", "br", "caption", "cite", "code", "col", "colgroup",
+    "dd", "del", "details", "div", "dl", "dt", "em", "figcaption", "figure", "h1", "h2", "h3", "h4",
+    "h5", "h6", "hr", "i", "img", "input", "ins", "kbd", "li", "mark", "ol", "p", "picture", "pre",
+    "q", "s", "samp", "section", "small", "source", "span", "strong", "sub", "summary", "sup",
+    "table", "tbody", "td", "tfoot", "th", "thead", "tr", "u", "ul", "var",
+    // Inline SVG, as produced by diagrams
+    "svg", "g", "defs", "marker", "path", "rect", "circle", "ellipse", "line", "polyline",
+    "polygon", "text", "tspan", "textpath", "title",
+];
+
+/// Attributes allowed on every kept element
+const GLOBAL_ATTRIBUTES: &[&str] = &["class", "title", "lang", "dir", "aria-hidden"];
+
+/// Prefix given to the `id` of elements, as GitHub does
+const ID_PREFIX: &str = "user-content-";
+
+/// Attributes allowed on specific elements
+#[rustfmt::skip]
+const ATTRIBUTES: &[(&str, &[&str])] = &[
+    ("a", &["href", "target", "rel"]),
+    ("img", &["src", "srcset", "alt", "width", "height", "loading", "decoding"]),
+    ("source", &["srcset", "sizes", "type"]),
+    ("input", &["type", "checked", "disabled"]),
+    ("ol", &["start"]),
+    ("td", &["align", "colspan", "rowspan"]),
+    ("th", &["align", "colspan", "rowspan"]),
+    ("details", &["open"]),
+];
+
+/// Presentation attributes allowed on SVG elements
+#[rustfmt::skip]
+const SVG_ATTRIBUTES: &[&str] = &[
+    "width", "height", "viewbox", "xmlns", "fill", "stroke", "stroke-width", "stroke-dasharray",
+    "d", "x", "y", "dx", "dy", "x1", "y1", "x2", "y2", "cx", "cy", "r", "rx", "ry", "points",
+    "transform", "font-size", "font-family", "text-anchor", "dominant-baseline", "marker-start",
+    "marker-end", "markerwidth", "markerheight", "refx", "refy", "orient", "startoffset", "href",
+];
+
+/// Attributes holding a URL, or a list of them
+const URL_ATTRIBUTES: &[&str] = &["href", "src", "srcset"];
+
+/// Schemes that may be linked to, anything else (`javascript:`, `data:`, ...) is removed
+const SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Sanitizes an HTML fragment
+pub(crate) fn sanitize(html: &str) -> String {
+    lol_html::rewrite_str(
+        html,
+        Settings {
+            element_content_handlers: vec![
+                element!("*", sanitize_element),
+                comments!("*", |c| {
+                    c.remove();
+                    Ok(())
+                }),
+            ],
+            ..Settings::default()
+        },
+    )
+    .expect("html sanitization failed")
+}
+
+fn sanitize_element(el: &mut Element) -> HandlerResult {
+    let tag = el.tag_name().to_ascii_lowercase();
+
+    if DROPPED.contains(&tag.as_str()) {
+        el.remove();
+        return Ok(());
+    }
+
+    if !ELEMENTS.contains(&tag.as_str()) {
+        el.remove_and_keep_content();
+        return Ok(());
+    }
+
+    // The only inputs markdown produces are task list checkboxes
+    if tag == "input" && el.get_attribute("type").as_deref() != Some("checkbox") {
+        el.remove();
+        return Ok(());
+    }
+
+    let is_svg = el.namespace_uri() == "http://www.w3.org/2000/svg";
+    let allowed = ATTRIBUTES
+        .iter()
+        .find(|(t, _)| *t == tag)
+        .map(|(_, attributes)| *attributes)
+        .unwrap_or_default();
+
+    let attributes = el
+        .attributes()
+        .iter()
+        .map(|a| (a.name(), a.value()))
+        .collect::<Vec<_>>();
+
+    for (name, value) in attributes {
+        let lower = name.to_ascii_lowercase();
+        let mut keep = GLOBAL_ATTRIBUTES.contains(&lower.as_str())
+            || allowed.contains(&lower.as_str())
+            || (is_svg && SVG_ATTRIBUTES.contains(&lower.as_str()));
+
+        if lower == "id" && !value.is_empty() {
+            if !value.starts_with(ID_PREFIX) {
+                el.set_attribute(&name, &format!("{}{}", ID_PREFIX, value))?;
+            }
+            continue;
+        }
+
+        if keep && URL_ATTRIBUTES.contains(&lower.as_str()) {
+            keep = if lower == "srcset" {
+                decode_entities(&value)
+                    .split(',')
+                    .all(|candidate| is_safe_url(candidate.split_whitespace().next().unwrap_or("")))
+            } else if is_svg {
+                // SVG may only reference elements within the document
+                value.starts_with('#')
+            } else {
+                is_safe_url(&value)
+            };
+        }
+
+        if !keep {
+            el.remove_attribute(&name);
+        }
+    }
+
+    // Don't lend our reputation to links in user content
+    if tag == "a"
+        && el
+            .get_attribute("href")
+            .is_some_and(|h| h.starts_with("http"))
+    {
+        el.set_attribute("rel", "nofollow noopener")?;
+    }
+
+    Ok(())
+}
+
+/// Named character references that can hide a scheme, the rest can't appear in one
+const ENTITIES: &[(&str, char)] = &[
+    ("amp", '&'),
+    ("colon", ':'),
+    ("tab", '\t'),
+    ("newline", '\n'),
+    ("nbsp", '\u{a0}'),
+    ("sol", '/'),
+    ("quest", '?'),
+    ("num", '#'),
+];
+
+/// Decodes the character references of an attribute value as the browser would.
+///
+/// Only numeric references and [`ENTITIES`] are decoded, others are left as they are.
+fn decode_entities(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let (c, len) = if let Some(number) = rest.strip_prefix('#') {
+            let (radix, digits) = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => (16, hex),
+                None => (10, number),
+            };
+            let end = digits
+                .find(|c: char| !c.is_digit(radix))
+                .unwrap_or(digits.len());
+            let c = u32::from_str_radix(&digits[..end], radix)
+                .ok()
+                .map(|n| char::from_u32(n).unwrap_or(char::REPLACEMENT_CHARACTER));
+            (c, rest.len() - digits.len() + end)
+        } else {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let name = rest[..end].to_ascii_lowercase();
+            let c = ENTITIES
+                .iter()
+                .find(|(entity, _)| *entity == name)
+                .map(|(_, c)| *c);
+            (c, end)
+        };
+
+        match c {
+            Some(c) => {
+                decoded.push(c);
+                rest = rest[len..].strip_prefix(';').unwrap_or(&rest[len..]);
+            }
+            None => decoded.push('&'),
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Returns true for relative URLs and URLs with an allowed scheme
+fn is_safe_url(url: &str) -> bool {
+    // Browsers decode references, then ignore whitespace and control characters when parsing a
+    // scheme
+    let url = decode_entities(url)
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>();
+
+    let end = url.find(['/', '?', '#']).unwrap_or(url.len());
+    let scheme = &url[..end];
+    // An undecoded reference could still be hiding a colon
+    if scheme.contains('&') {
+        return false;
+    }
+
+    match scheme.split_once(':') {
+        Some((scheme, _)) => SCHEMES.contains(&scheme.to_ascii_lowercase().as_str()),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        assert!(is_safe_url("/s/img/gantt.png"));
+        assert!(is_safe_url("../abstraction.md#commands"));
+        assert!(is_safe_url("https://mahoney.best"));
+        assert!(is_safe_url("mailto:me@mahoney.best"));
+        assert!(!is_safe_url("javascript:alert(1)"));
+        assert!(!is_safe_url(" java\tscript:alert(1)"));
+        assert!(!is_safe_url("data:text/html,<script>alert(1)</script>"));
+        assert!(is_safe_url("/search?q=robots&amp;page=2"));
+    }
+
+    #[test]
+    fn encoded_schemes() {
+        assert!(!is_safe_url("&#106;avascript:alert(1)"));
+        assert!(!is_safe_url("&#x6A&#x61vascript:alert(1)"));
+        assert!(!is_safe_url("javascript&colon;alert(1)"));
+        assert!(!is_safe_url("jav&#x09;ascript:alert(1)"));
+        assert!(!is_safe_url("jav&Tab;ascript:alert(1)"));
+        assert!(!is_safe_url("javascript&unknown;alert(1)"));
+
+        let html = sanitize(r#"<a href="&#106;avascript:alert(1)">a</a>"#);
+        assert_eq!(html, "<a>a</a>");
+        let html = sanitize(r#"<img srcset="/s/a.png 1x, &#106;avascript:alert(1) 2x">"#);
+        assert_eq!(html, "<img>");
+    }
+
+    #[test]
+    fn ids() {
+        let html = sanitize(r#"<h2 id="setup">Setup</h2><p id="user-content-a"></p>"#);
+        assert_eq!(
+            html,
+            r#"<h2 id="user-content-setup">Setup</h2><p id="user-content-a"></p>"#
+        );
+    }
+
+    #[test]
+    fn elements() {
+        let html = sanitize(concat!(
+            r#"<p style="color: red" onmouseover="alert(1)">Hi<!-- secret --></p>"#,
+            r#"<marquee>moving</marquee><iframe src="https://example.com">frame</iframe>"#,
+            r#"<svg viewBox="0 0 1 1" onload="alert(1)"><path d="M 0 0" /></svg>"#,
+        ));
+
+        assert_eq!(
+            html,
+            concat!(
+                "<p>Hi</p>moving",
+                r#"<svg viewBox="0 0 1 1"><path d="M 0 0" /></svg>"#,
+            )
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! Shortcodes, components embedded in markdown content.
+//!
+//! A paragraph consisting of nothing but a shortcode is replaced by the component it names:
+//!
+//! ```markdown
+//! {{< figure src="/static/img/gantt.png" caption="The build season" >}}
+//! ```
+//!
+//! Arguments are `key=value` pairs, values containing spaces must be quoted. Arguments without a
+//! key are positional, `{{< youtube dQw4w9WgXcQ >}}`.
+//!
+//! The built in components are `figure`, `gallery`, `youtube` and `download`. More can be added
+//! with [`register`], a component registered under an existing name replaces it.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use maud::{html, Markup};
+use once_cell::sync::Lazy;
+
+/// A component renders the arguments of a shortcode, or explains why it can't
+pub type Component = Arc<dyn Fn(&Args) -> Result<Markup, String> + Send + Sync>;
+
+static REGISTRY: Lazy<RwLock<HashMap<String, Component>>> = Lazy::new(|| {
+    let builtins: [(&str, Component); 4] = [
+        ("figure", Arc::new(figure)),
+        ("gallery", Arc::new(gallery)),
+        ("youtube", Arc::new(youtube)),
+        ("download", Arc::new(download)),
+    ];
+
+    RwLock::new(
+        builtins
+            .into_iter()
+            .map(|(name, component)| (name.to_string(), component))
+            .collect(),
+    )
+});
+
+/// Registers a component under `name`, replacing any component with the same name
+pub fn register<F>(name: &str, component: F)
+where
+    F: Fn(&Args) -> Result<Markup, String> + Send + Sync + 'static,
+{
+    REGISTRY
+        .write()
+        .unwrap()
+        .insert(name.to_string(), Arc::new(component));
+}
+
+/// The parsed arguments of a shortcode
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    pub name: String,
+    pub positional: Vec<String>,
+    pub named: Vec<(String, String)>,
+}
+
+impl Args {
+    /// Parses `{{< name key="value" ... >}}`, returning `None` if `source` isn't a shortcode
+    pub fn parse(source: &str) -> Option<Self> {
+        let inner = source
+            .trim()
+            .strip_prefix("{{<")?
+            .strip_suffix(">}}")?
+            .trim();
+
+        let mut tokens = Tokens(inner);
+        let name = match tokens.next()? {
+            (None, name) if !name.is_empty() => name,
+            _ => return None,
+        };
+
+        let mut args = Args {
+            name,
+            ..Args::default()
+        };
+        for token in tokens {
+            match token {
+                (Some(key), value) => args.named.push((key, value)),
+                (None, value) => args.positional.push(value),
+            }
+        }
+
+        Some(args)
+    }
+
+    /// Returns the value of a named argument
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.named
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the value of a named argument, failing if it's missing
+    pub fn required(&self, key: &str) -> Result<&str, String> {
+        self.get(key)
+            .ok_or_else(|| format!("`{}` is missing the `{}` argument", self.name, key))
+    }
+}
+
+/// Splits arguments into `(key, value)` pairs, honoring quotes
+struct Tokens<'a>(&'a str);
+
+impl Iterator for Tokens<'_> {
+    type Item = (Option<String>, String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.0.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+
+        let (key, rest) = match rest.find(['=', ' ', '"']) {
+            Some(i) if rest[i..].starts_with('=') => (Some(rest[..i].to_string()), &rest[i + 1..]),
+            _ => (None, rest),
+        };
+
+        let (value, rest) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => rest.split_once(' ').unwrap_or((rest, "")),
+        };
+
+        self.0 = rest;
+        Some((key, value.to_string()))
+    }
+}
+
+/// Renders a shortcode with its registered component
+pub(crate) fn render(args: &Args) -> Result<String, String> {
+    let component = REGISTRY
+        .read()
+        .unwrap()
+        .get(&args.name)
+        .cloned()
+        .ok_or_else(|| format!("unknown shortcode `{}`", args.name))?;
+
+    component(args).map(|markup| markup.into_string() + "\n")
+}
+
+/// An image with a caption, `{{< figure src="..." caption="..." alt="..." >}}`
+fn figure(args: &Args) -> Result<Markup, String> {
+    let src = args.required("src")?;
+    let caption = args.get("caption");
+    let alt = args.get("alt").or(caption).unwrap_or_default();
+
+    Ok(html! {
+        figure {
+            img src=(src) alt=(alt);
+            @if let Some(caption) = caption {
+                figcaption { (caption) }
+            }
+        }
+    })
+}
+
+/// A grid of images, `{{< gallery src="a.png, b.png" caption="..." >}}`
+fn gallery(args: &Args) -> Result<Markup, String> {
+    let srcs = args.required("src")?;
+
+    Ok(html! {
+        figure.gallery {
+            div.gallery-images {
+                @for src in srcs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                    a href=(src) { img src=(src) alt=""; }
+                }
+            }
+            @if let Some(caption) = args.get("caption") {
+                figcaption { (caption) }
+            }
+        }
+    })
+}
+
+/// An embedded video, `{{< youtube id >}}` or `{{< youtube id="..." title="..." >}}`
+fn youtube(args: &Args) -> Result<Markup, String> {
+    let id = args
+        .get("id")
+        .or(args.positional.first().map(String::as_str))
+        .ok_or("`youtube` is missing the video id")?;
+
+    if !id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!("invalid youtube id {:?}", id));
+    }
+
+    Ok(html! {
+        div.video {
+            iframe
+                src=(format!("https://www.youtube-nocookie.com/embed/{}", id))
+                title=(args.get("title").unwrap_or("YouTube video"))
+                loading="lazy"
+                allow="encrypted-media; picture-in-picture"
+                allowfullscreen {}
+        }
+    })
+}
+
+/// A card linking to a file, `{{< download href="..." title="..." size="..." >}}`
+fn download(args: &Args) -> Result<Markup, String> {
+    let href = args.required("href")?;
+    let name = href.rsplit('/').next().unwrap_or(href);
+
+    Ok(html! {
+        a.download href=(href) download {
+            span.download-icon aria-hidden="true" { "\u{2B73}" }
+            span.download-title { (args.get("title").unwrap_or(name)) }
+            @if let Some(size) = args.get("size") {
+                span.download-size { (size) }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let args = Args::parse(r#"{{< figure src=/s/img/a.png caption="A robot" big >}}"#).unwrap();
+        assert_eq!(
+            args,
+            Args {
+                name: "figure".to_string(),
+                positional: vec!["big".to_string()],
+                named: vec![
+                    ("src".to_string(), "/s/img/a.png".to_string()),
+                    ("caption".to_string(), "A robot".to_string()),
+                ],
+            }
+        );
+
+        assert!(Args::parse("{{< >}}").is_none());
+        assert!(Args::parse("{{ figure }}").is_none());
+        assert!(Args::parse("Not {{< figure >}}").is_none());
+    }
+
+    #[test]
+    fn registry() {
+        register("shout", |args| {
+            Ok(html! { strong { (args.positional.join(" ").to_uppercase()) } })
+        });
+
+        let args = Args::parse("{{< shout hello world >}}").unwrap();
+        assert_eq!(render(&args).unwrap(), "<strong>HELLO WORLD</strong>\n");
+
+        let args = Args::parse("{{< figure >}}").unwrap();
+        assert_eq!(
+            render(&args).unwrap_err(),
+            "`figure` is missing the `src` argument"
+        );
+    }
+}
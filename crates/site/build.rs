@@ -0,0 +1,51 @@
+//! Captures the date each content file was last changed from git history, so pages can show when
+//! they were updated without it being written in their front matter.
+
+use std::{collections::HashMap, env, fs, path::Path, process::Command};
+
+fn main() {
+    println!("cargo:rerun-if-changed=../../content");
+    // Every commit and checkout is appended to the HEAD log, HEAD itself only changes on checkout.
+    // Watching files that don't exist would rerun the script on every build.
+    for file in ["../../.git/logs/HEAD", "../../.git/index"] {
+        if Path::new(file).exists() {
+            println!("cargo:rerun-if-changed={}", file);
+        }
+    }
+
+    // Newest commits come first, so the first date seen for a path is its latest
+    let output = Command::new("git")
+        .args([
+            "log",
+            "--format=date %cs",
+            "--name-only",
+            "--relative",
+            "--",
+            ".",
+        ])
+        .current_dir("../../content")
+        .output();
+
+    let mut dates = HashMap::new();
+    let mut lines = vec![];
+    match output {
+        Ok(output) if output.status.success() => {
+            let log = String::from_utf8_lossy(&output.stdout);
+            let mut date = "";
+            for line in log.lines() {
+                if let Some(d) = line.strip_prefix("date ") {
+                    date = d;
+                } else if !line.is_empty() && !dates.contains_key(line) {
+                    dates.insert(line, date);
+                    lines.push(format!("{} {}", date, line));
+                }
+            }
+        }
+        // Building outside of a git checkout (such as the docker image, which leaves out `.git`),
+        // pages fall back to their front matter
+        _ => println!("cargo:warning=Unable to read content dates from git history"),
+    }
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("content-dates.txt");
+    fs::write(out, lines.join("\n")).unwrap();
+}
@@ -1,5 +1,13 @@
+mod error;
+mod format;
 mod pixel;
 mod robots;
+mod shortcodes;
+mod sitemap;
 
+pub use error::error_page;
+pub use format::bytes;
 pub use pixel::pixel_art_view;
-pub use robots::robots;
+pub use robots::{robots, HIDDEN_SERVICES};
+pub use shortcodes::register_shortcodes;
+pub use sitemap::sitemap;
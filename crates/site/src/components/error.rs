@@ -0,0 +1,34 @@
+use actix_web::http::StatusCode;
+use maud::{html, Markup, DOCTYPE};
+
+/// A friendly explanation of an error status
+fn description(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "Something about that request didn't make sense.",
+        StatusCode::UNAUTHORIZED => "You need to log in to see this.",
+        StatusCode::NOT_FOUND => "There's nothing here.",
+        StatusCode::PAYLOAD_TOO_LARGE => "That upload is too large.",
+        _ => "Something went wrong on our end.",
+    }
+}
+
+/// An error page, rendered with the site's layout.
+///
+/// `suggestion` is the path of a page the visitor may have meant.
+pub fn error_page(status: StatusCode, nav: Markup, suggestion: Option<&str>) -> Markup {
+    let reason = status.canonical_reason().unwrap_or("Error");
+
+    html! {
+        (DOCTYPE)
+        (blog::header(&format!("{} {}", status.as_u16(), reason)))
+        main {
+            (nav)
+            h1 { (status.as_u16()) " " (reason) }
+            p { (description(status)) }
+            @if let Some(suggestion) = suggestion {
+                p { "Did you mean " a href=(suggestion) { (suggestion) } "?" }
+            }
+            p { a href="/" { "Go home" } }
+        }
+    }
+}
@@ -1,5 +1,8 @@
+/// Services that crawlers are asked to stay out of
+pub const HIDDEN_SERVICES: &[&str] = &["a", "t", "r", "u", "f", "w"];
+
 /// Creates the robots.txt file
-pub fn robots(hidden_services: &[&str]) -> String {
+pub fn robots(hidden_services: &[&str], sitemap: &str) -> String {
     let mut robots = String::new();
 
     robots.push_str("User-agent: *\n");
@@ -8,5 +11,7 @@ pub fn robots(hidden_services: &[&str]) -> String {
         robots.push_str(&format!("Disallow: /{}\n", service));
     }
 
+    robots.push_str(&format!("\nSitemap: {}\n", sitemap));
+
     robots
 }
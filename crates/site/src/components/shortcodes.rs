@@ -0,0 +1,54 @@
+use std::io::Cursor;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use include_dir::Dir;
+use markdown::shortcodes::{self, Args};
+use maud::{html, Markup};
+
+/// Registers the site's own shortcodes, on top of the markdown crate's built in ones
+pub fn register_shortcodes(statics: &'static Dir<'static>) {
+    shortcodes::register("autopixel", move |args| autopixel(args, statics));
+}
+
+/// Pixel art of a static image, `{{< autopixel src="/static/img/..." size=8 colors=16 >}}`
+fn autopixel(args: &Args, statics: &Dir) -> Result<Markup, String> {
+    let src = args.required("src")?;
+    let number = |key: &str, default: usize| match args.get(key) {
+        Some(value) => value
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| format!("`{}` must be a positive number", key)),
+        None => Ok(default),
+    };
+    let size = number("size", 8)?;
+    let colors = number("colors", 16)?;
+
+    let path = ["/static/", "/s/"]
+        .iter()
+        .find_map(|prefix| src.strip_prefix(prefix))
+        .ok_or_else(|| format!("{} is not a static file", src))?;
+    let file = statics
+        .get_file(path)
+        .ok_or_else(|| format!("{} does not exist", src))?;
+
+    let (_, image) = autopixel::autopixel(Cursor::new(file.contents()), size, colors)
+        .map_err(|e| format!("autopixel failed on {}: {}", src, e))?;
+    let png = STANDARD.encode(autopixel::encode_png(&image));
+
+    Ok(html! {
+        figure.autopixel {
+            img
+                src=(format!("data:image/png;base64,{}", png))
+                alt=(args.get("alt").or(args.get("caption")).unwrap_or("Pixel art"))
+                width=(image.width() as usize * size)
+                height=(image.height() as usize * size);
+            figcaption {
+                @if let Some(caption) = args.get("caption") {
+                    (caption) " "
+                }
+                a href="/pixel/" { "Make your own" }
+            }
+        }
+    })
+}
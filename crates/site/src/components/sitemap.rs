@@ -0,0 +1,20 @@
+use chrono::NaiveDate;
+use maud::{html, PreEscaped};
+
+/// Creates an XML sitemap from absolute URLs and when they were last modified
+pub fn sitemap(urls: &[(String, Option<NaiveDate>)]) -> String {
+    html! {
+        (PreEscaped(r#"<?xml version="1.0" encoding="UTF-8"?>"#))
+        urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9" {
+            @for (loc, lastmod) in urls {
+                url {
+                    loc { (loc) }
+                    @if let Some(lastmod) = lastmod {
+                        lastmod { (lastmod) }
+                    }
+                }
+            }
+        }
+    }
+    .into_string()
+}
@@ -1,18 +1,205 @@
+use std::path::PathBuf;
+
+use blog::Collection;
+use ipnetwork::IpNetwork;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
-    pub username: String,
-    pub password: String,
+    /// The first admin, created while there are no accounts in `users.toml` of the data directory
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Where the site is hosted, used for canonical URLs and the sitemap
+    #[serde(default = "default_site_url")]
+    pub site_url: String,
+    /// Where state that outlives the process is kept, such as short links
+    #[serde(default = "default_data_dir")]
+    pub data_dir: PathBuf,
+    #[serde(default)]
+    pub session: SessionConfig,
+    #[serde(default)]
+    pub highlight: HighlightConfig,
+    /// Content collections, each mounted at its own prefix. By default the whole content is
+    /// served from `/m`, see [`blog::collection`]
+    #[serde(default = "default_collections")]
+    pub collections: Vec<Collection>,
+    /// Read the content from this directory rather than the copy baked into the binary, so
+    /// changes can be picked up from the admin panel without a rebuild
+    pub content_dir: Option<PathBuf>,
+    /// Redirect rules, checked before the ones in `redirects.toml` of the data directory
+    #[serde(default)]
+    pub redirects: Vec<RedirectRule>,
+    /// Trusted proxies and geolocation of the "who" service
+    #[serde(default)]
+    pub who: WhoConfig,
+}
+
+fn default_site_url() -> String {
+    "https://mahoney.best".to_string()
+}
+
+fn default_data_dir() -> PathBuf {
+    PathBuf::from("data")
+}
+
+fn default_collections() -> Vec<Collection> {
+    vec![Collection::default()]
+}
+
+/// Syntax highlighting themes, one for each `prefers-color-scheme`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HighlightConfig {
+    pub light: String,
+    pub dark: String,
+}
+
+impl Default for HighlightConfig {
+    fn default() -> Self {
+        HighlightConfig {
+            light: "InspiredGitHub".to_string(),
+            dark: "base16-eighties.dark".to_string(),
+        }
+    }
+}
+
+/// Which cross-site requests the session cookie is sent with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSite {
+    Strict,
+    #[default]
+    Lax,
+    None,
+}
+
+/// Configuration of login sessions
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionConfig {
+    /// The key signing the cookie, relative to the data directory
+    #[serde(default = "default_key_file")]
+    pub key_file: PathBuf,
+    #[serde(default = "default_cookie_name")]
+    pub cookie_name: String,
+    /// Only send the cookie over HTTPS, turn it off to log in over plain HTTP while developing
+    #[serde(default = "default_secure")]
+    pub secure: bool,
+    #[serde(default)]
+    pub same_site: SameSite,
+    /// Seconds without a request before being logged out, 0 to never time out
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: u64,
+    /// Seconds after logging in before being logged out however active the session is, 0 to
+    /// stay logged in until the browser is closed
+    #[serde(default = "default_absolute_timeout")]
+    pub absolute_timeout: u64,
+}
+
+fn default_key_file() -> PathBuf {
+    "session.key".into()
+}
+
+fn default_cookie_name() -> String {
+    "session".to_string()
+}
+
+fn default_secure() -> bool {
+    true
+}
+
+fn default_idle_timeout() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_absolute_timeout() -> u64 {
+    30 * 24 * 60 * 60
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            key_file: default_key_file(),
+            cookie_name: default_cookie_name(),
+            secure: default_secure(),
+            same_site: SameSite::default(),
+            idle_timeout: default_idle_timeout(),
+            absolute_timeout: default_absolute_timeout(),
+        }
+    }
+}
+
+/// How a rule's `from` is compared to the request path
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Match {
+    #[default]
+    Exact,
+    Prefix,
+    Regex,
+}
+
+/// A redirect rule as written in the configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedirectRule {
+    pub from: String,
+    pub to: String,
+    #[serde(default, rename = "match")]
+    pub kind: Match,
+    /// Redirect with a 301 rather than a 302
+    #[serde(default)]
+    pub permanent: bool,
+}
+
+/// Configuration of the "who" service
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WhoConfig {
+    /// Proxies trusted to report the client's address, `["127.0.0.1/32", "10.0.0.0/8"]`
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNetwork>,
+    /// A MaxMind City or Country database, such as GeoLite2-City.mmdb
+    pub geoip_database: Option<PathBuf>,
+    /// How many recent requests are kept for the log, 0 to turn it off
+    #[serde(default = "default_log_size")]
+    pub log_size: usize,
+}
+
+fn default_log_size() -> usize {
+    200
+}
+
+impl Default for WhoConfig {
+    fn default() -> Self {
+        WhoConfig {
+            trusted_proxies: vec![],
+            geoip_database: None,
+            log_size: default_log_size(),
+        }
+    }
 }
 
 impl Config {
     pub fn load() -> Config {
         let config = std::fs::read_to_string("config.toml").expect("Could not open config.toml");
-        toml::from_str(&config).expect("Failed to parse config.toml")
-    }
+        let config: Config = toml::from_str(&config).expect("Failed to parse config.toml");
+
+        for theme in [&config.highlight.light, &config.highlight.dark] {
+            if markdown::highlight::stylesheet(theme).is_none() {
+                let themes = markdown::highlight::themes().collect::<Vec<_>>();
+                panic!(
+                    "Unknown highlight theme {:?}, expected one of {:?}",
+                    theme, themes
+                );
+            }
+        }
+
+        if config.collections.is_empty() {
+            panic!("At least one collection must be configured");
+        }
+        for collection in &config.collections {
+            if let Err(e) = collection.validate() {
+                panic!("{}", e);
+            }
+        }
 
-    pub fn check_admin(&self, username: &str, password: &str) -> bool {
-        username == self.username && password == self.password
+        config
     }
 }
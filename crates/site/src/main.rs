@@ -1,39 +1,144 @@
 use actix_web::{
-    middleware::{Compress, Logger},
+    middleware::{from_fn, Compress, Logger},
     web::{redirect, Data},
     App, HttpServer,
 };
+use blog::Visibility;
 use mahoney_best::{
     components,
     config::Config,
-    services::{self, ArtCache},
+    services::{
+        self, ApiTokens, ArtCache, AuditLog, Content, Health, LoginThrottle, Redirects, Role,
+        ShortLinks, UploadIndex, Users, Who,
+    },
 };
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+
+/// How often the short link hits are written to disk
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+/// How often `redirects.toml` is checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Runs `task` every `period` in the background
+fn every(period: Duration, task: impl Fn() + 'static) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(period);
+        loop {
+            interval.tick().await;
+            task();
+        }
+    });
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
+    let health = Arc::new(Health::default());
     let cache = Arc::new(ArtCache::new(128));
     let config = Arc::new(Config::load());
+    let links = Arc::new(ShortLinks::load(config.data_dir.join("shortlinks.json")));
+    let redirects = Redirects::new(&config.redirects, config.data_dir.join("redirects.toml"))
+        .unwrap_or_else(|e| panic!("{}", e));
+    let redirects = Arc::new(redirects);
+    every(RELOAD_INTERVAL, {
+        let redirects = redirects.clone();
+        move || redirects.reload()
+    });
+    every(FLUSH_INTERVAL, {
+        let links = links.clone();
+        move || {
+            if let Err(e) = links.flush() {
+                log::warn!("Failed to save short links: {}", e);
+            }
+        }
+    });
+    let who = Arc::new(Who::new(&config.who).unwrap_or_else(|e| panic!("{}", e)));
 
+    let users = Users::load(config.data_dir.join("users.toml"));
+    if let (true, Some(username), Some(password)) =
+        (users.is_empty(), &config.username, &config.password)
+    {
+        users
+            .add(username, password, Role::Admin)
+            .unwrap_or_else(|e| panic!("Could not create the admin account: {}", e));
+        log::info!(
+            "Created the admin account {:?}, its password can be removed from config.toml",
+            username
+        );
+    }
+    let users = Arc::new(users);
+    let throttle = Arc::new(LoginThrottle::default());
+    let audit = Arc::new(AuditLog::new(config.data_dir.join("audit.log")));
+    let tokens = Arc::new(ApiTokens::load(config.data_dir.join("tokens.json")));
+    let upload_index = Arc::new(UploadIndex::load(config.data_dir.join("uploads.json")));
+
+    let key_file = config.data_dir.join(&config.session.key_file);
+    let key = services::session_key(&key_file)
+        .unwrap_or_else(|e| panic!("Could not load the session key: {}", e));
+
+    let content = Content::new(
+        &config.site_url,
+        &config.collections,
+        config.content_dir.as_deref(),
+    )
+    .unwrap_or_else(|e| panic!("{}", e));
+    let content = Arc::new(content);
+
+    // Crawlers are kept out of the hidden services and the collections that aren't public
+    let hidden = config
+        .collections
+        .iter()
+        .filter(|collection| collection.visibility != Visibility::Public)
+        .map(|collection| collection.prefix().trim_start_matches('/'))
+        .chain(components::HIDDEN_SERVICES.iter().copied())
+        .collect::<Vec<_>>();
+    let robots = components::robots(&hidden, &format!("{}/sitemap.xml", config.site_url));
+
+    let flushed = links.clone();
     let app = HttpServer::new(move || {
         App::new()
             .app_data(Data::new(config.clone()))
+            .app_data(Data::new(health.clone()))
             .app_data(Data::new(cache.clone()))
+            .app_data(Data::new(links.clone()))
+            .app_data(Data::new(redirects.clone()))
+            .app_data(Data::new(who.clone()))
+            .app_data(Data::new(users.clone()))
+            .app_data(Data::new(throttle.clone()))
+            .app_data(Data::new(audit.clone()))
+            .app_data(Data::new(tokens.clone()))
+            .app_data(Data::new(upload_index.clone()))
+            .app_data(Data::new(content.clone()))
+            .wrap(services::error_handlers())
+            .wrap(config.session.identity_middleware())
+            .wrap(config.session.session_middleware(key.clone()))
+            .wrap(from_fn(services::redirect_rules))
+            .wrap(from_fn(services::log_visits))
+            .wrap(from_fn(services::count_requests))
             .wrap(Logger::default())
             .wrap(Compress::default())
             .service(services::baked_files())
-            .service(services::markdown_service())
+            .service(services::content_service(&content))
             .service(services::user_service())
             .service(services::file_service())
             .service(services::autopixel_service())
-            .service(
-                actix_web::web::resource("/robots.txt")
-                    .to(|| async { components::robots(&["t", "r", "u", "f", "w"]) }),
-            )
+            .service(services::shortener_service())
+            .service(services::who_service())
+            .service(services::admin_service())
+            .service(actix_web::web::resource("/robots.txt").to({
+                let robots = robots.clone();
+                move || {
+                    let robots = robots.clone();
+                    async move { robots }
+                }
+            }))
+            .service(actix_web::web::resource("/sitemap.xml").to(services::sitemap_handler))
             .service(redirect("/", "/m/home.md"))
     });
 
-    app.bind("0.0.0.0:8080")?.run().await
+    app.bind("0.0.0.0:8080")?.run().await?;
+
+    // Keep the hits counted since the last flush
+    flushed.flush()
 }
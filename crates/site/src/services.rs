@@ -1,11 +1,34 @@
+mod accounts;
+mod admin;
+mod audit;
 mod autopixel;
 mod baked;
+mod csrf;
+mod errors;
 mod files;
 mod markdown;
+mod redirects;
+mod sessions;
+mod shortener;
+mod store;
+mod throttle;
+mod tokens;
+mod two_factor;
 mod users;
+mod who;
 
+pub use accounts::{Account, Role, SecondFactor, User, Users};
+pub use admin::{admin_service, count_requests, Health};
+pub use audit::{AuditEntry, AuditEvent, AuditLog};
 pub use autopixel::{autopixel_service, ArtCache};
 pub use baked::baked_files;
-pub use files::file_service;
-pub use markdown::markdown_service;
+pub use errors::error_handlers;
+pub use files::{file_service, UploadIndex, UploadInfo};
+pub use markdown::{content_service, markdown_service, sitemap_handler, Content};
+pub use redirects::{redirect_rules, Redirects};
+pub use sessions::session_key;
+pub use shortener::{shortener_service, ShortLink, ShortLinks};
+pub use throttle::LoginThrottle;
+pub use tokens::{ApiToken, ApiTokens, Scope};
 pub use users::user_service;
+pub use who::{log_visits, who_service, Location, Visit, Who};
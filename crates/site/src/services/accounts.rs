@@ -0,0 +1,569 @@
+//! User accounts and their roles.
+//!
+//! Accounts are kept in `users.toml` in the data directory, with Argon2 hashed passwords:
+//!
+//! ```toml
+//! [[users]]
+//! username = "alex"
+//! password = "$argon2id$v=19$m=19456,t=2,p=1$..."
+//! role = "admin"
+//! ```
+//!
+//! They're managed by the admin from `/a/`. While there are none, the `username` and `password`
+//! of `config.toml` are used to create the first admin.
+//!
+//! Accounts with [two-factor authentication](super::two_factor) also keep their TOTP secret and the
+//! hashes of their recovery codes there.
+//!
+//! Handlers take an `Option<Account>` to find out who's logged in, the session only holds the
+//! username so deleted accounts and role changes take effect right away. Scripts authenticate
+//! the same way with an [API token](super::tokens).
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    future::{ready, Ready},
+    io,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use actix_identity::IdentityExt;
+use actix_web::{
+    dev::Payload,
+    error::{ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized},
+    http::header,
+    web, FromRequest, HttpRequest, HttpResponse,
+};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::services::{
+    store,
+    two_factor::{check_code, normalize_recovery_code, TwoFactor},
+    ApiTokens,
+};
+
+/// What an account is allowed to do, each role can do everything the previous ones can
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Can read drafts, scheduled pages and private collections
+    #[default]
+    Viewer,
+    /// Can upload files
+    Uploader,
+    /// Can manage the site and its accounts
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Viewer, Role::Uploader, Role::Admin];
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Viewer => write!(f, "viewer"),
+            Role::Uploader => write!(f, "uploader"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// An account as saved in `users.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub username: String,
+    /// The Argon2 hash of the password, in PHC format
+    password: String,
+    pub role: Role,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    two_factor: Option<TwoFactor>,
+}
+
+impl User {
+    /// Whether logging in needs a code after the password
+    pub fn has_two_factor(&self) -> bool {
+        self.two_factor.is_some()
+    }
+}
+
+/// How a second factor was proven
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecondFactor {
+    /// A code from the authenticator app
+    Code,
+    /// A recovery code, which can't be used again
+    RecoveryCode,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct UsersFile {
+    #[serde(default)]
+    users: Vec<User>,
+}
+
+/// Every account, keyed by username
+pub struct Users {
+    path: PathBuf,
+    users: RwLock<BTreeMap<String, User>>,
+}
+
+impl Users {
+    /// Loads the accounts saved at `path`, starting without any if the file doesn't exist yet
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let users = store::load::<UsersFile>(&path).users;
+
+        Users {
+            path,
+            users: RwLock::new(
+                users
+                    .into_iter()
+                    .map(|user| (user.username.clone(), user))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Writes the accounts to disk, replacing the file at once so a crash can't truncate it
+    fn save(&self, users: &BTreeMap<String, User>) -> io::Result<()> {
+        let file = UsersFile {
+            users: users.values().cloned().collect(),
+        };
+        store::save(&self.path, &file)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.read().unwrap().is_empty()
+    }
+
+    /// Creates an account
+    pub fn add(&self, username: &str, password: &str, role: Role) -> actix_web::Result<()> {
+        if !valid_username(username) {
+            return Err(ErrorBadRequest(
+                "Usernames may only contain letters, numbers, '-', '_' and '.'",
+            ));
+        }
+        if password.is_empty() {
+            return Err(ErrorBadRequest("The password can't be empty"));
+        }
+
+        let user = User {
+            username: username.to_string(),
+            password: hash(password).map_err(ErrorInternalServerError)?,
+            role,
+            two_factor: None,
+        };
+
+        let mut users = self.users.write().unwrap();
+        if users.contains_key(username) {
+            return Err(ErrorBadRequest("Username already taken"));
+        }
+        users.insert(username.to_string(), user);
+        self.save(&users).map_err(ErrorInternalServerError)
+    }
+
+    /// Changes the role of an account, returning false if it doesn't exist
+    pub fn set_role(&self, username: &str, role: Role) -> io::Result<bool> {
+        let mut users = self.users.write().unwrap();
+        let Some(user) = users.get_mut(username) else {
+            return Ok(false);
+        };
+        user.role = role;
+
+        self.save(&users).map(|_| true)
+    }
+
+    /// Removes an account, returning false if it didn't exist
+    pub fn delete(&self, username: &str) -> io::Result<bool> {
+        let mut users = self.users.write().unwrap();
+        if users.remove(username).is_none() {
+            return Ok(false);
+        }
+
+        self.save(&users).map(|_| true)
+    }
+
+    /// Turns on two-factor authentication for an account with the TOTP `secret` and
+    /// `recovery_codes`, returning false if it doesn't exist
+    pub fn enable_two_factor(
+        &self,
+        username: &str,
+        secret: &str,
+        recovery_codes: &[String],
+    ) -> io::Result<bool> {
+        let recovery_codes = recovery_codes
+            .iter()
+            .map(|code| hash(&normalize_recovery_code(code)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| io::Error::other(e.to_string()))?;
+
+        let mut users = self.users.write().unwrap();
+        let Some(user) = users.get_mut(username) else {
+            return Ok(false);
+        };
+        user.two_factor = Some(TwoFactor {
+            secret: secret.to_string(),
+            recovery_codes,
+            last_step: 0,
+        });
+
+        self.save(&users).map(|_| true)
+    }
+
+    /// Turns off two-factor authentication for an account, returning false if it doesn't exist
+    pub fn disable_two_factor(&self, username: &str) -> io::Result<bool> {
+        let mut users = self.users.write().unwrap();
+        let Some(user) = users.get_mut(username) else {
+            return Ok(false);
+        };
+        user.two_factor = None;
+
+        self.save(&users).map(|_| true)
+    }
+
+    /// Checks the second factor of an account, either a code from the authenticator app or a
+    /// recovery code. Either can only be used once.
+    pub fn verify_code(&self, username: &str, code: &str) -> io::Result<Option<SecondFactor>> {
+        let Some(two_factor) = self.get(username).and_then(|user| user.two_factor) else {
+            return Ok(None);
+        };
+
+        if let Some(step) = check_code(&two_factor.secret, code) {
+            let mut users = self.users.write().unwrap();
+            let Some(two_factor) = users
+                .get_mut(username)
+                .and_then(|user| user.two_factor.as_mut())
+                .filter(|two_factor| step > two_factor.last_step)
+            else {
+                return Ok(None);
+            };
+            two_factor.last_step = step;
+
+            return self.save(&users).map(|_| Some(SecondFactor::Code));
+        }
+
+        // Hashing is slow, so only try the recovery codes with something that could be one
+        let code = normalize_recovery_code(code);
+        if code.len() != 10 {
+            return Ok(None);
+        }
+        let Some(used) = two_factor.recovery_codes.iter().find(|hash| {
+            PasswordHash::new(hash).is_ok_and(|hash| {
+                Argon2::default()
+                    .verify_password(code.as_bytes(), &hash)
+                    .is_ok()
+            })
+        }) else {
+            return Ok(None);
+        };
+
+        let mut users = self.users.write().unwrap();
+        let Some(two_factor) = users
+            .get_mut(username)
+            .and_then(|user| user.two_factor.as_mut())
+        else {
+            return Ok(None);
+        };
+        // Someone else may have used it in the meantime
+        let Some(index) = two_factor
+            .recovery_codes
+            .iter()
+            .position(|hash| hash == used)
+        else {
+            return Ok(None);
+        };
+        two_factor.recovery_codes.remove(index);
+
+        self.save(&users).map(|_| Some(SecondFactor::RecoveryCode))
+    }
+
+    pub fn get(&self, username: &str) -> Option<User> {
+        self.users.read().unwrap().get(username).cloned()
+    }
+
+    /// Every account, sorted by username
+    pub fn list(&self) -> Vec<User> {
+        self.users.read().unwrap().values().cloned().collect()
+    }
+
+    /// Returns the account if the password is right
+    pub fn verify(&self, username: &str, password: &str) -> Option<User> {
+        let Some(user) = self.get(username) else {
+            // Hash anyway, so unknown usernames take as long as wrong passwords
+            let _ = hash(password);
+            return None;
+        };
+
+        let hash = PasswordHash::new(&user.password).ok()?;
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .ok()
+            .map(|_| user)
+    }
+}
+
+fn hash(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+}
+
+fn valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username.len() <= 64
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// The account of the logged in user
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub username: String,
+    pub role: Role,
+    /// Authenticated with an API token rather than the session cookie. Browsers never send the
+    /// token on their own, so these requests don't need a CSRF token.
+    pub api_token: bool,
+}
+
+impl Account {
+    /// Checks `user` is logged in with at least `role`, for actions. Strangers get a 401 and
+    /// everyone else a 403.
+    pub fn require(user: Option<Account>, role: Role) -> actix_web::Result<Account> {
+        match user {
+            None => Err(ErrorUnauthorized("Authentication required")),
+            Some(user) if user.role < role => Err(ErrorForbidden("Not allowed")),
+            Some(user) => Ok(user),
+        }
+    }
+
+    /// Checks `user` is logged in with at least `role`, for the page at `path`. Strangers are
+    /// sent to log in and everyone else gets a 403.
+    pub fn require_page(
+        user: Option<Account>,
+        role: Role,
+        path: &str,
+    ) -> Result<Account, HttpResponse> {
+        match user {
+            None => Err(HttpResponse::Found()
+                .append_header(("location", format!("/u/?redirect={}", path)))
+                .finish()),
+            Some(user) if user.role < role => Err(HttpResponse::Forbidden().finish()),
+            Some(user) => Ok(user),
+        }
+    }
+}
+
+impl FromRequest for Account {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let account = || {
+            // Checked first, the identity can't be read without the middleware that comes with it
+            let users = req
+                .app_data::<web::Data<Arc<Users>>>()
+                .ok_or_else(|| ErrorInternalServerError("Accounts aren't available"))?;
+
+            let api_token = bearer_token(req);
+            let username = match api_token {
+                Some(token) => req
+                    .app_data::<web::Data<Arc<ApiTokens>>>()
+                    .ok_or_else(|| ErrorUnauthorized("API tokens aren't available"))?
+                    .authenticate(token, req.path())?,
+                None => {
+                    let identity = req.get_identity().map_err(ErrorUnauthorized)?;
+                    identity.id().map_err(ErrorUnauthorized)?
+                }
+            };
+
+            // The account may have been deleted since logging in
+            let user = users
+                .get(&username)
+                .ok_or_else(|| ErrorUnauthorized("Unknown account"))?;
+
+            Ok(Account {
+                username: user.username,
+                role: user.role,
+                api_token: api_token.is_some(),
+            })
+        };
+
+        ready(account())
+    }
+}
+
+/// The token of an `Authorization: Bearer` header
+pub(crate) fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    let header = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = header.split_once(' ')?;
+
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use actix_identity::IdentityMiddleware;
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::{cookie::Key, test, App};
+
+    use super::*;
+    use crate::services::{two_factor, Scope};
+
+    #[actix_web::test]
+    async fn test_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data/users.toml");
+        let users = Users::load(&path);
+        assert!(users.is_empty());
+
+        users.add("alex", "hunter2", Role::Admin).unwrap();
+        users.add("guest", "guest", Role::Viewer).unwrap();
+        assert!(users.add("alex", "again", Role::Viewer).is_err());
+        assert!(users.add("a b", "password", Role::Viewer).is_err());
+        assert!(users.add("empty", "", Role::Viewer).is_err());
+
+        // Passwords are never saved as they are
+        let file = fs::read_to_string(&path).unwrap();
+        assert!(file.contains("$argon2id$") && !file.contains("hunter2"));
+
+        assert_eq!(users.verify("alex", "hunter2").unwrap().role, Role::Admin);
+        assert!(users.verify("alex", "hunter3").is_none());
+        assert!(users.verify("nobody", "hunter2").is_none());
+
+        assert!(users.set_role("guest", Role::Uploader).unwrap());
+        assert!(users.delete("alex").unwrap());
+        assert!(!users.delete("alex").unwrap());
+
+        let list = users
+            .list()
+            .into_iter()
+            .map(|user| (user.username, user.role))
+            .collect::<Vec<_>>();
+        assert_eq!(list, [("guest".to_string(), Role::Uploader)]);
+        assert!(users.verify("guest", "guest").is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_two_factor() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.toml");
+        let users = Users::load(&path);
+        users.add("alex", "hunter2", Role::Admin).unwrap();
+        assert!(!users.get("alex").unwrap().has_two_factor());
+        assert_eq!(users.verify_code("alex", "123456").unwrap(), None);
+
+        let secret = two_factor::new_secret();
+        let codes = ["abcde-12345".to_string(), "fghij-67890".to_string()];
+        assert!(users.enable_two_factor("alex", &secret, &codes).unwrap());
+        assert!(!users.enable_two_factor("nobody", &secret, &codes).unwrap());
+        let file = fs::read_to_string(&path).unwrap();
+        assert!(file.contains(&secret) && !file.contains("abcde"));
+
+        // Codes only work once
+        let users = Users::load(&path);
+        assert!(users.get("alex").unwrap().has_two_factor());
+        let code = two_factor::current_code(&secret);
+        assert_eq!(
+            users.verify_code("alex", &code).unwrap(),
+            Some(SecondFactor::Code)
+        );
+        assert_eq!(users.verify_code("alex", &code).unwrap(), None);
+        assert_eq!(
+            users.verify_code("alex", "ABCDE12345").unwrap(),
+            Some(SecondFactor::RecoveryCode)
+        );
+        assert_eq!(users.verify_code("alex", "abcde-12345").unwrap(), None);
+
+        assert!(users.disable_two_factor("alex").unwrap());
+        assert!(!Users::load(&path).get("alex").unwrap().has_two_factor());
+    }
+
+    #[actix_web::test]
+    async fn test_api_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let users = Users::load(dir.path().join("users.toml"));
+        users.add("alex", "hunter2", Role::Uploader).unwrap();
+        let tokens = ApiTokens::load(dir.path().join("tokens.json"));
+        let token = tokens
+            .create("backup", "alex", vec![Scope::Files], None)
+            .unwrap();
+        let orphan = tokens
+            .create("orphan", "nobody", vec![Scope::Files], None)
+            .unwrap();
+
+        let whoami = |user: Option<Account>| async move {
+            let user = Account::require(user, Role::Uploader)?;
+            Ok::<_, actix_web::Error>(format!("{} {}", user.username, user.api_token))
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(users)))
+                .app_data(web::Data::new(Arc::new(tokens)))
+                .wrap(IdentityMiddleware::default())
+                .wrap(SessionMiddleware::new(
+                    CookieSessionStore::default(),
+                    Key::generate(),
+                ))
+                .route("/f/", web::post().to(whoami))
+                .route("/t/", web::post().to(whoami)),
+        )
+        .await;
+
+        let call = |uri: &str, authorization: &str| {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .insert_header((header::AUTHORIZATION, authorization.to_string()))
+                .to_request();
+            test::call_service(&app, req)
+        };
+
+        let res = call("/f/", &format!("Bearer {}", token)).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(test::read_body(res).await, "alex true");
+        let res = call("/f/", &format!("bearer  {}", token)).await;
+        assert_eq!(res.status(), 200);
+
+        // Out of scope, the wrong scheme, and for an account that's gone
+        assert_eq!(
+            call("/t/", &format!("Bearer {}", token)).await.status(),
+            401
+        );
+        assert_eq!(call("/f/", &format!("Basic {}", token)).await.status(), 401);
+        assert_eq!(
+            call("/f/", &format!("Bearer {}", orphan)).await.status(),
+            401
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_require() {
+        let user = |role| {
+            Some(Account {
+                username: "alex".to_string(),
+                role,
+                api_token: false,
+            })
+        };
+
+        assert!(Account::require(user(Role::Admin), Role::Uploader).is_ok());
+        let forbidden = Account::require(user(Role::Viewer), Role::Uploader).unwrap_err();
+        assert_eq!(forbidden.as_response_error().status_code(), 403);
+        let stranger = Account::require(None, Role::Viewer).unwrap_err();
+        assert_eq!(stranger.as_response_error().status_code(), 401);
+
+        let res = Account::require_page(None, Role::Admin, "/a/").unwrap_err();
+        assert_eq!(res.headers().get("location").unwrap(), "/u/?redirect=/a/");
+        let res = Account::require_page(user(Role::Uploader), Role::Admin, "/a/").unwrap_err();
+        assert_eq!(res.status(), 403);
+    }
+}
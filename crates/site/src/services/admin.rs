@@ -0,0 +1,870 @@
+//! The admin panel, a dashboard of every service.
+//!
+//! Uploaded files can be renamed and deleted, cached pixel art purged, the content reloaded,
+//! short links deleted and API tokens issued, next to the health of the server.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use actix_session::Session;
+use actix_web::{
+    body::MessageBody,
+    dev::{HttpServiceFactory, ServiceRequest, ServiceResponse},
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    get,
+    middleware::{from_fn, Next},
+    post,
+    web::{self, redirect},
+    Either, HttpResponse, Responder,
+};
+use chrono::{DateTime, Duration, Utc};
+use maud::{html, Markup, DOCTYPE};
+use serde::Deserialize;
+
+use crate::{
+    components::bytes,
+    services::{
+        csrf,
+        files::{dir_link, file_link, uploads, UPLOADS},
+        Account, ApiTokens, ArtCache, AuditLog, Content, Role, Scope, ShortLinks, Users,
+    },
+};
+
+/// Uptime and request counts of the server
+pub struct Health {
+    started: DateTime<Utc>,
+    requests: AtomicU64,
+    client_errors: AtomicU64,
+    server_errors: AtomicU64,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            started: Utc::now(),
+            requests: AtomicU64::new(0),
+            client_errors: AtomicU64::new(0),
+            server_errors: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Health {
+    /// Counts a response with the status `status`
+    fn count(&self, status: u16) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        match status {
+            400..=499 => self.client_errors.fetch_add(1, Ordering::Relaxed),
+            500..=599 => self.server_errors.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+    }
+
+    /// How many requests were answered, and how many of them with 4xx and 5xx errors
+    pub fn requests(&self) -> (u64, u64, u64) {
+        (
+            self.requests.load(Ordering::Relaxed),
+            self.client_errors.load(Ordering::Relaxed),
+            self.server_errors.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Middleware counting the requests answered by the server
+pub async fn count_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> actix_web::Result<ServiceResponse<impl MessageBody>> {
+    let health = req.app_data::<web::Data<Arc<Health>>>().cloned();
+    let res = next.call(req).await?;
+    if let Some(health) = health {
+        health.count(res.status().as_u16());
+    }
+
+    Ok(res)
+}
+
+/// The resident memory of the process, only known on Linux
+fn memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+
+    Some(kilobytes * 1024)
+}
+
+/// Formats a duration as days, hours and minutes, `3d 4h 5m`
+fn uptime(started: DateTime<Utc>) -> String {
+    let minutes = (Utc::now() - started).num_minutes();
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    match days {
+        0 => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes),
+    }
+}
+
+/// Takes the admin back to the panel after an action
+fn back() -> HttpResponse {
+    HttpResponse::SeeOther()
+        .append_header(("location", "/a/"))
+        .finish()
+}
+
+pub fn admin_service() -> impl HttpServiceFactory {
+    web::scope("/a")
+        .wrap(from_fn(csrf::protect))
+        .service(redirect("", "/a/"))
+        .service(index)
+        .service(purge_sketches)
+        .service(remove_sketch)
+        .service(reload_content)
+        .service(delete_link)
+        .service(add_user)
+        .service(set_role)
+        .service(delete_user)
+        .service(reset_two_factor)
+        .service(create_token)
+        .service(revoke_token)
+}
+
+/// The stores listed on the admin page, extracted together to keep the handler readable
+type Listed = (
+    web::Data<Arc<ShortLinks>>,
+    web::Data<Arc<Users>>,
+    web::Data<Arc<ApiTokens>>,
+);
+
+#[get("/")]
+async fn index(
+    user: Option<Account>,
+    session: Session,
+    health: web::Data<Arc<Health>>,
+    cache: web::Data<Arc<ArtCache>>,
+    content: web::Data<Arc<Content>>,
+    audit: web::Data<Arc<AuditLog>>,
+    (links, users, tokens): Listed,
+) -> actix_web::Result<Either<HttpResponse, Markup>> {
+    // 302 redirect to login if not authenticated
+    let user = match Account::require_page(user, Role::Admin, "/a/") {
+        Ok(user) => user,
+        Err(res) => return Ok(Either::Left(res)),
+    };
+    let token = csrf::token(&session)?;
+
+    let (requests, client_errors, server_errors) = health.requests();
+    let sketches = cache.hashes();
+
+    Ok(Either::Right(html! {
+        (DOCTYPE)
+        (blog::header("Admin"))
+        main {
+            h1 { "Admin" }
+
+            h2 { "Health" }
+            table {
+                tr { td { "Started" } td { (health.started.format("%Y-%m-%d %H:%M UTC")) } }
+                tr { td { "Uptime" } td { (uptime(health.started)) } }
+                tr { td { "Memory" } td { @match memory() { Some(memory) => (bytes(memory)), None => "Unknown" } } }
+                tr { td { "Requests" } td { (requests) } }
+                tr { td { "Client errors (4xx)" } td { (client_errors) } }
+                tr { td { "Server errors (5xx)" } td { (server_errors) } }
+            }
+
+            h2 { "Content" }
+            table {
+                thead {
+                    tr { th { "Collection" } th { "Prefix" } th { "Visibility" } th { "Published pages" } }
+                }
+                tbody {
+                    @for blog in content.collections().iter() {
+                        tr {
+                            td { (blog.collection().name) }
+                            td { a href=(blog.uri("home.md")) { (blog.collection().prefix()) } }
+                            td { (format!("{:?}", blog.collection().visibility)) }
+                            td { (blog.published().len()) }
+                        }
+                    }
+                }
+            }
+            form method="post" action="/a/content/reload" {
+                (csrf::field(&token))
+                button { "Reload content" }
+            }
+
+            h2 { "Files" }
+            @match uploads(Path::new(UPLOADS)) {
+                Ok(files) => table {
+                    thead {
+                        tr { th { "Name" } th { "Size" } th { "Modified" } th {} th {} }
+                    }
+                    tbody {
+                        @for file in files {
+                            tr {
+                                td {
+                                    @if file.is_dir {
+                                        a href=(dir_link(&file.name)) { (file.name) "/" }
+                                    } @else {
+                                        a href=(file_link(&file.name)) { (file.name) }
+                                    }
+                                }
+                                td { @if !file.is_dir { (bytes(file.size)) } }
+                                td { @if let Some(modified) = file.modified { (modified.format("%Y-%m-%d %H:%M")) } }
+                                td {
+                                    form method="post" action="/f/rename" {
+                                        (csrf::field(&token))
+                                        input type="hidden" name="redirect" value="/a/";
+                                        input type="hidden" name="path" value=(file.name);
+                                        input type="text" name="to" value=(file.name) required;
+                                        button { "Rename" }
+                                    }
+                                }
+                                td {
+                                    form method="post" action="/f/delete" {
+                                        (csrf::field(&token))
+                                        input type="hidden" name="redirect" value="/a/";
+                                        input type="hidden" name="path" value=(file.name);
+                                        button { "Delete" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+                Err(e) => p { "Could not list the uploads: " (e) },
+            }
+            p { a href="/f/" { "Upload files" } }
+
+            h2 { "Pixel art" }
+            p { (sketches.len()) " cached sketches" }
+            div class="gallery-images" {
+                @for hash in &sketches {
+                    figure {
+                        a href=(format!("/pixel/sketches/{:x}", hash)) {
+                            img src=(format!("/pixel/sketches/{:x}.png", hash)) alt="Cached sketch";
+                        }
+                        form method="post" action=(format!("/a/pixel/{:x}/remove", hash)) {
+                            (csrf::field(&token))
+                            button { "Remove" }
+                        }
+                    }
+                }
+            }
+            @if !sketches.is_empty() {
+                form method="post" action="/a/pixel/purge" {
+                    (csrf::field(&token))
+                    button { "Purge the cache" }
+                }
+            }
+
+            h2 { "Short links" }
+            table {
+                thead {
+                    tr { th { "Code" } th { "URL" } th { "Hits" } th {} }
+                }
+                tbody {
+                    @for (code, link) in links.list() {
+                        tr {
+                            td { a href=(format!("/t/{}", code)) { (code) } }
+                            td { (link.url) }
+                            td { (link.hits) }
+                            td {
+                                form method="post" action=(format!("/a/links/{}/delete", code)) {
+                                    (csrf::field(&token))
+                                    button { "Delete" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            p { a href="/t/" { "Create short links" } }
+
+            h2 { "Users" }
+            table {
+                thead {
+                    tr { th { "Username" } th { "Role" } th { "Two-factor" } th {} }
+                }
+                tbody {
+                    @for account in users.list() {
+                        tr {
+                            td { (account.username) }
+                            td {
+                                form method="post" action=(format!("/a/users/{}/role", account.username)) {
+                                    (csrf::field(&token))
+                                    select name="role" {
+                                        @for role in Role::ALL {
+                                            option value=(role) selected[role == account.role] { (role) }
+                                        }
+                                    }
+                                    button disabled[account.username == user.username] { "Change" }
+                                }
+                            }
+                            td {
+                                @if account.has_two_factor() {
+                                    form method="post" action=(format!("/a/users/{}/two-factor/reset", account.username)) {
+                                        (csrf::field(&token))
+                                        button { "Reset" }
+                                    }
+                                } @else {
+                                    "off"
+                                }
+                            }
+                            td {
+                                form method="post" action=(format!("/a/users/{}/delete", account.username)) {
+                                    (csrf::field(&token))
+                                    button disabled[account.username == user.username] { "Delete" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            form method="post" action="/a/users" {
+                (csrf::field(&token))
+                input type="text" name="username" placeholder="Username" required;
+                input type="password" name="password" placeholder="Password" required;
+                select name="role" {
+                    @for role in Role::ALL {
+                        option value=(role) { (role) }
+                    }
+                }
+                button { "Add user" }
+            }
+
+            h2 { "API tokens" }
+            table {
+                thead {
+                    tr {
+                        th { "Name" } th { "User" } th { "Scopes" } th { "Created" } th { "Expires" }
+                        th { "Last used" } th {}
+                    }
+                }
+                tbody {
+                    @for (id, api_token) in tokens.list() {
+                        tr {
+                            td { (api_token.name) }
+                            td { (api_token.username) }
+                            td {
+                                (api_token.scopes.iter().map(Scope::to_string).collect::<Vec<_>>().join(", "))
+                            }
+                            td { (api_token.created.format("%Y-%m-%d")) }
+                            td {
+                                @match api_token.expires {
+                                    Some(expires) if api_token.expired(Utc::now()) => { "expired " (expires.format("%Y-%m-%d")) }
+                                    Some(expires) => { (expires.format("%Y-%m-%d")) }
+                                    None => { "never" }
+                                }
+                            }
+                            td {
+                                @match api_token.last_used {
+                                    Some(last_used) => { (last_used.format("%Y-%m-%d %H:%M")) }
+                                    None => { "never" }
+                                }
+                            }
+                            td {
+                                form method="post" action=(format!("/a/tokens/{}/revoke", id)) {
+                                    (csrf::field(&token))
+                                    button { "Revoke" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            form method="post" action="/a/tokens" {
+                (csrf::field(&token))
+                input type="text" name="name" placeholder="Name" required;
+                select name="username" {
+                    @for account in users.list() {
+                        option value=(account.username) selected[account.username == user.username] { (account.username) }
+                    }
+                }
+                @for scope in Scope::ALL {
+                    label { input type="checkbox" name=(scope); " " (scope) }
+                }
+                input type="number" name="expires" min="1" placeholder="Days until it expires";
+                button { "Create token" }
+            }
+
+            h2 { "Recent logins" }
+            table {
+                thead {
+                    tr { th { "Time" } th { "Event" } th { "Username" } th { "IP" } }
+                }
+                tbody {
+                    @for entry in audit.recent(20) {
+                        tr {
+                            td { (entry.time.format("%Y-%m-%d %H:%M:%S")) }
+                            td { (entry.event) }
+                            td { (entry.username) }
+                            td {
+                                @if let Some(ip) = entry.ip { (ip) } @else { "unknown" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }))
+}
+
+#[post("/pixel/purge")]
+async fn purge_sketches(
+    user: Option<Account>,
+    cache: web::Data<Arc<ArtCache>>,
+) -> actix_web::Result<impl Responder> {
+    Account::require(user, Role::Admin)?;
+
+    cache.purge();
+    Ok(back())
+}
+
+#[post("/pixel/{hash}/remove")]
+async fn remove_sketch(
+    user: Option<Account>,
+    cache: web::Data<Arc<ArtCache>>,
+    hash: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+    Account::require(user, Role::Admin)?;
+
+    let hash = u64::from_str_radix(&hash, 16).map_err(|_| ErrorBadRequest("Invalid hash"))?;
+    if !cache.remove(hash) {
+        return Err(ErrorNotFound("No such sketch"));
+    }
+    Ok(back())
+}
+
+#[post("/content/reload")]
+async fn reload_content(
+    user: Option<Account>,
+    content: web::Data<Arc<Content>>,
+) -> actix_web::Result<impl Responder> {
+    Account::require(user, Role::Admin)?;
+
+    // Rendering every page takes a while, keep it off the worker
+    web::block(move || content.reload())
+        .await
+        .map_err(ErrorInternalServerError)?
+        .map_err(ErrorInternalServerError)?;
+    Ok(back())
+}
+
+#[post("/links/{code}/delete")]
+async fn delete_link(
+    user: Option<Account>,
+    links: web::Data<Arc<ShortLinks>>,
+    code: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+    Account::require(user, Role::Admin)?;
+
+    if !links.delete(&code).map_err(ErrorInternalServerError)? {
+        return Err(ErrorNotFound("No such link"));
+    }
+    Ok(back())
+}
+
+#[derive(Debug, Deserialize)]
+struct UserForm {
+    username: String,
+    password: String,
+    role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+struct RoleForm {
+    role: Role,
+}
+
+#[post("/users")]
+async fn add_user(
+    user: Option<Account>,
+    users: web::Data<Arc<Users>>,
+    form: web::Form<UserForm>,
+) -> actix_web::Result<impl Responder> {
+    Account::require(user, Role::Admin)?;
+
+    users.add(form.username.trim(), &form.password, form.role)?;
+    Ok(back())
+}
+
+#[post("/users/{username}/role")]
+async fn set_role(
+    user: Option<Account>,
+    users: web::Data<Arc<Users>>,
+    username: web::Path<String>,
+    form: web::Form<RoleForm>,
+) -> actix_web::Result<impl Responder> {
+    let user = Account::require(user, Role::Admin)?;
+
+    // Admins can't lock themselves out
+    if user.username == *username {
+        return Err(ErrorBadRequest("You can't change your own role"));
+    }
+    if !users
+        .set_role(&username, form.role)
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorNotFound("No such user"));
+    }
+    Ok(back())
+}
+
+#[post("/users/{username}/delete")]
+async fn delete_user(
+    user: Option<Account>,
+    users: web::Data<Arc<Users>>,
+    tokens: web::Data<Arc<ApiTokens>>,
+    username: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+    let user = Account::require(user, Role::Admin)?;
+
+    if user.username == *username {
+        return Err(ErrorBadRequest("You can't delete yourself"));
+    }
+    if !users.delete(&username).map_err(ErrorInternalServerError)? {
+        return Err(ErrorNotFound("No such user"));
+    }
+    // A new account with the same name mustn't inherit them
+    tokens
+        .revoke_all(&username)
+        .map_err(ErrorInternalServerError)?;
+    Ok(back())
+}
+
+/// Turns off two-factor authentication for someone who lost their phone and recovery codes
+#[post("/users/{username}/two-factor/reset")]
+async fn reset_two_factor(
+    user: Option<Account>,
+    users: web::Data<Arc<Users>>,
+    username: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+    Account::require(user, Role::Admin)?;
+
+    if !users
+        .disable_two_factor(&username)
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorNotFound("No such user"));
+    }
+    Ok(back())
+}
+
+/// Issues an API token, showing it the only time it can be seen. The form has the `name` and
+/// `username`, a field named after each scope to give it and optionally the days until it expires.
+#[post("/tokens")]
+async fn create_token(
+    user: Option<Account>,
+    users: web::Data<Arc<Users>>,
+    tokens: web::Data<Arc<ApiTokens>>,
+    form: web::Form<HashMap<String, String>>,
+) -> actix_web::Result<Markup> {
+    Account::require(user, Role::Admin)?;
+
+    let field = |name| form.get(name).map(|value| value.trim()).unwrap_or_default();
+    let username = field("username");
+    if users.get(username).is_none() {
+        return Err(ErrorBadRequest("No such user"));
+    }
+    let scopes = Scope::ALL
+        .into_iter()
+        .filter(|scope| form.contains_key(&scope.to_string()))
+        .collect();
+    let expires = match field("expires") {
+        "" => None,
+        days => {
+            let expires = days
+                .parse()
+                .ok()
+                .filter(|&days| days > 0)
+                .and_then(Duration::try_days)
+                .and_then(|days| Utc::now().checked_add_signed(days))
+                .ok_or_else(|| ErrorBadRequest("Expiry must be a number of days"))?;
+            Some(expires)
+        }
+    };
+
+    let token = tokens.create(field("name"), username, scopes, expires)?;
+
+    Ok(html! {
+        (DOCTYPE)
+        (blog::header("Admin"))
+        main {
+            h1 { "API token created" }
+            p { "Copy it now, it won't be shown again. Send it in an " code { "Authorization: Bearer" } " header." }
+            p { code { (token) } }
+            p { a href="/a/" { "Back" } }
+        }
+    })
+}
+
+#[post("/tokens/{id}/revoke")]
+async fn revoke_token(
+    user: Option<Account>,
+    tokens: web::Data<Arc<ApiTokens>>,
+    id: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+    Account::require(user, Role::Admin)?;
+
+    if !tokens.revoke(&id).map_err(ErrorInternalServerError)? {
+        return Err(ErrorNotFound("No such token"));
+    }
+    Ok(back())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_identity::{Identity, IdentityMiddleware};
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::{
+        cookie::Key, middleware::from_fn, test, App, HttpMessage, HttpRequest, HttpResponse,
+    };
+
+    use super::*;
+    use crate::services::{csrf::tests::csrf_session, AuditEvent, ShortLink};
+
+    #[actix_web::test]
+    async fn test_admin_panel() {
+        let dir = tempfile::tempdir().unwrap();
+        let health = Arc::new(Health::default());
+        let cache = Arc::new(ArtCache::new(4));
+        cache.insert(0xabc, String::new(), vec![]);
+        let links = Arc::new(ShortLinks::load(dir.path().join("shortlinks.json")));
+        links
+            .create(Some("gh"), ShortLink::new("https://github.com"))
+            .unwrap();
+        let users = Arc::new(Users::load(dir.path().join("users.toml")));
+        users.add("alex", "password", Role::Admin).unwrap();
+        users.add("guest", "password", Role::Viewer).unwrap();
+        let audit = Arc::new(AuditLog::new(dir.path().join("audit.log")));
+        audit.record(AuditEvent::LoginFailed, "mallory", None);
+        let tokens = Arc::new(ApiTokens::load(dir.path().join("tokens.json")));
+        tokens
+            .create("feed", "guest", vec![Scope::Pixel], None)
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(health.clone()))
+                .app_data(web::Data::new(cache.clone()))
+                .app_data(web::Data::new(links.clone()))
+                .app_data(web::Data::new(users.clone()))
+                .app_data(web::Data::new(audit.clone()))
+                .app_data(web::Data::new(tokens.clone()))
+                .app_data(web::Data::new(Arc::new(Content::baked())))
+                .wrap(IdentityMiddleware::default())
+                .wrap(SessionMiddleware::new(
+                    CookieSessionStore::default(),
+                    Key::generate(),
+                ))
+                .wrap(from_fn(count_requests))
+                .service(admin_service())
+                .route(
+                    "/login/{username}",
+                    web::get().to(|req: HttpRequest, username: web::Path<String>| async move {
+                        Identity::login(&req.extensions(), username.into_inner()).unwrap();
+                        HttpResponse::Ok().finish()
+                    }),
+                )
+                .route(
+                    "/fail",
+                    web::get().to(|| async { HttpResponse::InternalServerError().finish() }),
+                ),
+        )
+        .await;
+
+        let login = |username: &str| {
+            let req = test::TestRequest::get()
+                .uri(&format!("/login/{}", username))
+                .to_request();
+            let app = &app;
+            async move {
+                let res = test::call_service(app, req).await;
+                res.response().cookies().next().unwrap().into_owned()
+            }
+        };
+
+        // Strangers are sent to log in, and can't do anything without a session
+        let req = test::TestRequest::get().uri("/a/").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 302);
+        assert_eq!(res.headers().get("location").unwrap(), "/u/?redirect=/a/");
+        for uri in ["/a/pixel/purge", "/a/links/gh/delete", "/a/content/reload"] {
+            let req = test::TestRequest::post().uri(uri).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 403, "{}", uri);
+        }
+
+        // Neither can other users
+        let guest = login("guest").await;
+        let req = test::TestRequest::get()
+            .uri("/a/")
+            .cookie(guest.clone())
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+        let req = test::TestRequest::post()
+            .uri("/a/pixel/purge")
+            .cookie(guest)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+        let cookie = login("alex").await;
+        let req = test::TestRequest::get()
+            .uri("/a/")
+            .cookie(cookie.clone())
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("Uptime"));
+        assert!(body.contains(r#"src="/pixel/sketches/abc.png""#));
+        assert!(body.contains("https://github.com"));
+        assert!(body.contains("guest"));
+        assert!(body.contains("mallory"));
+
+        // Forms send back the token of the session
+        let req = test::TestRequest::get()
+            .uri("/a/")
+            .cookie(cookie)
+            .to_request();
+        let (cookie, token) = csrf_session(test::call_service(&app, req).await).await;
+        let req = test::TestRequest::post()
+            .uri("/a/pixel/purge")
+            .cookie(cookie.clone())
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+        for uri in [
+            "/a/pixel/abc/remove",
+            "/a/links/gh/delete",
+            "/a/users/guest/delete",
+        ] {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .cookie(cookie.clone())
+                .insert_header((csrf::HEADER, token.as_str()))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 303, "{}", uri);
+        }
+        assert!(cache.hashes().is_empty());
+        assert!(links.list().is_empty());
+        assert!(users.get("guest").is_none());
+        assert!(tokens.list().is_empty());
+
+        let req = test::TestRequest::post()
+            .uri("/a/pixel/abc/remove")
+            .cookie(cookie.clone())
+            .insert_header((csrf::HEADER, token.as_str()))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+        // Users are added with a role, and admins can't lock themselves out
+        let req = test::TestRequest::post()
+            .uri("/a/users")
+            .cookie(cookie.clone())
+            .insert_header((csrf::HEADER, token.as_str()))
+            .set_form([
+                ("username", "sam"),
+                ("password", "secret"),
+                ("role", "uploader"),
+            ])
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 303);
+        assert_eq!(users.verify("sam", "secret").unwrap().role, Role::Uploader);
+
+        let req = test::TestRequest::post()
+            .uri("/a/users/alex/role")
+            .cookie(cookie.clone())
+            .insert_header((csrf::HEADER, token.as_str()))
+            .set_form([("role", "viewer")])
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+        let req = test::TestRequest::post()
+            .uri("/a/users/alex/delete")
+            .cookie(cookie.clone())
+            .insert_header((csrf::HEADER, token.as_str()))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+        assert_eq!(users.get("alex").unwrap().role, Role::Admin);
+
+        users
+            .enable_two_factor("sam", "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP", &[])
+            .unwrap();
+        let req = test::TestRequest::post()
+            .uri("/a/users/sam/two-factor/reset")
+            .cookie(cookie.clone())
+            .insert_header((csrf::HEADER, token.as_str()))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 303);
+        assert!(!users.get("sam").unwrap().has_two_factor());
+
+        // Tokens are shown once, then only listed
+        let req = test::TestRequest::post()
+            .uri("/a/tokens")
+            .cookie(cookie.clone())
+            .insert_header((csrf::HEADER, token.as_str()))
+            .set_form([
+                ("name", "backup"),
+                ("username", "sam"),
+                ("files", "on"),
+                ("expires", "30"),
+            ])
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert!(String::from_utf8_lossy(&body).contains("<code>mb_"));
+        let (id, api_token) = tokens.list().pop().unwrap();
+        assert_eq!(
+            (api_token.username.as_str(), api_token.scopes),
+            ("sam", vec![Scope::Files])
+        );
+        assert!(api_token.expires.is_some());
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/a/tokens/{}/revoke", id))
+            .cookie(cookie)
+            .insert_header((csrf::HEADER, token.as_str()))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 303);
+        assert!(tokens.list().is_empty());
+
+        // Every answer is counted, errors by whose fault they are
+        for (uri, counted) in [
+            ("/login/alex", (1, 0, 0)),
+            ("/nothing", (1, 1, 0)),
+            ("/fail", (1, 0, 1)),
+        ] {
+            let (requests, client_errors, server_errors) = health.requests();
+            let req = test::TestRequest::get().uri(uri).to_request();
+            test::call_service(&app, req).await;
+            let (after, after_client, after_server) = health.requests();
+            assert_eq!(
+                (
+                    after - requests,
+                    after_client - client_errors,
+                    after_server - server_errors
+                ),
+                counted,
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_formatting() {
+        assert_eq!(bytes(512), "512 B");
+        assert_eq!(bytes(1536), "1.5 KiB");
+        assert_eq!(bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+        assert_eq!(uptime(Utc::now() - chrono::Duration::minutes(61)), "1h 1m");
+    }
+}
@@ -0,0 +1,173 @@
+//! Audit log of logins.
+//!
+//! Logins, failed attempts, throttled attempts, logouts and two-factor changes are appended to
+//! `audit.log` in the data directory as one JSON object per line, and the latest are kept in memory
+//! for the admin panel.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    net::IpAddr,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+/// How many of the latest entries are kept in memory
+const TAIL_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    Login,
+    LoginFailed,
+    /// An attempt refused without checking the password, see [`LoginThrottle`](super::LoginThrottle)
+    Throttled,
+    /// The password was right but the second factor wasn't
+    SecondFactorFailed,
+    RecoveryCodeUsed,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    Logout,
+}
+
+impl std::fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditEvent::Login => write!(f, "Logged in"),
+            AuditEvent::LoginFailed => write!(f, "Wrong password"),
+            AuditEvent::Throttled => write!(f, "Throttled"),
+            AuditEvent::SecondFactorFailed => write!(f, "Wrong code"),
+            AuditEvent::RecoveryCodeUsed => write!(f, "Used a recovery code"),
+            AuditEvent::TwoFactorEnabled => write!(f, "Turned on two-factor"),
+            AuditEvent::TwoFactorDisabled => write!(f, "Turned off two-factor"),
+            AuditEvent::Logout => write!(f, "Logged out"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: DateTime<Utc>,
+    pub event: AuditEvent,
+    pub username: String,
+    pub ip: Option<IpAddr>,
+}
+
+/// The log file, appended to by every worker, and its latest entries
+pub struct AuditLog {
+    path: PathBuf,
+    tail: Mutex<VecDeque<AuditEntry>>,
+}
+
+impl AuditLog {
+    /// Opens the log at `path`, reading its latest entries if it exists
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let tail = match fs::read_to_string(&path) {
+            Ok(log) => {
+                let mut tail = log
+                    .lines()
+                    .rev()
+                    .filter_map(|line| serde_json::from_str(line).ok())
+                    .take(TAIL_SIZE)
+                    .collect::<VecDeque<_>>();
+                tail.make_contiguous().reverse();
+                tail
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => {
+                warn!("Failed to read {}: {}", path.display(), e);
+                VecDeque::new()
+            }
+        };
+
+        AuditLog {
+            path,
+            tail: Mutex::new(tail),
+        }
+    }
+
+    /// Adds an entry, a log that can't be written is reported but doesn't stop anyone logging in
+    pub fn record(&self, event: AuditEvent, username: &str, ip: Option<IpAddr>) {
+        let entry = AuditEntry {
+            time: Utc::now(),
+            event,
+            username: username.to_string(),
+            ip,
+        };
+        info!("Audit: {} {:?} from {:?}", event, username, ip);
+
+        let mut tail = self.tail.lock().unwrap();
+        if let Err(e) = self.append(&entry) {
+            warn!("Failed to write {}: {}", self.path.display(), e);
+        }
+
+        if tail.len() >= TAIL_SIZE {
+            tail.pop_front();
+        }
+        tail.push_back(entry);
+    }
+
+    fn append(&self, entry: &AuditEntry) -> io::Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(&line)
+    }
+
+    /// The latest `count` entries, newest first, up to the last hundred
+    pub fn recent(&self, count: usize) -> Vec<AuditEntry> {
+        let tail = self.tail.lock().unwrap();
+        tail.iter().rev().take(count).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data/audit.log");
+        let log = AuditLog::new(&path);
+        assert!(log.recent(10).is_empty());
+
+        let ip = Some("203.0.113.5".parse().unwrap());
+        log.record(AuditEvent::LoginFailed, "alex", ip);
+        log.record(AuditEvent::Login, "alex", ip);
+        log.record(AuditEvent::Logout, "alex", None);
+
+        let events = log
+            .recent(2)
+            .into_iter()
+            .map(|entry| (entry.event, entry.ip))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [(AuditEvent::Logout, None), (AuditEvent::Login, ip)]
+        );
+        assert_eq!(AuditLog::new(&path).recent(10), log.recent(10));
+
+        for _ in 0..TAIL_SIZE {
+            log.record(AuditEvent::Login, "sam", None);
+        }
+        assert_eq!(log.recent(usize::MAX).len(), TAIL_SIZE);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap().lines().count(),
+            TAIL_SIZE + 3
+        );
+    }
+}
@@ -17,9 +17,12 @@ use actix_web::{
 use autopixel::autopixel;
 use twox_hash::XxHash64;
 
+/// A generated sketch, the p5js source and the rendered png
+type Sketch = (String, Arc<[u8]>);
+
 pub struct ArtCache {
     max_size: usize,
-    cache: RwLock<VecDeque<(u64, (String, Arc<[u8]>))>>,
+    cache: RwLock<VecDeque<(u64, Sketch)>>,
 }
 
 impl ArtCache {
@@ -31,7 +34,7 @@ impl ArtCache {
     }
 
     /// Removes the oldest element from the cache if it is full
-    fn insert(&self, hash: u64, p5js: String, image: Vec<u8>) {
+    pub(crate) fn insert(&self, hash: u64, p5js: String, image: Vec<u8>) {
         let mut guard = self.cache.write().unwrap();
         if guard.len() >= self.max_size {
             guard.pop_front();
@@ -40,7 +43,7 @@ impl ArtCache {
     }
 
     /// Returns the p5js and image if it is in the cache
-    fn get(&self, hash: u64) -> Option<(String, Arc<[u8]>)> {
+    fn get(&self, hash: u64) -> Option<Sketch> {
         self.cache
             .read()
             .unwrap()
@@ -49,6 +52,33 @@ impl ArtCache {
             .map(|(_, v)| v)
             .cloned()
     }
+
+    /// The hashes of the cached sketches, newest first
+    pub(crate) fn hashes(&self) -> Vec<u64> {
+        self.cache
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .map(|(hash, _)| *hash)
+            .collect()
+    }
+
+    /// Removes a sketch, returning false if it wasn't cached
+    pub(crate) fn remove(&self, hash: u64) -> bool {
+        let mut guard = self.cache.write().unwrap();
+        let len = guard.len();
+        guard.retain(|(h, _)| *h != hash);
+        guard.len() != len
+    }
+
+    /// Removes every sketch, returning how many there were
+    pub(crate) fn purge(&self) -> usize {
+        let mut guard = self.cache.write().unwrap();
+        let len = guard.len();
+        guard.clear();
+        len
+    }
 }
 
 pub fn autopixel_service() -> impl HttpServiceFactory {
@@ -117,8 +147,8 @@ async fn upload(
     // SHA2 hash of the file
     let mut hasher = XxHash64::default();
     hasher.write(&form.file.data);
-    hasher.write_usize(form.colors.clone());
-    hasher.write_usize(form.size.clone());
+    hasher.write_usize(*form.colors);
+    hasher.write_usize(*form.size);
     let hash = hasher.finish();
 
     // Create a buf-reader from the file
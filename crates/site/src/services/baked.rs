@@ -1,12 +1,66 @@
-use actix_web::{dev::HttpServiceFactory, get, HttpResponse};
+use std::sync::Arc;
+
+use actix_web::{dev::HttpServiceFactory, get, web, HttpResponse};
 use include_dir::{include_dir, Dir};
 use log::warn;
+use markdown::{
+    highlight,
+    images::{self, ImageIndex},
+};
+
+use crate::config::Config;
 
-const FILES: Dir = include_dir!("static");
+pub(crate) const FILES: Dir = include_dir!("static");
 
 /// A static file service that serves files baked into the binary
 pub fn baked_files() -> impl HttpServiceFactory {
-    actix_web::web::scope("/s").service(baked_files_handler)
+    actix_web::web::scope("/s")
+        .app_data(web::Data::new(image_index()))
+        .service(image_variant_handler)
+        .service(highlight_handler)
+        .service(baked_files_handler)
+}
+
+/// Serves the syntax highlighting stylesheet for a color scheme, `light` or `dark`
+#[get("/highlight/{scheme}.css")]
+async fn highlight_handler(
+    config: web::Data<Arc<Config>>,
+    scheme: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    let theme = match scheme.as_str() {
+        "light" => &config.highlight.light,
+        "dark" => &config.highlight.dark,
+        _ => return Err(actix_web::error::ErrorNotFound("Unknown color scheme")),
+    };
+
+    let css = highlight::stylesheet(theme)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Unknown theme"))?;
+
+    Ok(HttpResponse::Ok().content_type("text/css").body(css))
+}
+
+/// Returns the index of images in the static files, used to render responsive images
+pub(crate) fn image_index() -> &'static ImageIndex {
+    images::init(&FILES, "/s/")
+}
+
+/// Serves resized variants of the static images, they are content addressed so they never change
+#[get("/img/{hash:[0-9a-f]{16}}-{width:[0-9]+}.{ext}")]
+async fn image_variant_handler(
+    path: web::Path<(String, u32, String)>,
+    index: web::Data<&'static ImageIndex>,
+) -> actix_web::Result<HttpResponse> {
+    let (hash, width, extension) = path.into_inner();
+    let hash = u64::from_str_radix(&hash, 16).map_err(actix_web::error::ErrorNotFound)?;
+
+    let (bytes, mime) = web::block(move || index.variant(hash, width, &extension))
+        .await?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Image not found"))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(mime)
+        .insert_header(("cache-control", "public, max-age=31536000, immutable"))
+        .body(bytes.to_vec()))
 }
 
 #[get("/{filename:.*}")]
@@ -25,3 +79,78 @@ async fn baked_files_handler(req: actix_web::HttpRequest) -> actix_web::Result<H
 pub fn get_file(filename: &str) -> Option<&'static str> {
     FILES.get_file(filename).and_then(|f| f.contents_utf8())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{test, web::Data, App};
+    use markdown::Markdown;
+    use maud::Render;
+
+    use super::{baked_files, image_index};
+    use crate::config::Config;
+
+    #[actix_web::test]
+    async fn test_responsive_images() {
+        image_index();
+        let html = Markdown("![Diagrams](/static/img/big_three.jpg)")
+            .render()
+            .into_string();
+
+        assert!(html.contains("<picture><source type=\"image/jpeg\""));
+        assert!(html.contains("loading=\"lazy\""));
+
+        // The first variant in the srcset can be fetched
+        let variant = html.split("srcset=\"").nth(1).unwrap();
+        let variant = variant.split(' ').next().unwrap();
+
+        let app = test::init_service(App::new().service(baked_files())).await;
+        let req = test::TestRequest::get().uri(variant).to_request();
+        let res = test::call_service(&app, req).await;
+
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get("content-type").unwrap(), "image/jpeg");
+
+        // Only with the extension of the original's format
+        let req = test::TestRequest::get()
+            .uri(&variant.replace(".jpg", ".png"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_highlight_stylesheets() {
+        let config: Config = toml::from_str(
+            r#"
+            username = "admin"
+            password = "password"
+
+            [highlight]
+            light = "Solarized (light)"
+            dark = "Solarized (dark)"
+            "#,
+        )
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(Arc::new(config)))
+                .service(baked_files()),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/s/highlight/dark.css")
+            .to_request();
+        let css = test::call_and_read_body(&app, req).await;
+        assert!(String::from_utf8_lossy(&css).contains("Solarized (dark)"));
+
+        let req = test::TestRequest::get()
+            .uri("/s/highlight/sepia.css")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 404);
+    }
+}
@@ -0,0 +1,225 @@
+//! Cross-site request forgery tokens.
+//!
+//! Each session gets a random token that forms send back, in a hidden `csrf` field or the
+//! `X-CSRF-Token` header for scripts. Another site can make a browser post to ours, but it can't
+//! read the token to go with it.
+//!
+//! Every service with forms is wrapped in [`protect`], which checks the token of anything but a
+//! `GET` before the handler runs.
+
+use std::pin::Pin;
+
+use actix_session::{Session, SessionExt};
+use actix_web::{
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error::{ErrorForbidden, ErrorInternalServerError, PayloadError},
+    middleware::Next,
+    web::Bytes,
+    HttpMessage,
+};
+use futures_util::{stream, Stream};
+use maud::{html, Markup};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Deserialize;
+
+use crate::services::accounts::bearer_token;
+
+/// Where the token is kept in the session
+const SESSION_KEY: &str = "csrf";
+
+/// The header scripts send the token in
+pub const HEADER: &str = "x-csrf-token";
+
+/// The token of the session, created the first time it's needed
+pub fn token(session: &Session) -> actix_web::Result<String> {
+    if let Some(token) = session
+        .get::<String>(SESSION_KEY)
+        .map_err(ErrorInternalServerError)?
+    {
+        return Ok(token);
+    }
+
+    let token = rand::thread_rng()
+        .sample_iter(Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect::<String>();
+    session
+        .insert(SESSION_KEY, &token)
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(token)
+}
+
+/// A hidden form field holding the token
+pub fn field(token: &str) -> Markup {
+    html! {
+        input type="hidden" name="csrf" value=(token);
+    }
+}
+
+/// Checks a request sent back the token of its session
+pub fn verify(session: &Session, token: Option<&str>) -> actix_web::Result<()> {
+    let expected = session
+        .get::<String>(SESSION_KEY)
+        .map_err(ErrorInternalServerError)?;
+
+    match (expected, token) {
+        (Some(expected), Some(token)) if constant_time_eq(&expected, token) => Ok(()),
+        _ => Err(ErrorForbidden(
+            "Invalid CSRF token, reload the page and try again",
+        )),
+    }
+}
+
+/// The fields of a form that matter here, the others are left to the handler
+#[derive(Deserialize)]
+struct TokenField {
+    csrf: Option<String>,
+}
+
+/// Middleware rejecting requests that could change something unless they send back the token of
+/// their session, in the `csrf` field of a form or the [`HEADER`]. Requests made with an API token
+/// don't use the session, so there's nothing for another site to borrow and they're let through.
+pub async fn protect(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> actix_web::Result<ServiceResponse<impl MessageBody>> {
+    if req.method().is_safe() || bearer_token(req.request()).is_some() {
+        return next.call(req).await.map(|res| res.map_into_left_body());
+    }
+
+    let token = match req.headers().get(HEADER) {
+        Some(token) => token.to_str().ok().map(str::to_string),
+        None if req.content_type() == "application/x-www-form-urlencoded" => {
+            // The handler reads the form again, so the body is put back once the token is found
+            let body = req.extract::<Bytes>().await?;
+            let token = serde_urlencoded::from_bytes::<TokenField>(&body)
+                .ok()
+                .and_then(|field| field.csrf);
+            let body: Pin<Box<dyn Stream<Item = Result<Bytes, PayloadError>>>> =
+                Box::pin(stream::once(async { Ok(body) }));
+            req.set_payload(Payload::from(body));
+            token
+        }
+        None => None,
+    };
+
+    // Answered here like the handler would have, rather than failing the whole service
+    match verify(&req.get_session(), token.as_deref()) {
+        Ok(()) => next.call(req).await.map(|res| res.map_into_left_body()),
+        Err(e) => Ok(req.error_response(e).map_into_right_body()),
+    }
+}
+
+/// Compares without stopping at the first difference, so timing doesn't give the token away
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::{
+        cookie::{Cookie, Key},
+        test, web, App, HttpResponse,
+    };
+
+    use super::*;
+
+    /// The session cookie and CSRF token of a page with a form
+    pub(crate) async fn csrf_session<B: MessageBody>(
+        res: ServiceResponse<B>,
+    ) -> (Cookie<'static>, String) {
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+        let body = test::read_body(res).await;
+        let token = String::from_utf8_lossy(&body)
+            .split(r#"name="csrf" value=""#)
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap()
+            .to_string();
+        (cookie, token)
+    }
+
+    #[derive(Deserialize)]
+    struct NameForm {
+        name: String,
+    }
+
+    #[actix_web::test]
+    async fn test_protect() {
+        let app = test::init_service(
+            App::new()
+                .wrap(SessionMiddleware::new(
+                    CookieSessionStore::default(),
+                    Key::generate(),
+                ))
+                .service(
+                    web::scope("")
+                        .wrap(actix_web::middleware::from_fn(protect))
+                        .route(
+                            "/",
+                            web::get().to(|session: Session| async move {
+                                let token = token(&session)?;
+                                Ok::<_, actix_web::Error>(field(&token))
+                            }),
+                        )
+                        .route(
+                            "/",
+                            web::post().to(|form: web::Form<NameForm>| async move {
+                                HttpResponse::Ok().body(form.into_inner().name)
+                            }),
+                        ),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/").to_request();
+        let (cookie, token) = csrf_session(test::call_service(&app, req).await).await;
+
+        // The form still reaches the handler after the token is read from it
+        let req = test::TestRequest::post()
+            .uri("/")
+            .cookie(cookie.clone())
+            .set_form([("name", "alex"), ("csrf", &token)])
+            .to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "alex");
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .cookie(cookie.clone())
+            .insert_header((HEADER, token.as_str()))
+            .set_form([("name", "sam")])
+            .to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "sam");
+
+        // Without the token, with another, or without the session it came with
+        for (cookie, token) in [
+            (Some(cookie.clone()), None),
+            (Some(cookie), Some("forged")),
+            (None, Some(token.as_str())),
+        ] {
+            let mut req = test::TestRequest::post().uri("/");
+            if let Some(cookie) = cookie {
+                req = req.cookie(cookie);
+            }
+            let form = [("name", "mallory"), ("csrf", token.unwrap_or_default())];
+            let res = test::call_service(&app, req.set_form(form).to_request()).await;
+            assert_eq!(res.status(), 403, "{:?}", token);
+        }
+
+        // API tokens don't come from the session
+        let req = test::TestRequest::post()
+            .uri("/")
+            .insert_header(("authorization", "Bearer mb_token"))
+            .set_form([("name", "script")])
+            .to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "script");
+    }
+}
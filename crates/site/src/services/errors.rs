@@ -0,0 +1,155 @@
+//! Site wide error pages.
+//!
+//! Error responses are replaced by a page rendered with the site's layout, 404s include a "did you
+//! mean" link to the closest page. Requests that want JSON get a JSON error instead, and JSON
+//! errors from API routes are left alone.
+
+use std::sync::Arc;
+
+use actix_web::{
+    dev::ServiceResponse,
+    http::{
+        header::{self, ContentType, HeaderValue},
+        StatusCode,
+    },
+    middleware::{ErrorHandlerResponse, ErrorHandlers},
+    web, HttpResponse,
+};
+use blog::Viewer;
+
+use crate::{components::error_page, services::Content};
+
+/// Statuses that are rendered as error pages
+const STATUSES: [StatusCode; 5] = [
+    StatusCode::BAD_REQUEST,
+    StatusCode::UNAUTHORIZED,
+    StatusCode::NOT_FOUND,
+    StatusCode::PAYLOAD_TOO_LARGE,
+    StatusCode::INTERNAL_SERVER_ERROR,
+];
+
+/// Middleware rendering error responses as error pages
+pub fn error_handlers<B: 'static>() -> ErrorHandlers<B> {
+    STATUSES
+        .into_iter()
+        .fold(ErrorHandlers::new(), |handlers, status| {
+            handlers.handler(status, render_error)
+        })
+}
+
+fn is_json(value: Option<&HeaderValue>) -> bool {
+    value
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("application/json"))
+}
+
+fn render_error<B>(res: ServiceResponse<B>) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    if is_json(res.headers().get(header::CONTENT_TYPE)) {
+        return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+    }
+
+    let (req, res) = res.into_parts();
+    let status = res.status();
+
+    // Keep headers such as `WWW-Authenticate`, the body is replaced
+    let mut response = HttpResponse::build(status);
+    for (name, value) in res.headers() {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            response.append_header((name.clone(), value.clone()));
+        }
+    }
+
+    let accept = req.headers().get(header::ACCEPT);
+    let wants_html = accept
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("text/html"));
+
+    let response = if is_json(accept) && !wants_html {
+        response.json(serde_json::json!({
+            "status": status.as_u16(),
+            "error": status.canonical_reason().unwrap_or("Error"),
+        }))
+    } else {
+        // Pages outside of a collection, or in a private one, get the main collection's nav
+        let collections = req
+            .app_data::<web::Data<Arc<Content>>>()
+            .map(|content| content.collections());
+        let path = req.path();
+        let found = collections.as_deref().and_then(|collections| {
+            collections
+                .find(path)
+                .filter(|(blog, _)| blog.collection().visibility.allows(Viewer::Public))
+                .or_else(|| Some((collections.main()?, path.strip_prefix('/').unwrap_or(path))))
+        });
+
+        let suggestion = match (status, found) {
+            (StatusCode::NOT_FOUND, Some((blog, path))) => {
+                blog.suggest(path).map(|page| blog.uri(page))
+            }
+            _ => None,
+        };
+
+        let nav = found
+            .map(|(blog, _)| blog.nav("home.md", Viewer::Public))
+            .unwrap_or_default();
+        response
+            .content_type(ContentType::html())
+            .body(error_page(status, nav, suggestion.as_deref()).into_string())
+    };
+
+    Ok(ErrorHandlerResponse::Response(
+        ServiceResponse::new(req, response).map_into_right_body(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, App, HttpResponse};
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_error_pages() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(Content::baked())))
+                .wrap(error_handlers())
+                .route(
+                    "/bad",
+                    web::get().to(|| async { HttpResponse::BadRequest().body("Bad image") }),
+                )
+                .route(
+                    "/api",
+                    web::get().to(|| async {
+                        HttpResponse::BadRequest().json(serde_json::json!({ "reason": "api" }))
+                    }),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/m/robotics/hom.md")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 404);
+        let body = String::from_utf8_lossy(&test::read_body(res).await).to_string();
+        assert!(body.contains("<h1>404 Not Found</h1>"));
+        assert!(body.contains(r#"Did you mean <a href="/m/robotics/home.md">"#));
+
+        let req = test::TestRequest::get().uri("/bad").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert!(String::from_utf8_lossy(&body).contains("<h1>400 Bad Request</h1>"));
+
+        // API routes keep their own errors, clients wanting JSON get JSON
+        let req = test::TestRequest::get().uri("/api").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, r#"{"reason":"api"}"#);
+
+        let req = test::TestRequest::get()
+            .uri("/nope")
+            .insert_header(("accept", "application/json"))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, r#"{"error":"Not Found","status":404}"#);
+    }
+}
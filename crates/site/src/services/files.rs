@@ -1,66 +1,550 @@
-use std::{fs, io::Write, path::Path};
+//! A file upload/download service, `/f/{path}` serves the files in `uploads/`.
+//!
+//! Uploaders browse the files from `/f/`, where they can sort them, upload, rename and delete them
+//! and organise them in directories. The filesystem doesn't keep track of who uploaded what, so
+//! that's saved to `uploads.json` in the data directory.
+
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, RwLock},
+};
 
 use actix_files::Files;
-use actix_identity::Identity;
 use actix_multipart::Multipart;
+use actix_session::Session;
 use actix_web::{
     dev::HttpServiceFactory,
-    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized},
-    get, post,
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    get,
+    middleware::from_fn,
+    post,
     web::{self, redirect},
     Either, HttpResponse, Responder,
 };
+use chrono::{DateTime, Utc};
 use futures_util::StreamExt;
 use maud::{html, Markup, PreEscaped, DOCTYPE};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
 use tempfile::NamedTempFile;
 
+use crate::{
+    components::bytes,
+    services::{csrf, store, users::is_local, Account, Role},
+};
+
+/// Where uploaded files are kept
+pub(crate) const UPLOADS: &str = "uploads";
+
+/// What's escaped in the segments of links to files
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// An uploaded file or directory
+#[derive(Debug)]
+pub(crate) struct Upload {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+/// The files and directories uploaded to `dir`, sorted by name
+pub(crate) fn uploads(dir: &Path) -> io::Result<Vec<Upload>> {
+    let mut uploads = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        // Doesn't follow symlinks, which are skipped
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() && !metadata.is_dir() {
+            continue;
+        }
+
+        uploads.push(Upload {
+            name: entry.file_name().to_string_lossy().to_string(),
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(DateTime::from),
+        });
+    }
+    uploads.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(uploads)
+}
+
+/// Checks `path` is relative to the uploads and can't leave them, returning it with `/` between
+/// its components
+fn clean_path(path: &str) -> actix_web::Result<String> {
+    let invalid = || ErrorBadRequest("Invalid path");
+    let components = Path::new(path)
+        .components()
+        .map(|component| match component {
+            Component::Normal(name) => name.to_str().ok_or_else(invalid),
+            _ => Err(invalid()),
+        })
+        .collect::<actix_web::Result<Vec<_>>>()?;
+
+    match components.is_empty() {
+        true => Err(invalid()),
+        false => Ok(components.join("/")),
+    }
+}
+
+/// Like [`clean_path`], allowing the empty path for the top of the uploads
+fn clean_dir(dir: &str) -> actix_web::Result<String> {
+    match dir {
+        "" => Ok(String::new()),
+        dir => clean_path(dir),
+    }
+}
+
+/// `name` in the directory `dir` of the uploads
+fn join(dir: &str, name: &str) -> String {
+    match dir {
+        "" => name.to_string(),
+        dir => format!("{}/{}", dir, name),
+    }
+}
+
+/// The directory `path` is in
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+/// Checks `name` is a plain file name, that doesn't lead to another directory
+fn file_name(name: &str) -> actix_web::Result<&str> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(name),
+        _ => Err(ErrorBadRequest("Invalid file name")),
+    }
+}
+
+fn not_found(e: io::Error) -> actix_web::Error {
+    match e.kind() {
+        io::ErrorKind::NotFound => ErrorNotFound("No such file"),
+        _ => ErrorInternalServerError(e),
+    }
+}
+
+/// Deletes the upload at `path` in `dir`, directories only once they're empty. Returns the path
+/// cleaned up.
+pub(crate) fn delete_upload(dir: &Path, path: &str) -> actix_web::Result<String> {
+    let path = clean_path(path)?;
+    let full = dir.join(&path);
+
+    if fs::symlink_metadata(&full).map_err(not_found)?.is_dir() {
+        fs::remove_dir(&full).map_err(|e| match e.raw_os_error() {
+            Some(libc::ENOTEMPTY) => ErrorBadRequest("Directory isn't empty"),
+            _ => not_found(e),
+        })?;
+    } else {
+        fs::remove_file(&full).map_err(not_found)?;
+    }
+
+    Ok(path)
+}
+
+/// Renames the upload at `from` in `dir` to `to` in the same directory, without replacing an
+/// existing one. Returns the old and new paths.
+pub(crate) fn rename_upload(
+    dir: &Path,
+    from: &str,
+    to: &str,
+) -> actix_web::Result<(String, String)> {
+    let from = clean_path(from)?;
+    let to = join(parent(&from), file_name(to)?);
+    let (from_full, to_full) = (dir.join(&from), dir.join(&to));
+    let exists = || ErrorBadRequest("File already exists");
+
+    if fs::symlink_metadata(&from_full)
+        .map_err(not_found)?
+        .is_dir()
+    {
+        // Directories can't be linked, so check first. A race could still replace an empty one.
+        if fs::symlink_metadata(&to_full).is_ok() {
+            return Err(exists());
+        }
+        fs::rename(&from_full, &to_full).map_err(not_found)?;
+    } else {
+        // Linking fails if the new name is taken, where renaming would silently replace the file
+        fs::hard_link(&from_full, &to_full).map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => exists(),
+            _ => not_found(e),
+        })?;
+        fs::remove_file(&from_full).map_err(ErrorInternalServerError)?;
+    }
+
+    Ok((from, to))
+}
+
+/// Creates the directory `name` in the directory `parent` of `dir`, returning its path
+pub(crate) fn create_upload_dir(dir: &Path, parent: &str, name: &str) -> actix_web::Result<String> {
+    let path = join(&clean_dir(parent)?, file_name(name)?);
+    fs::create_dir(dir.join(&path)).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => ErrorBadRequest("File already exists"),
+        _ => not_found(e),
+    })?;
+
+    Ok(path)
+}
+
+/// Who uploaded a file, and when
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadInfo {
+    pub uploader: String,
+    pub uploaded: DateTime<Utc>,
+}
+
+/// Who uploaded every file, keyed by path
+pub struct UploadIndex {
+    path: PathBuf,
+    uploads: RwLock<BTreeMap<String, UploadInfo>>,
+}
+
+impl UploadIndex {
+    /// Loads the index saved at `path`, starting without any if the file doesn't exist yet
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        UploadIndex {
+            uploads: RwLock::new(store::load(&path)),
+            path,
+        }
+    }
+
+    /// Writes the index to disk, replacing the file at once so a crash can't truncate it
+    fn save(&self, uploads: &BTreeMap<String, UploadInfo>) -> io::Result<()> {
+        store::save(&self.path, uploads)
+    }
+
+    pub fn get(&self, path: &str) -> Option<UploadInfo> {
+        self.uploads.read().unwrap().get(path).cloned()
+    }
+
+    /// Notes that `uploader` just uploaded the file at `path`
+    pub fn record(&self, path: &str, uploader: &str) -> io::Result<()> {
+        let mut uploads = self.uploads.write().unwrap();
+        uploads.insert(
+            path.to_string(),
+            UploadInfo {
+                uploader: uploader.to_string(),
+                uploaded: Utc::now(),
+            },
+        );
+
+        self.save(&uploads)
+    }
+
+    /// Follows a file or directory to its new path
+    pub fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let mut uploads = self.uploads.write().unwrap();
+        let moved = uploads
+            .keys()
+            .filter(|path| *path == from || path.starts_with(&format!("{}/", from)))
+            .cloned()
+            .collect::<Vec<_>>();
+        if moved.is_empty() {
+            return Ok(());
+        }
+
+        for path in moved {
+            let info = uploads.remove(&path).unwrap();
+            uploads.insert(format!("{}{}", to, &path[from.len()..]), info);
+        }
+        self.save(&uploads)
+    }
+
+    pub fn remove(&self, path: &str) -> io::Result<()> {
+        let mut uploads = self.uploads.write().unwrap();
+        if uploads.remove(path).is_none() {
+            return Ok(());
+        }
+
+        self.save(&uploads)
+    }
+}
+
 /// A file upload/download service that serves files from the filesystem
 pub fn file_service() -> impl HttpServiceFactory {
     // Make upload directory if it doesn't exist
-    if !Path::new("uploads").exists() {
-        std::fs::create_dir("uploads").expect("Failed to create uploads directory");
+    if !Path::new(UPLOADS).exists() {
+        std::fs::create_dir(UPLOADS).expect("Failed to create uploads directory");
     }
 
     // Static file service
     web::scope("/f")
+        .wrap(from_fn(csrf::protect))
         .service(redirect("", "/f/"))
         .service(index)
         .service(upload_file)
-        .service(Files::new("/", "uploads"))
+        .service(delete_file)
+        .service(rename_file)
+        .service(make_dir)
+        .service(Files::new("/", UPLOADS))
+}
+
+/// A link to the upload at `path`
+pub(crate) fn file_link(path: &str) -> String {
+    let path = path
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, SEGMENT).to_string())
+        .collect::<Vec<_>>();
+    format!("/f/{}", path.join("/"))
+}
+
+/// A link to the file browser, showing the directory `dir`
+pub(crate) fn dir_link(dir: &str) -> String {
+    match dir {
+        "" => "/f/".to_string(),
+        dir => format!("/f/?dir={}", utf8_percent_encode(dir, SEGMENT)),
+    }
+}
+
+/// Takes the uploader back to the directory they were in after an action
+fn back(dir: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .append_header(("location", dir_link(dir)))
+        .finish()
+}
+
+/// Like [`back`], unless the form asked to go to another page of the site
+fn back_to(redirect: Option<&str>, dir: &str) -> HttpResponse {
+    match redirect.filter(|path| is_local(path)) {
+        Some(path) => HttpResponse::SeeOther()
+            .append_header(("location", path))
+            .finish(),
+        None => back(dir),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Sort {
+    #[default]
+    Name,
+    Size,
+    Uploaded,
+    Uploader,
+    Type,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct BrowseQuery {
+    #[serde(default)]
+    dir: String,
+    #[serde(default)]
+    sort: Sort,
+    #[serde(default)]
+    order: Order,
+}
+
+/// A row of the file browser
+#[derive(Debug)]
+struct Entry {
+    upload: Upload,
+    path: String,
+    uploader: Option<String>,
+    /// When it was uploaded, or last modified for files from before uploads were recorded
+    uploaded: Option<DateTime<Utc>>,
+    mime: String,
+}
+
+/// Sorts the entries of a directory, keeping directories first
+fn sort_entries(entries: &mut [Entry], sort: Sort, order: Order) {
+    entries.sort_by(|a, b| {
+        let ordering = match sort {
+            Sort::Name => Ordering::Equal,
+            Sort::Size => a.upload.size.cmp(&b.upload.size),
+            Sort::Uploaded => a.uploaded.cmp(&b.uploaded),
+            Sort::Uploader => a.uploader.cmp(&b.uploader),
+            Sort::Type => a.mime.cmp(&b.mime),
+        }
+        .then_with(|| a.upload.name.cmp(&b.upload.name));
+        let ordering = match order {
+            Order::Asc => ordering,
+            Order::Desc => ordering.reverse(),
+        };
+
+        b.upload.is_dir.cmp(&a.upload.is_dir).then(ordering)
+    });
 }
 
 #[get("/")]
-async fn index(user: Option<Identity>) -> Either<HttpResponse, Markup> {
+async fn index(
+    user: Option<Account>,
+    session: Session,
+    upload_index: web::Data<Arc<UploadIndex>>,
+    query: web::Query<BrowseQuery>,
+) -> actix_web::Result<Either<HttpResponse, Markup>> {
     // 302 redirect to login if not authenticated
-    if user.is_none() {
-        return Either::Left(
-            HttpResponse::Found()
-                .append_header(("location", "/u/?redirect=/f"))
-                .finish(),
-        );
+    if let Err(res) = Account::require_page(user, Role::Uploader, "/f") {
+        return Ok(Either::Left(res));
     }
+    let token = csrf::token(&session)?;
 
-    Either::Right(html! {
+    let dir = clean_dir(&query.dir)?;
+    let mut entries = uploads(&Path::new(UPLOADS).join(&dir))
+        .map_err(not_found)?
+        .into_iter()
+        .map(|upload| {
+            let path = join(&dir, &upload.name);
+            let info = upload_index.get(&path);
+            let mime = match upload.is_dir {
+                true => String::new(),
+                false => mime_guess::from_path(&upload.name)
+                    .first_or_octet_stream()
+                    .to_string(),
+            };
+
+            Entry {
+                uploader: info.as_ref().map(|info| info.uploader.clone()),
+                uploaded: info.map(|info| info.uploaded).or(upload.modified),
+                upload,
+                path,
+                mime,
+            }
+        })
+        .collect::<Vec<_>>();
+    sort_entries(&mut entries, query.sort, query.order);
+
+    // Headers sort by their column, and the other way when already sorted by it
+    let sort_link = |sort: Sort, label: &str| {
+        let order = match (query.sort == sort, query.order) {
+            (true, Order::Asc) => "desc",
+            _ => "asc",
+        };
+        let sort = match sort {
+            Sort::Name => "name",
+            Sort::Size => "size",
+            Sort::Uploaded => "uploaded",
+            Sort::Uploader => "uploader",
+            Sort::Type => "type",
+        };
+        html! {
+            a href=(format!("/f/?dir={}&sort={}&order={}", utf8_percent_encode(&dir, SEGMENT), sort, order)) {
+                (label)
+            }
+        }
+    };
+
+    // Every directory from the top down to this one
+    let mut crumbs = vec![];
+    let mut crumb = String::new();
+    for name in dir.split('/').filter(|name| !name.is_empty()) {
+        crumb = join(&crumb, name);
+        crumbs.push((name, crumb.clone()));
+    }
+
+    Ok(Either::Right(html! {
         (DOCTYPE)
-        h1 { "File Service" }
-        p { "Upload and download files here" }
-        form method="post" enctype="multipart/form-data" {
-            input type="file" name="file" multiple;
-            input type="button" value="Upload" onclick="uploadFiles()";
+        (blog::header("Files"))
+        main {
+            h1 { "File Service" }
+            p {
+                a href="/f/" { "uploads" }
+                @for (name, path) in &crumbs {
+                    " / " a href=(dir_link(path)) { (name) }
+                }
+            }
+
+            table {
+                thead {
+                    tr {
+                        th { (sort_link(Sort::Name, "Name")) }
+                        th { (sort_link(Sort::Size, "Size")) }
+                        th { (sort_link(Sort::Uploaded, "Uploaded")) }
+                        th { (sort_link(Sort::Uploader, "Uploader")) }
+                        th { (sort_link(Sort::Type, "Type")) }
+                        th {} th {}
+                    }
+                }
+                tbody {
+                    @for entry in &entries {
+                        tr {
+                            td {
+                                @if entry.upload.is_dir {
+                                    a href=(dir_link(&entry.path)) { (entry.upload.name) "/" }
+                                } @else {
+                                    a href=(file_link(&entry.path)) { (entry.upload.name) }
+                                }
+                            }
+                            td { @if !entry.upload.is_dir { (bytes(entry.upload.size)) } }
+                            td { @if let Some(uploaded) = entry.uploaded { (uploaded.format("%Y-%m-%d %H:%M")) } }
+                            td { @if let Some(uploader) = &entry.uploader { (uploader) } }
+                            td { (entry.mime) }
+                            td {
+                                form method="post" action="/f/rename" {
+                                    (csrf::field(&token))
+                                    input type="hidden" name="path" value=(entry.path);
+                                    input type="text" name="to" value=(entry.upload.name) required;
+                                    button { "Rename" }
+                                }
+                            }
+                            td {
+                                form method="post" action="/f/delete" {
+                                    (csrf::field(&token))
+                                    input type="hidden" name="path" value=(entry.path);
+                                    button { "Delete" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            form method="post" action="/f/mkdir" {
+                (csrf::field(&token))
+                input type="hidden" name="dir" value=(dir);
+                input type="text" name="name" placeholder="Directory name" required;
+                button { "New directory" }
+            }
+
+            p { "Upload files here" }
+            form method="post" enctype="multipart/form-data" {
+                (csrf::field(&token))
+                input type="file" name="file" multiple;
+                input type="button" value="Upload" onclick="uploadFiles()";
+            }
         }
 
         (PreEscaped("<script src=\"/s/files.js\"></script>"))
-    })
+    }))
 }
 
+#[derive(Debug, Deserialize)]
+struct UploadQuery {
+    #[serde(default)]
+    dir: String,
+}
+
+/// Uploads files into the directory of the `dir` query parameter
 #[post("/")]
 async fn upload_file(
-    user: Option<Identity>,
+    user: Option<Account>,
+    upload_index: web::Data<Arc<UploadIndex>>,
+    query: web::Query<UploadQuery>,
     mut payload: Multipart,
 ) -> actix_web::Result<impl Responder> {
-    // Requires authentication
-    if user.is_none() {
-        return Err(ErrorUnauthorized("Authentication required"));
+    let user = Account::require(user, Role::Uploader)?;
+
+    let dir = clean_dir(&query.dir)?;
+    if !Path::new(UPLOADS).join(&dir).is_dir() {
+        return Err(ErrorNotFound("No such directory"));
     }
 
     // Steam multipart files to disk
@@ -77,6 +561,7 @@ async fn upload_file(
             .and_then(|f| Path::new(f).file_name())
             .map(|f| f.to_string_lossy().to_string())
             .ok_or(ErrorBadRequest("Filename missing"))?;
+        let path = join(&dir, &filename);
 
         // Write file to disk
         while let Some(chunk) = field.next().await {
@@ -86,14 +571,227 @@ async fn upload_file(
 
         // Persist the temporary file by copying it into the `uploads` directory
         let mut persisted =
-            fs::File::create_new(format!("uploads/{}", filename)).map_err(|e| match e.kind() {
+            fs::File::create_new(Path::new(UPLOADS).join(&path)).map_err(|e| match e.kind() {
                 std::io::ErrorKind::AlreadyExists => ErrorBadRequest("File already exists"),
                 _ => ErrorInternalServerError(e),
             })?;
 
         // Copy the temporary file to the persisted file
         std::io::copy(&mut tmp, &mut persisted).map_err(ErrorInternalServerError)?;
+        upload_index
+            .record(&path, &user.username)
+            .map_err(ErrorInternalServerError)?;
     }
 
     Ok(HttpResponse::Ok().finish())
 }
+
+#[derive(Debug, Deserialize)]
+struct DeleteForm {
+    path: String,
+    /// Where to go afterwards, for forms on other pages such as the admin panel
+    redirect: Option<String>,
+}
+
+#[post("/delete")]
+async fn delete_file(
+    user: Option<Account>,
+    upload_index: web::Data<Arc<UploadIndex>>,
+    form: web::Form<DeleteForm>,
+) -> actix_web::Result<impl Responder> {
+    Account::require(user, Role::Uploader)?;
+
+    let path = delete_upload(Path::new(UPLOADS), &form.path)?;
+    upload_index
+        .remove(&path)
+        .map_err(ErrorInternalServerError)?;
+    Ok(back_to(form.redirect.as_deref(), parent(&path)))
+}
+
+#[derive(Debug, Deserialize)]
+struct RenameForm {
+    path: String,
+    to: String,
+    redirect: Option<String>,
+}
+
+#[post("/rename")]
+async fn rename_file(
+    user: Option<Account>,
+    upload_index: web::Data<Arc<UploadIndex>>,
+    form: web::Form<RenameForm>,
+) -> actix_web::Result<impl Responder> {
+    Account::require(user, Role::Uploader)?;
+
+    let (from, to) = rename_upload(Path::new(UPLOADS), &form.path, form.to.trim())?;
+    upload_index
+        .rename(&from, &to)
+        .map_err(ErrorInternalServerError)?;
+    Ok(back_to(form.redirect.as_deref(), parent(&to)))
+}
+
+#[derive(Debug, Deserialize)]
+struct DirForm {
+    dir: String,
+    name: String,
+}
+
+#[post("/mkdir")]
+async fn make_dir(
+    user: Option<Account>,
+    form: web::Form<DirForm>,
+) -> actix_web::Result<impl Responder> {
+    Account::require(user, Role::Uploader)?;
+
+    let path = create_upload_dir(Path::new(UPLOADS), &form.dir, form.name.trim())?;
+    Ok(back(&path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_manage_uploads() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("b.txt"), "bb").unwrap();
+        fs::create_dir(dir.join("sub")).unwrap();
+
+        let names = |dir| {
+            uploads(dir)
+                .unwrap()
+                .into_iter()
+                .filter(|upload| !upload.is_dir)
+                .map(|upload| (upload.name, upload.size))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(dir),
+            [("a.txt".to_string(), 1), ("b.txt".to_string(), 2)]
+        );
+
+        // Files can't be moved out of the directory, or over each other
+        assert!(rename_upload(dir, "a.txt", "../a.txt").is_err());
+        assert!(rename_upload(dir, "a.txt", "sub/a.txt").is_err());
+        assert!(rename_upload(dir, "a.txt", "b.txt").is_err());
+        assert!(delete_upload(dir, "../a.txt").is_err());
+        assert!(delete_upload(dir, "c.txt").is_err());
+
+        rename_upload(dir, "a.txt", "c.txt").unwrap();
+        delete_upload(dir, "b.txt").unwrap();
+        assert_eq!(names(dir), [("c.txt".to_string(), 1)]);
+    }
+
+    #[actix_web::test]
+    async fn test_subdirectories() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        for path in ["", "/", "/etc", "..", "a/../b", "./a"] {
+            assert!(clean_path(path).is_err(), "{:?}", path);
+        }
+        assert_eq!(clean_path("a//b/").unwrap(), "a/b");
+        assert_eq!(clean_dir("").unwrap(), "");
+
+        assert_eq!(create_upload_dir(dir, "", "docs").unwrap(), "docs");
+        assert_eq!(create_upload_dir(dir, "docs", "old").unwrap(), "docs/old");
+        assert!(create_upload_dir(dir, "docs", "old").is_err());
+        assert!(create_upload_dir(dir, "missing", "old").is_err());
+        assert!(create_upload_dir(dir, "docs", "../escape").is_err());
+        fs::write(dir.join("docs/old/a.txt"), "a").unwrap();
+
+        // Renames stay in the same directory, and directories are only deleted once empty
+        assert_eq!(
+            rename_upload(dir, "docs/old/a.txt", "b.txt").unwrap(),
+            ("docs/old/a.txt".to_string(), "docs/old/b.txt".to_string())
+        );
+        assert!(rename_upload(dir, "docs/old", "old").is_err());
+        assert!(delete_upload(dir, "docs").is_err());
+        rename_upload(dir, "docs/old", "new").unwrap();
+        assert!(dir.join("docs/new/b.txt").is_file());
+        delete_upload(dir, "docs/new/b.txt").unwrap();
+        delete_upload(dir, "docs/new").unwrap();
+
+        let listing = uploads(dir)
+            .unwrap()
+            .into_iter()
+            .map(|upload| (upload.name, upload.is_dir))
+            .collect::<Vec<_>>();
+        assert_eq!(listing, [("docs".to_string(), true)]);
+
+        // Forms on other pages go back to them, as long as they're on this site
+        let location = |redirect| {
+            let res = back_to(redirect, "docs");
+            res.headers()
+                .get("location")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(location(None), "/f/?dir=docs");
+        assert_eq!(location(Some("/a/")), "/a/");
+        assert_eq!(location(Some("//example.com")), "/f/?dir=docs");
+    }
+
+    #[actix_web::test]
+    async fn test_upload_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("uploads.json");
+        let uploads = UploadIndex::load(&path);
+
+        uploads.record("docs/a.txt", "alex").unwrap();
+        uploads.record("docs/old/b.txt", "sam").unwrap();
+        uploads.record("docs.txt", "sam").unwrap();
+        uploads.rename("docs", "papers").unwrap();
+        uploads.remove("docs.txt").unwrap();
+
+        assert_eq!(uploads.get("papers/a.txt").unwrap().uploader, "alex");
+        assert_eq!(uploads.get("papers/old/b.txt").unwrap().uploader, "sam");
+        assert!(uploads.get("docs/a.txt").is_none());
+        assert!(uploads.get("docs.txt").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_sort_entries() {
+        let entry = |name: &str, is_dir, size, uploader: Option<&str>| Entry {
+            upload: Upload {
+                name: name.to_string(),
+                is_dir,
+                size,
+                modified: None,
+            },
+            path: name.to_string(),
+            uploader: uploader.map(str::to_string),
+            uploaded: None,
+            mime: mime_guess::from_path(name)
+                .first_or_octet_stream()
+                .to_string(),
+        };
+        let mut entries = vec![
+            entry("b.png", false, 10, Some("sam")),
+            entry("z", true, 4096, None),
+            entry("a.txt", false, 20, Some("alex")),
+        ];
+        let names = |entries: &[Entry]| {
+            entries
+                .iter()
+                .map(|entry| entry.upload.name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        sort_entries(&mut entries, Sort::Name, Order::Asc);
+        assert_eq!(names(&entries), ["z", "a.txt", "b.png"]);
+        sort_entries(&mut entries, Sort::Size, Order::Desc);
+        assert_eq!(names(&entries), ["z", "a.txt", "b.png"]);
+        sort_entries(&mut entries, Sort::Uploader, Order::Desc);
+        assert_eq!(names(&entries), ["z", "b.png", "a.txt"]);
+        sort_entries(&mut entries, Sort::Type, Order::Asc);
+        assert_eq!(names(&entries), ["z", "b.png", "a.txt"]);
+
+        assert_eq!(file_link("docs/a b#1.txt"), "/f/docs/a%20b%231.txt");
+        assert_eq!(dir_link("docs/old"), "/f/?dir=docs%2Fold");
+    }
+}
@@ -1,37 +1,309 @@
-use std::path::PathBuf;
-
-use actix_web::{
-    dev::HttpServiceFactory,
-    get,
-    web::{self, Data},
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
 };
-use blog::Blog;
-use include_dir::{include_dir, Dir};
-use log::info;
+
+use actix_web::{dev::HttpServiceFactory, get, web, HttpResponse, Scope};
+use blog::{Collection, Collections, Viewer};
+use chrono::NaiveDate;
+use include_dir::{include_dir, Dir, DirEntry, File};
+use log::{info, warn};
 use maud::Markup;
+use typed_arena::Arena;
+
+use crate::{
+    components::{register_shortcodes, sitemap},
+    config::Config,
+    services::{
+        baked::{image_index, FILES},
+        Account, Role,
+    },
+};
 
 const CONTENT: Dir = include_dir!("$CARGO_MANIFEST_DIR/../../content");
 
-/// Markdown rendering service that functions as the foundation of the site
+/// `{date} {path}` lines, the date each content file was last changed in git (see `build.rs`)
+const CONTENT_DATES: &str = include_str!(concat!(env!("OUT_DIR"), "/content-dates.txt"));
+
+/// Parses [`CONTENT_DATES`] into a map from path to date
+fn content_dates() -> HashMap<String, NaiveDate> {
+    CONTENT_DATES
+        .lines()
+        .filter_map(|line| {
+            let (date, path) = line.split_once(' ')?;
+            Some((path.to_string(), date.parse().ok()?))
+        })
+        .collect()
+}
+
+/// The blog's collections, shared by every worker so they're only rendered (and their links
+/// checked) once, and rebuilt when the content is reloaded
+pub struct Content {
+    site_url: String,
+    collections: Vec<Collection>,
+    content_dir: Option<PathBuf>,
+    live: RwLock<Arc<Collections>>,
+}
+
+impl Content {
+    /// Builds the blog's collections for a site hosted at `site_url`, from `content_dir` if given
+    /// or from the content baked into the binary
+    pub fn new(
+        site_url: &str,
+        collections: &[Collection],
+        content_dir: Option<&Path>,
+    ) -> Result<Self, String> {
+        let live = build(site_url, collections, content_dir)?;
+        Ok(Content {
+            site_url: site_url.to_string(),
+            collections: collections.to_vec(),
+            content_dir: content_dir.map(Path::to_path_buf),
+            live: RwLock::new(Arc::new(live)),
+        })
+    }
+
+    /// The default collection of the content baked into the binary, without a site URL
+    pub fn baked() -> Self {
+        Self::new("", &[Collection::default()], None).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// The collections being served
+    pub fn collections(&self) -> Arc<Collections> {
+        self.live.read().unwrap().clone()
+    }
+
+    /// Renders the collections again, reading the content directory again if there is one.
+    ///
+    /// Requests that are already being served finish with the previous collections. A content
+    /// directory that can't be read, or that's missing a collection's directory, leaves the
+    /// previous collections in place.
+    pub fn reload(&self) -> Result<Arc<Collections>, String> {
+        let collections = build(
+            &self.site_url,
+            &self.collections,
+            self.content_dir.as_deref(),
+        )?;
+        let collections = Arc::new(collections);
+        *self.live.write().unwrap() = collections.clone();
+        info!("Reloaded the content");
+
+        Ok(collections)
+    }
+}
+
+fn build(
+    site_url: &str,
+    collections: &[Collection],
+    content_dir: Option<&Path>,
+) -> Result<Collections, String> {
+    // Images must be indexed before rendering so they can be made responsive
+    image_index();
+    register_shortcodes(&FILES);
+
+    let (tree, arena, read);
+    let content = match content_dir {
+        Some(dir) => {
+            tree = Tree::read(dir, dir)
+                .map_err(|e| format!("Could not read {}: {}", dir.display(), e))?;
+            arena = Arena::new();
+            read = tree.dir(&arena);
+            &read
+        }
+        None => &CONTENT,
+    };
+
+    for collection in collections {
+        let dir = collection.dir.trim_matches('/');
+        if !dir.is_empty() && content.get_dir(dir).is_none() {
+            return Err(format!(
+                "Collection {:?} directory {:?} does not exist",
+                collection.name, dir
+            ));
+        }
+    }
+
+    let collections = Collections::from_include_dir(content, collections, &content_dates())
+        .with_site_url(site_url);
+    for link in collections.broken_links(&FILES) {
+        warn!("Broken link {}", link);
+    }
+
+    Ok(collections)
+}
+
+/// A content directory read from disk
+struct Tree {
+    /// Relative to the content directory, like the paths of the baked content
+    path: String,
+    entries: Vec<Entry>,
+}
+
+enum Entry {
+    Dir(Tree),
+    File(String, Vec<u8>),
+}
+
+impl Tree {
+    fn read(root: &Path, dir: &Path) -> io::Result<Tree> {
+        let relative = |path: &Path| {
+            path.strip_prefix(root)
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        let mut paths = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        paths.sort();
+
+        let mut entries = vec![];
+        for path in paths {
+            if path.is_dir() {
+                entries.push(Entry::Dir(Tree::read(root, &path)?));
+            } else {
+                entries.push(Entry::File(relative(&path), fs::read(&path)?));
+            }
+        }
+
+        Ok(Tree {
+            path: relative(dir),
+            entries,
+        })
+    }
+
+    /// The same shape as the baked content. A [`Dir`] only borrows its entries, so they're kept
+    /// in `arena` until the collections are built.
+    fn dir<'a>(&'a self, arena: &'a Arena<DirEntry<'a>>) -> Dir<'a> {
+        // Collected first, the arena can't be allocated in while it's being extended
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                Entry::Dir(tree) => DirEntry::Dir(tree.dir(arena)),
+                Entry::File(path, contents) => DirEntry::File(File::new(path, contents)),
+            })
+            .collect::<Vec<_>>();
+
+        Dir::new(&self.path, arena.alloc_extend(entries))
+    }
+}
+
+/// Public pages that aren't part of the blog, the other services are in
+/// [`HIDDEN_SERVICES`](crate::components::HIDDEN_SERVICES)
+const SERVICE_PAGES: &[&str] = &["/pixel/"];
+
+/// Generates the sitemap from the published pages of the blog
+pub async fn sitemap_handler(
+    config: web::Data<Arc<Config>>,
+    content: web::Data<Arc<Content>>,
+) -> HttpResponse {
+    let mut urls = content
+        .collections()
+        .sitemap()
+        .into_iter()
+        .map(|(uri, updated)| (format!("{}{}", config.site_url, uri), updated))
+        .collect::<Vec<_>>();
+
+    urls.extend(
+        SERVICE_PAGES
+            .iter()
+            .map(|page| (format!("{}{}", config.site_url, page), None)),
+    );
+
+    HttpResponse::Ok()
+        .content_type("application/xml")
+        .body(sitemap(&urls))
+}
+
+/// Markdown rendering service that functions as the foundation of the site, serving the content
+/// baked into the binary as the default collection
 pub fn markdown_service() -> impl HttpServiceFactory {
-    let blog = Blog::from_include_dir(&CONTENT);
+    content_service(&Arc::new(Content::baked()))
+}
+
+/// A collection served by a scope, found by its position as the collections may be reloaded
+struct Mounted {
+    content: Arc<Content>,
+    index: usize,
+}
+
+impl Mounted {
+    /// The current collections and the position of this one
+    fn get(&self) -> (Arc<Collections>, usize) {
+        (self.content.collections(), self.index)
+    }
+}
 
-    web::scope("/m")
-        .app_data(Data::new(blog))
-        .service(markdown_handler)
+/// Serves each collection of `content` from a scope at its prefix, with its pages and feed
+pub fn content_service(content: &Arc<Content>) -> impl HttpServiceFactory {
+    content
+        .collections()
+        .iter()
+        .enumerate()
+        .map(|(index, blog)| {
+            let content = content.clone();
+            web::scope(blog.collection().prefix())
+                .app_data(web::Data::new(Mounted { content, index }))
+                .service(feed_handler)
+                .service(markdown_handler)
+        })
+        .collect::<Vec<Scope>>()
+}
+
+#[get("/feed.xml")]
+async fn feed_handler(mounted: web::Data<Mounted>) -> Option<HttpResponse> {
+    let (collections, index) = mounted.get();
+    let feed = collections.iter().nth(index)?.feed()?;
+
+    Some(
+        HttpResponse::Ok()
+            .content_type("application/atom+xml")
+            .body(feed.into_string()),
+    )
 }
 
 #[get("/{filename:.*}")]
-async fn markdown_handler(path: web::Path<PathBuf>, blog: web::Data<Blog>) -> Option<Markup> {
-    info!("Requesting {:?}", path);
-    blog.get(&path.to_string_lossy()).cloned()
+async fn markdown_handler(
+    path: web::Path<PathBuf>,
+    mounted: web::Data<Mounted>,
+    user: Option<Account>,
+) -> Option<Markup> {
+    let (collections, index) = mounted.get();
+    let blog = collections.iter().nth(index)?;
+    info!("Requesting {:?} from {}", path, blog.collection().name);
+
+    // Drafts, scheduled pages and private collections are only visible to admins
+    let viewer = match user {
+        Some(user) if user.role >= Role::Admin => Viewer::Admin,
+        _ => Viewer::Public,
+    };
+
+    blog.get(&path.to_string_lossy(), viewer)
 }
 
 #[cfg(test)]
 mod tests {
-    use actix_web::{test, App};
+    use actix_identity::IdentityMiddleware;
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use std::sync::Arc;
+
+    use actix_web::{
+        cookie::Key,
+        test,
+        web::{self, Data},
+        App,
+    };
+    use blog::{Blog, Collection, Reason, Viewer, Visibility};
+    use markdown::Markdown;
+    use maud::Render;
 
-    use super::markdown_service;
+    use super::{
+        content_dates, content_service, markdown_service, register_shortcodes, sitemap_handler,
+        Arena, Config, Content, Path, Tree, CONTENT, FILES,
+    };
 
     // Check what happens if the path includes ".."
     #[actix_web::test]
@@ -45,4 +317,157 @@ mod tests {
 
         assert_eq!(res.status(), 404);
     }
+
+    // Links to hidden pages are allowed, they're work in progress, but missing targets are not
+    #[actix_web::test]
+    async fn test_broken_links() {
+        let blog = Blog::from_include_dir(&CONTENT, &content_dates());
+        let missing = blog
+            .broken_links(&FILES)
+            .into_iter()
+            .filter(|link| link.reason == Reason::Missing)
+            .map(|link| link.to_string())
+            .collect::<Vec<_>>();
+
+        assert!(missing.is_empty(), "Broken links:\n{}", missing.join("\n"));
+    }
+
+    // Site shortcodes can read the baked static files
+    #[actix_web::test]
+    async fn test_autopixel_shortcode() {
+        register_shortcodes(&FILES);
+
+        let html = Markdown("{{< autopixel src=/static/img/labview.png size=16 colors=4 >}}")
+            .render()
+            .into_string();
+        assert!(html.contains(r#"<figure class="autopixel"><img src="data:image/png;base64,"#));
+
+        let html = Markdown("{{< autopixel src=/static/img/nope.png >}}")
+            .render()
+            .into_string();
+        assert!(html.starts_with("<p>{{&lt; autopixel"));
+    }
+
+    // Every page shows its reading time, and the committed ones when they were updated. Dates
+    // are only known when building from a git checkout.
+    #[actix_web::test]
+    async fn test_page_meta() {
+        let committed = content_dates().contains_key("robotics/home.md");
+
+        let app = test::init_service(
+            App::new()
+                .wrap(IdentityMiddleware::default())
+                .wrap(SessionMiddleware::new(
+                    CookieSessionStore::default(),
+                    Key::generate(),
+                ))
+                .service(markdown_service()),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/m/robotics/home.md")
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = String::from_utf8_lossy(&body);
+
+        assert_eq!(
+            body.contains(r#"<p class="page-meta">Updated <time datetime=""#),
+            committed
+        );
+        assert!(body.contains(" min read"));
+    }
+
+    // Only published pages and public services are listed
+    #[actix_web::test]
+    async fn test_sitemap() {
+        let config: Config = toml::from_str(
+            r#"
+            username = "admin"
+            password = "admin"
+            site_url = "https://example.com"
+            "#,
+        )
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(Arc::new(config)))
+                .app_data(Data::new(Arc::new(Content::baked())))
+                .route("/sitemap.xml", web::get().to(sitemap_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/sitemap.xml").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = String::from_utf8_lossy(&body);
+
+        assert!(body.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?><urlset"#));
+        assert!(body.contains("<loc>https://example.com/m/home.md</loc>"));
+        assert!(body.contains("<loc>https://example.com/pixel/</loc>"));
+        assert!(!body.contains("website.md"));
+        assert!(!body.contains("/f/"));
+    }
+
+    // Collections are served from their own prefix, private ones only to the admin
+    #[actix_web::test]
+    async fn test_collections() {
+        let frc = Collection {
+            name: "frc".to_string(),
+            prefix: "/frc".to_string(),
+            dir: "robotics".to_string(),
+            visibility: Visibility::Private,
+            ..Collection::default()
+        };
+        let content = Content::new("", &[Collection::default(), frc], None).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .wrap(IdentityMiddleware::default())
+                .wrap(SessionMiddleware::new(
+                    CookieSessionStore::default(),
+                    Key::generate(),
+                ))
+                .service(content_service(&Arc::new(content))),
+        )
+        .await;
+
+        for (uri, status) in [
+            ("/m/home.md", 200),
+            ("/m/robotics/home.md", 404),
+            ("/frc/home.md", 404),
+            ("/frc/feed.xml", 404),
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), status, "{}", uri);
+        }
+
+        let req = test::TestRequest::get().uri("/m/feed.xml").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "application/atom+xml"
+        );
+        let body = test::read_body(res).await;
+        assert!(
+            String::from_utf8_lossy(&body).contains("<feed xmlns=\"http://www.w3.org/2005/Atom\">")
+        );
+    }
+
+    // Content read from disk is laid out like the baked content
+    #[actix_web::test]
+    async fn test_read_content() {
+        let root: &Path = concat!(env!("CARGO_MANIFEST_DIR"), "/../../content").as_ref();
+        let tree = Tree::read(root, root).unwrap();
+        let arena = Arena::new();
+        let content = tree.dir(&arena);
+        assert_eq!(content.files().count(), CONTENT.files().count());
+        assert_eq!(
+            content.get_file("robotics/home.md").unwrap().contents(),
+            CONTENT.get_file("robotics/home.md").unwrap().contents()
+        );
+
+        let blog = Blog::from_include_dir(&content, &content_dates());
+        assert!(blog.get("robotics/home.md", Viewer::Public).is_some());
+    }
 }
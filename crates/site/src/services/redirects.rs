@@ -0,0 +1,326 @@
+//! Redirect rules, for moving pages without breaking old links.
+//!
+//! Rules come from `[[redirects]]` in `config.toml` and from `redirects.toml` in the data
+//! directory, which is read again when [`Redirects::reload`] finds it changed. They're checked in order before routing, the
+//! first match wins:
+//!
+//! ```toml
+//! [[redirects]]
+//! from = "/r/github"
+//! to = "https://github.com/Alextopher"
+//!
+//! [[redirects]]
+//! from = "/m/robotics/"
+//! to = "/frc/"
+//! match = "prefix"
+//! permanent = true
+//!
+//! [[redirects]]
+//! from = "^/m/notes/(?<year>\\d{4})-(?<slug>.+)$"
+//! to = "/notes/$year/$slug"
+//! match = "regex"
+//! ```
+//!
+//! Prefix rules keep the rest of the path, regex rules can use their captures in `to`. The query
+//! string is kept unless `to` has its own.
+
+use std::{
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, HttpResponse,
+};
+use log::{info, warn};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::config::{Match, RedirectRule};
+
+/// The format of the rules file
+#[derive(Debug, Default, Deserialize)]
+struct RulesFile {
+    #[serde(default)]
+    redirects: Vec<RedirectRule>,
+}
+
+/// A rule ready to be matched
+#[derive(Debug)]
+struct Rule {
+    from: Pattern,
+    to: String,
+    permanent: bool,
+}
+
+/// What a rule matches the request path with
+#[derive(Debug)]
+enum Pattern {
+    Exact(String),
+    Prefix(String),
+    Regex(Regex),
+}
+
+impl Rule {
+    fn compile(rule: &RedirectRule) -> Result<Self, String> {
+        let from = match rule.kind {
+            Match::Exact => Pattern::Exact(rule.from.clone()),
+            Match::Prefix => Pattern::Prefix(rule.from.clone()),
+            Match::Regex => Pattern::Regex(
+                Regex::new(&rule.from)
+                    .map_err(|e| format!("Invalid redirect from {:?}: {}", rule.from, e))?,
+            ),
+        };
+
+        Ok(Rule {
+            from,
+            to: rule.to.clone(),
+            permanent: rule.permanent,
+        })
+    }
+
+    /// The location `path` is redirected to, if this rule matches it
+    fn apply(&self, path: &str) -> Option<String> {
+        match &self.from {
+            Pattern::Exact(from) if from == path => Some(self.to.clone()),
+            Pattern::Exact(_) => None,
+            Pattern::Prefix(from) => path
+                .strip_prefix(from.as_str())
+                .map(|rest| format!("{}{}", self.to, rest)),
+            Pattern::Regex(from) => from.captures(path).map(|captures| {
+                let mut location = String::new();
+                captures.expand(&self.to, &mut location);
+                location
+            }),
+        }
+    }
+}
+
+fn compile(rules: &[RedirectRule]) -> Result<Vec<Rule>, String> {
+    rules.iter().map(Rule::compile).collect()
+}
+
+/// The rules of the rules file and the modification time they were read at
+#[derive(Default)]
+struct FileRules {
+    modified: Option<SystemTime>,
+    rules: Vec<Rule>,
+}
+
+/// Every redirect rule of the site
+pub struct Redirects {
+    config: Vec<Rule>,
+    file: PathBuf,
+    file_rules: RwLock<FileRules>,
+}
+
+impl Redirects {
+    /// Compiles the rules from the configuration and reads the rules file, which doesn't have to
+    /// exist yet
+    pub fn new(rules: &[RedirectRule], file: impl Into<PathBuf>) -> Result<Self, String> {
+        let redirects = Redirects {
+            config: compile(rules)?,
+            file: file.into(),
+            file_rules: RwLock::new(FileRules::default()),
+        };
+        redirects.reload();
+
+        Ok(redirects)
+    }
+
+    /// Reads the rules file again if it changed since it was last read. Broken files are logged
+    /// and the previous rules are kept.
+    pub fn reload(&self) {
+        let modified = match fs::metadata(&self.file).and_then(|m| m.modified()) {
+            Ok(modified) => Some(modified),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("Could not read {}: {}", self.file.display(), e);
+                return;
+            }
+        };
+
+        if self.file_rules.read().unwrap().modified == modified {
+            return;
+        }
+
+        let rules = match modified {
+            Some(_) => fs::read_to_string(&self.file)
+                .map_err(|e| e.to_string())
+                .and_then(|rules| toml::from_str::<RulesFile>(&rules).map_err(|e| e.to_string()))
+                .and_then(|rules| compile(&rules.redirects)),
+            None => Ok(vec![]),
+        };
+
+        let mut file_rules = self.file_rules.write().unwrap();
+        file_rules.modified = modified;
+        match rules {
+            Ok(rules) => {
+                info!(
+                    "Loaded {} redirects from {}",
+                    rules.len(),
+                    self.file.display()
+                );
+                file_rules.rules = rules;
+            }
+            Err(e) => warn!(
+                "Keeping the previous redirects, {}: {}",
+                self.file.display(),
+                e
+            ),
+        }
+    }
+
+    /// Where a request for `path` is redirected to, and whether the redirect is permanent
+    pub fn resolve(&self, path: &str) -> Option<(String, bool)> {
+        let file_rules = self.file_rules.read().unwrap();
+        self.config
+            .iter()
+            .chain(&file_rules.rules)
+            .find_map(|rule| {
+                rule.apply(path)
+                    // A rule redirecting to itself would loop forever
+                    .filter(|location| location != path)
+                    .map(|location| (location, rule.permanent))
+            })
+    }
+}
+
+/// Middleware answering requests that match a redirect rule, before they're routed
+pub async fn redirect_rules(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> actix_web::Result<ServiceResponse<impl MessageBody>> {
+    let redirect = req
+        .app_data::<web::Data<Arc<Redirects>>>()
+        .and_then(|redirects| redirects.resolve(req.path()));
+
+    let Some((mut location, permanent)) = redirect else {
+        return next.call(req).await.map(|res| res.map_into_left_body());
+    };
+
+    if !location.contains('?') && !req.query_string().is_empty() {
+        location = format!("{}?{}", location, req.query_string());
+    }
+
+    let mut response = match permanent {
+        true => HttpResponse::MovedPermanently(),
+        false => HttpResponse::Found(),
+    };
+    let response = response.append_header(("location", location)).finish();
+
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{middleware::from_fn, test, App};
+
+    use super::*;
+
+    fn rule(kind: Match, from: &str, to: &str) -> RedirectRule {
+        RedirectRule {
+            from: from.to_string(),
+            to: to.to_string(),
+            kind,
+            permanent: false,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("redirects.toml");
+        let redirects = Redirects::new(
+            &[
+                rule(Match::Exact, "/r/gh", "https://github.com"),
+                rule(Match::Prefix, "/m/robotics/", "/frc/"),
+                rule(
+                    Match::Regex,
+                    r"^/m/notes/(?<year>\d{4})-(?<slug>.+)$",
+                    "/notes/$year/$slug",
+                ),
+                rule(Match::Prefix, "/loop", "/loop"),
+            ],
+            &file,
+        )
+        .unwrap();
+
+        let resolve = |path| redirects.resolve(path).map(|(location, _)| location);
+        assert_eq!(resolve("/r/gh").unwrap(), "https://github.com");
+        assert_eq!(resolve("/r/gh/more"), None);
+        assert_eq!(resolve("/m/robotics/java.md").unwrap(), "/frc/java.md");
+        assert_eq!(
+            resolve("/m/notes/2024-rust.md").unwrap(),
+            "/notes/2024/rust.md"
+        );
+        assert_eq!(resolve("/m/notes/rust.md"), None);
+        assert_eq!(resolve("/loop"), None);
+
+        assert!(Redirects::new(&[rule(Match::Regex, "(", "/")], &file).is_err());
+
+        // The rules file is picked up without a restart, and a broken one is ignored
+        fs::write(
+            &file,
+            "[[redirects]]\nfrom = \"/old\"\nto = \"/new\"\npermanent = true\n",
+        )
+        .unwrap();
+        assert_eq!(resolve("/old"), None);
+        redirects.reload();
+        assert_eq!(
+            redirects.resolve("/old").unwrap(),
+            ("/new".to_string(), true)
+        );
+
+        fs::write(&file, "[[redirects]]\nfrom = \"/old\"").unwrap();
+        redirects.file_rules.write().unwrap().modified = None;
+        redirects.reload();
+        assert_eq!(
+            redirects.resolve("/old").unwrap(),
+            ("/new".to_string(), true)
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_redirect_middleware() {
+        let dir = tempfile::tempdir().unwrap();
+        let redirects = Redirects::new(
+            &[
+                rule(Match::Prefix, "/old/", "/new/"),
+                RedirectRule {
+                    permanent: true,
+                    ..rule(Match::Exact, "/r/home", "/new/home")
+                },
+            ],
+            dir.path().join("redirects.toml"),
+        )
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(redirects)))
+                .wrap(from_fn(redirect_rules))
+                .route("/new/home", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for (uri, status, location) in [
+            ("/old/home?page=2", 302, Some("/new/home?page=2")),
+            ("/r/home", 301, Some("/new/home")),
+            ("/new/home", 200, None),
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), status, "{}", uri);
+            assert_eq!(
+                res.headers().get("location").map(|l| l.to_str().unwrap()),
+                location
+            );
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Login sessions, kept in a signed cookie.
+//!
+//! The session middleware is configured from `[session]` in `config.toml`:
+//!
+//! ```toml
+//! [session]
+//! key_file = "session.key"
+//! cookie_name = "session"
+//! secure = true
+//! same_site = "lax"
+//! idle_timeout = 86400
+//! absolute_timeout = 2592000
+//! ```
+//!
+//! The signing key is generated on the first start and saved so logins survive restarts, deleting
+//! the file logs everyone out.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+use crate::config::{SameSite, SessionConfig};
+use actix_identity::IdentityMiddleware;
+use actix_session::{
+    config::{BrowserSession, PersistentSession, SessionLifecycle},
+    storage::CookieSessionStore,
+    SessionMiddleware,
+};
+use actix_web::cookie::{self, Key};
+
+impl From<SameSite> for cookie::SameSite {
+    fn from(same_site: SameSite) -> Self {
+        match same_site {
+            SameSite::Strict => cookie::SameSite::Strict,
+            SameSite::Lax => cookie::SameSite::Lax,
+            SameSite::None => cookie::SameSite::None,
+        }
+    }
+}
+
+impl SessionConfig {
+    /// The middleware keeping sessions in a signed cookie
+    pub fn session_middleware(&self, key: Key) -> SessionMiddleware<CookieSessionStore> {
+        let lifecycle: SessionLifecycle = match self.absolute_timeout {
+            0 => BrowserSession::default().into(),
+            timeout => PersistentSession::default()
+                .session_ttl(cookie::time::Duration::seconds(timeout as i64))
+                .into(),
+        };
+
+        SessionMiddleware::builder(CookieSessionStore::default(), key)
+            .cookie_name(self.cookie_name.clone())
+            .cookie_secure(self.secure)
+            .cookie_same_site(self.same_site.into())
+            .cookie_http_only(true)
+            .session_lifecycle(lifecycle)
+            .build()
+    }
+
+    /// The middleware logging users out once their session times out
+    pub fn identity_middleware(&self) -> IdentityMiddleware {
+        let timeout = |seconds| match seconds {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        };
+
+        IdentityMiddleware::builder()
+            .visit_deadline(timeout(self.idle_timeout))
+            .login_deadline(timeout(self.absolute_timeout))
+            .build()
+    }
+}
+
+/// Loads the key signing session cookies from `path`, generating and saving one if it doesn't
+/// exist yet
+pub fn session_key(path: &Path) -> io::Result<Key> {
+    match fs::read(path) {
+        Ok(bytes) => Key::try_from(bytes.as_slice()).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                fs::create_dir_all(dir)?;
+            }
+
+            let key = Key::generate();
+            let mut file = fs::OpenOptions::new();
+            file.write(true).create_new(true);
+            // Only the site has any business reading it
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+            file.open(path)?.write_all(key.master())?;
+
+            Ok(key)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{cookie::Cookie, test, web::Data, App};
+
+    use super::*;
+    use crate::services::{
+        csrf::tests::csrf_session, shortener_service, user_service, AuditLog, LoginThrottle, Role,
+        ShortLinks, Users,
+    };
+
+    #[actix_web::test]
+    async fn test_session_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data/session.key");
+
+        let key = session_key(&path).unwrap();
+        assert_eq!(session_key(&path).unwrap().master(), key.master());
+
+        fs::write(&path, "too short").unwrap();
+        assert!(session_key(&path).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_login_logout() {
+        let dir = tempfile::tempdir().unwrap();
+        let users = Users::load(dir.path().join("users.toml"));
+        users.add("alex", "hunter2", Role::Admin).unwrap();
+        let links = ShortLinks::load(dir.path().join("shortlinks.json"));
+
+        let config: SessionConfig = toml::from_str(
+            r#"
+            cookie_name = "auth"
+            same_site = "strict"
+            "#,
+        )
+        .unwrap();
+        let key = session_key(&dir.path().join("session.key")).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(Arc::new(users)))
+                .app_data(Data::new(Arc::new(links)))
+                .app_data(Data::new(Arc::new(LoginThrottle::default())))
+                .app_data(Data::new(Arc::new(AuditLog::new(
+                    dir.path().join("audit.log"),
+                ))))
+                .wrap(config.identity_middleware())
+                .wrap(config.session_middleware(key))
+                .service(user_service())
+                .service(shortener_service()),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/u/").to_request();
+        let (cookie, token) = csrf_session(test::call_service(&app, req).await).await;
+        let login = |password: &str| {
+            test::TestRequest::post()
+                .uri("/u/login?redirect=/t/")
+                .cookie(cookie.clone())
+                .set_form([
+                    ("username", "alex"),
+                    ("password", password),
+                    ("csrf", &token),
+                ])
+                .to_request()
+        };
+        let manage = |cookie: Option<Cookie<'static>>| {
+            let req = test::TestRequest::get().uri("/t/");
+            match cookie {
+                Some(cookie) => req.cookie(cookie),
+                None => req,
+            }
+            .to_request()
+        };
+
+        let res = test::call_service(&app, login("wrong")).await;
+        assert_eq!(res.status(), 401);
+
+        let res = test::call_service(&app, login("hunter2")).await;
+        assert_eq!(res.status(), 302);
+        assert_eq!(res.headers().get("location").unwrap(), "/t/");
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+        assert_eq!(cookie.name(), "auth");
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.same_site(), Some(cookie::SameSite::Strict));
+
+        let res = test::call_service(&app, manage(Some(cookie.clone()))).await;
+        assert_eq!(res.status(), 200);
+
+        let req = test::TestRequest::post()
+            .uri("/u/logout")
+            .cookie(cookie)
+            .set_form([("csrf", &token)])
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 302);
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+        assert_eq!(cookie.value(), "");
+
+        let res = test::call_service(&app, manage(Some(cookie))).await;
+        assert_eq!(res.status(), 302);
+        assert_eq!(res.headers().get("location").unwrap(), "/u/?redirect=/t/");
+        let res = test::call_service(&app, manage(None)).await;
+        assert_eq!(res.status(), 302);
+    }
+
+    #[actix_web::test]
+    async fn test_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let users = Users::load(dir.path().join("users.toml"));
+        users.add("alex", "hunter2", Role::Admin).unwrap();
+        let links = ShortLinks::load(dir.path().join("shortlinks.json"));
+        let config = SessionConfig {
+            idle_timeout: 1,
+            ..SessionConfig::default()
+        };
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(Arc::new(users)))
+                .app_data(Data::new(Arc::new(links)))
+                .app_data(Data::new(Arc::new(LoginThrottle::default())))
+                .app_data(Data::new(Arc::new(AuditLog::new(
+                    dir.path().join("audit.log"),
+                ))))
+                .wrap(config.identity_middleware())
+                .wrap(config.session_middleware(Key::generate()))
+                .service(user_service())
+                .service(shortener_service()),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/u/").to_request();
+        let (cookie, token) = csrf_session(test::call_service(&app, req).await).await;
+        let req = test::TestRequest::post()
+            .uri("/u/login")
+            .cookie(cookie)
+            .set_form([
+                ("username", "alex"),
+                ("password", "hunter2"),
+                ("csrf", &token),
+            ])
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+
+        actix_web::rt::time::sleep(Duration::from_millis(1100)).await;
+        let req = test::TestRequest::get()
+            .uri("/t/")
+            .cookie(cookie)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 302);
+    }
+}
@@ -0,0 +1,481 @@
+//! Short links, `/t/{code}` redirects to a longer URL.
+//!
+//! Links are created by the admin from `/t/` with a random code or a custom alias, and are saved
+//! to `shortlinks.json` in the data directory so they survive restarts. Hits are only counted in
+//! memory by the redirects and written out by [`ShortLinks::flush`].
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use actix_session::Session;
+use actix_web::{
+    dev::HttpServiceFactory,
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    get,
+    middleware::from_fn,
+    post,
+    web::{self, redirect},
+    Either, HttpResponse, Responder,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use maud::{html, Markup, DOCTYPE};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::services::{csrf, store, Account, Role};
+
+/// Length of generated codes, 62^6 is plenty for one person
+const CODE_LENGTH: usize = 6;
+
+/// A short link and its statistics
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShortLink {
+    pub url: String,
+    /// Redirect with a 301 rather than a 302
+    #[serde(default)]
+    pub permanent: bool,
+    #[serde(default)]
+    pub hits: u64,
+    pub created: DateTime<Utc>,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl ShortLink {
+    pub fn new(url: &str) -> Self {
+        ShortLink {
+            url: url.to_string(),
+            permanent: false,
+            hits: 0,
+            created: Utc::now(),
+            expires: None,
+        }
+    }
+
+    pub fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// A link with a hit counter that redirects can bump without the write lock
+struct Counted {
+    link: ShortLink,
+    hits: AtomicU64,
+}
+
+impl Counted {
+    fn new(link: ShortLink) -> Self {
+        Counted {
+            hits: AtomicU64::new(link.hits),
+            link,
+        }
+    }
+
+    fn snapshot(&self) -> ShortLink {
+        ShortLink {
+            hits: self.hits.load(Ordering::Relaxed),
+            ..self.link.clone()
+        }
+    }
+}
+
+/// Every short link, keyed by code
+pub struct ShortLinks {
+    path: PathBuf,
+    links: RwLock<BTreeMap<String, Counted>>,
+    /// Whether there are hits that haven't been saved yet
+    dirty: AtomicBool,
+}
+
+impl ShortLinks {
+    /// Loads the links saved at `path`, starting without any if the file doesn't exist yet
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let links: BTreeMap<String, ShortLink> = store::load(&path);
+
+        ShortLinks {
+            path,
+            links: RwLock::new(
+                links
+                    .into_iter()
+                    .map(|(code, link)| (code, Counted::new(link)))
+                    .collect(),
+            ),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Writes the links to disk, replacing the file at once so a crash can't truncate it
+    fn save(&self, links: &BTreeMap<String, Counted>) -> io::Result<()> {
+        // Every hit up to here is in the snapshot
+        self.dirty.store(false, Ordering::Relaxed);
+        let links = links
+            .iter()
+            .map(|(code, link)| (code, link.snapshot()))
+            .collect::<BTreeMap<_, _>>();
+        store::save(&self.path, &links)
+    }
+
+    /// Saves the hits counted since the last save, if there are any
+    pub fn flush(&self) -> io::Result<()> {
+        let links = self.links.read().unwrap();
+        if !self.dirty.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        self.save(&links)
+            .inspect_err(|_| self.dirty.store(true, Ordering::Relaxed))
+    }
+
+    /// Adds a link under `alias`, or a random code if there isn't one, returning its code
+    pub fn create(&self, alias: Option<&str>, link: ShortLink) -> actix_web::Result<String> {
+        if !valid_url(&link.url) {
+            return Err(ErrorBadRequest(
+                "Links must be http(s) URLs or paths on this site",
+            ));
+        }
+
+        let mut links = self.links.write().unwrap();
+        let code = match alias {
+            Some(alias) if !valid_code(alias) => {
+                return Err(ErrorBadRequest(
+                    "Aliases may only contain letters, numbers, '-' and '_'",
+                ))
+            }
+            Some(alias) if links.contains_key(alias) => {
+                return Err(ErrorBadRequest("Alias already taken"))
+            }
+            Some(alias) => alias.to_string(),
+            None => loop {
+                let code = random_code();
+                if !links.contains_key(&code) {
+                    break code;
+                }
+            },
+        };
+
+        links.insert(code.clone(), Counted::new(link));
+        self.save(&links).map_err(ErrorInternalServerError)?;
+
+        Ok(code)
+    }
+
+    /// Counts a visit to a link, returning it unless it doesn't exist or has expired
+    ///
+    /// The hit is only kept in memory until the next [`ShortLinks::flush`].
+    pub fn hit(&self, code: &str) -> Option<ShortLink> {
+        let links = self.links.read().unwrap();
+        let counted = links
+            .get(code)
+            .filter(|counted| !counted.link.expired(Utc::now()))?;
+        let hits = counted.hits.fetch_add(1, Ordering::Relaxed) + 1;
+        self.dirty.store(true, Ordering::Relaxed);
+
+        Some(ShortLink {
+            hits,
+            ..counted.link.clone()
+        })
+    }
+
+    /// Removes a link, returning false if it didn't exist
+    pub fn delete(&self, code: &str) -> io::Result<bool> {
+        let mut links = self.links.write().unwrap();
+        if links.remove(code).is_none() {
+            return Ok(false);
+        }
+
+        self.save(&links).map(|_| true)
+    }
+
+    /// Every link, sorted by code
+    pub fn list(&self) -> Vec<(String, ShortLink)> {
+        self.links
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(code, counted)| (code.clone(), counted.snapshot()))
+            .collect()
+    }
+}
+
+fn random_code() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(CODE_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+fn valid_code(code: &str) -> bool {
+    !code.is_empty()
+        && code.len() <= 64
+        && code
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Only web links and paths on this site, `javascript:` and friends are rejected
+fn valid_url(url: &str) -> bool {
+    url.starts_with("https://")
+        || url.starts_with("http://")
+        || (url.starts_with('/') && !url.starts_with("//"))
+}
+
+/// URL shortener, the admin manages links from `/t/`
+pub fn shortener_service() -> impl HttpServiceFactory {
+    web::scope("/t")
+        .wrap(from_fn(csrf::protect))
+        .service(redirect("", "/t/"))
+        .service(index)
+        .service(create)
+        .service(delete)
+        .service(follow)
+}
+
+#[get("/")]
+async fn index(
+    user: Option<Account>,
+    session: Session,
+    links: web::Data<Arc<ShortLinks>>,
+) -> actix_web::Result<Either<HttpResponse, Markup>> {
+    // 302 redirect to login if not authenticated
+    if let Err(res) = Account::require_page(user, Role::Admin, "/t/") {
+        return Ok(Either::Left(res));
+    }
+    let token = csrf::token(&session)?;
+
+    let now = Utc::now();
+    Ok(Either::Right(html! {
+        (DOCTYPE)
+        (blog::header("Short links"))
+        main {
+            h1 { "Short links" }
+            form method="post" action="/t/" {
+                (csrf::field(&token))
+                input type="url" name="url" placeholder="https://example.com" required;
+                input type="text" name="alias" placeholder="Alias (optional)";
+                label { "Expires after " input type="date" name="expires"; }
+                label { input type="checkbox" name="permanent"; " Permanent (301)" }
+                button { "Shorten" }
+            }
+            table {
+                thead {
+                    tr { th { "Code" } th { "URL" } th { "Redirect" } th { "Hits" } th { "Created" } th { "Expires" } th {} }
+                }
+                tbody {
+                    @for (code, link) in links.list() {
+                        tr {
+                            td { a href=(format!("/t/{}", code)) { (code) } }
+                            td { (link.url) }
+                            td { @if link.permanent { "301" } @else { "302" } }
+                            td { (link.hits) }
+                            td { (link.created.format("%Y-%m-%d")) }
+                            td {
+                                @match link.expires {
+                                    Some(_) if link.expired(now) => "Expired",
+                                    Some(expires) => (expires.format("%Y-%m-%d %H:%M UTC")),
+                                    None => "Never",
+                                }
+                            }
+                            td {
+                                form method="post" action=(format!("/t/{}/delete", code)) {
+                                    (csrf::field(&token))
+                                    button { "Delete" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }))
+}
+
+/// The creation form, empty inputs are sent as empty strings
+#[derive(Debug, Deserialize)]
+struct CreateForm {
+    url: String,
+    alias: Option<String>,
+    /// The last day the link works
+    expires: Option<String>,
+    permanent: Option<String>,
+}
+
+#[post("/")]
+async fn create(
+    user: Option<Account>,
+    links: web::Data<Arc<ShortLinks>>,
+    form: web::Form<CreateForm>,
+) -> actix_web::Result<impl Responder> {
+    Account::require(user, Role::Admin)?;
+
+    let non_empty = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+
+    let expires = match non_empty(&form.expires) {
+        Some(date) => {
+            let date = date
+                .parse::<NaiveDate>()
+                .map_err(|_| ErrorBadRequest("Invalid expiry date"))?;
+            date.succ_opt()
+                .map(|next| next.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        }
+        None => None,
+    };
+
+    let link = ShortLink {
+        permanent: form.permanent.is_some(),
+        expires,
+        ..ShortLink::new(form.url.trim())
+    };
+    links.create(non_empty(&form.alias).as_deref(), link)?;
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("location", "/t/"))
+        .finish())
+}
+
+#[post("/{code}/delete")]
+async fn delete(
+    user: Option<Account>,
+    links: web::Data<Arc<ShortLinks>>,
+    code: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+    Account::require(user, Role::Admin)?;
+
+    if !links.delete(&code).map_err(ErrorInternalServerError)? {
+        return Err(ErrorNotFound("No such link"));
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("location", "/t/"))
+        .finish())
+}
+
+#[get("/{code}")]
+async fn follow(
+    links: web::Data<Arc<ShortLinks>>,
+    code: web::Path<String>,
+) -> actix_web::Result<impl Responder> {
+    let link = links.hit(&code).ok_or(ErrorNotFound("No such link"))?;
+
+    let mut response = match link.permanent {
+        true => HttpResponse::MovedPermanently(),
+        false => HttpResponse::Found(),
+    };
+    Ok(response.append_header(("location", link.url)).finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_identity::IdentityMiddleware;
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::{cookie::Key, test, App};
+    use chrono::Duration;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data/shortlinks.json");
+        let links = ShortLinks::load(&path);
+
+        let code = links
+            .create(None, ShortLink::new("https://example.com"))
+            .unwrap();
+        assert_eq!(code.len(), CODE_LENGTH);
+        assert!(code.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        links
+            .create(Some("gh"), ShortLink::new("/m/home.md"))
+            .unwrap();
+        assert!(links.create(Some("gh"), ShortLink::new("/")).is_err());
+        assert!(links.create(Some("a/b"), ShortLink::new("/")).is_err());
+        assert!(links
+            .create(None, ShortLink::new("javascript:alert(1)"))
+            .is_err());
+
+        let expired = ShortLink {
+            expires: Some(Utc::now() - Duration::seconds(1)),
+            ..ShortLink::new("/")
+        };
+        links.create(Some("old"), expired).unwrap();
+        assert!(links.hit("old").is_none());
+
+        links.hit("gh").unwrap();
+        assert_eq!(links.hit("gh").unwrap().hits, 2);
+
+        // Hits are only written out by a flush
+        assert_eq!(ShortLinks::load(&path).hit("gh").unwrap().hits, 1);
+        links.flush().unwrap();
+        assert_eq!(ShortLinks::load(&path).hit("gh").unwrap().hits, 3);
+
+        assert_eq!(links.list().len(), 3);
+        assert!(links.delete("gh").unwrap());
+        assert!(!links.delete("gh").unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_shortener() {
+        let dir = tempfile::tempdir().unwrap();
+        let links = Arc::new(ShortLinks::load(dir.path().join("shortlinks.json")));
+        links
+            .create(Some("temp"), ShortLink::new("/m/home.md"))
+            .unwrap();
+        let permanent = ShortLink {
+            permanent: true,
+            ..ShortLink::new("https://example.com")
+        };
+        links.create(Some("perm"), permanent).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(links.clone()))
+                .wrap(IdentityMiddleware::default())
+                .wrap(SessionMiddleware::new(
+                    CookieSessionStore::default(),
+                    Key::generate(),
+                ))
+                .service(shortener_service()),
+        )
+        .await;
+
+        for (uri, status, location) in [
+            ("/t/temp", 302, Some("/m/home.md")),
+            ("/t/perm", 301, Some("https://example.com")),
+            ("/t/nope", 404, None),
+            ("/t/", 302, Some("/u/?redirect=/t/")),
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), status, "{}", uri);
+            assert_eq!(
+                res.headers().get("location").map(|l| l.to_str().unwrap()),
+                location
+            );
+        }
+
+        // Only the admin can manage links, from a form of their session
+        let req = test::TestRequest::post()
+            .uri("/t/")
+            .set_form([("url", "https://example.com")])
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+        let req = test::TestRequest::post().uri("/t/temp/delete").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+        assert_eq!(links.hit("temp").unwrap().hits, 2);
+    }
+}
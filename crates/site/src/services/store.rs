@@ -0,0 +1,81 @@
+//! Loading and saving the files in the data directory.
+//!
+//! Files ending in `.toml` are written as TOML so they're easy to edit by hand, anything else as
+//! JSON.
+
+use std::{fs, io, path::Path};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+fn is_toml(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "toml")
+}
+
+/// Loads the file at `path`, starting from the default if it doesn't exist yet
+pub(crate) fn load<T: DeserializeOwned + Default>(path: &Path) -> T {
+    let file = match fs::read_to_string(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return T::default(),
+        Err(e) => panic!("Could not open {}: {}", path.display(), e),
+    };
+
+    let value = match is_toml(path) {
+        true => toml::from_str(&file).map_err(|e| e.to_string()),
+        false => serde_json::from_str(&file).map_err(|e| e.to_string()),
+    };
+    value.unwrap_or_else(|e| panic!("Failed to parse {}: {}", path.display(), e))
+}
+
+/// Writes `value` to `path`, replacing the file at once so a crash can't truncate it
+pub(crate) fn save<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+
+    let file = match is_toml(path) {
+        true => toml::to_string_pretty(value).map_err(io::Error::other)?,
+        false => serde_json::to_string_pretty(value)?,
+    };
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, file)?;
+    fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Counts {
+        counts: BTreeMap<String, u64>,
+    }
+
+    #[actix_web::test]
+    async fn test_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let counts = Counts {
+            counts: [("a".to_string(), 1), ("b".to_string(), 2)].into(),
+        };
+
+        for name in ["counts.json", "counts.toml"] {
+            let path = dir.path().join("data").join(name);
+            assert_eq!(load::<Counts>(&path), Counts::default());
+
+            save(&path, &counts).unwrap();
+            assert_eq!(load::<Counts>(&path), counts);
+            assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+            fs::remove_file(path).unwrap();
+        }
+
+        let file = dir.path().join("data/counts.toml");
+        save(&file, &counts).unwrap();
+        assert!(fs::read_to_string(file).unwrap().contains("[counts]"));
+    }
+}
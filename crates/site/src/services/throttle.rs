@@ -0,0 +1,176 @@
+//! Throttling of login attempts.
+//!
+//! Login attempts are counted for both the client's address and the username tried. After a few
+//! free attempts each further one doubles the wait before the next, up to a cap, so guessing
+//! passwords gets slow without locking anyone out for long. An attempt is counted before the
+//! password is checked, so guesses sent in parallel can't all get through while the first ones
+//! are being hashed, and the wait runs from when it failed. Attempts are forgotten after an hour of quiet, or for good on a successful
+//! login.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Failures allowed before having to wait
+const FREE_ATTEMPTS: u32 = 5;
+
+/// The wait after the first failure past the free ones
+pub(crate) const BASE_DELAY: Duration = Duration::from_secs(1);
+
+const MAX_DELAY: Duration = Duration::from_secs(15 * 60);
+
+/// How long failures are remembered for
+const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Ip(IpAddr),
+    Username(String),
+}
+
+fn keys(ip: Option<IpAddr>, username: &str) -> impl Iterator<Item = Key> {
+    ip.map(Key::Ip)
+        .into_iter()
+        .chain([Key::Username(username.to_string())])
+}
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+impl Failures {
+    /// When the next attempt is allowed
+    fn retry_at(&self) -> Instant {
+        let delay = match self.count.checked_sub(FREE_ATTEMPTS + 1) {
+            None => Duration::ZERO,
+            Some(doublings) => BASE_DELAY
+                .checked_mul(2u32.saturating_pow(doublings))
+                .unwrap_or(MAX_DELAY)
+                .min(MAX_DELAY),
+        };
+        self.last + delay
+    }
+}
+
+/// Failed login attempts by address and username
+#[derive(Debug, Default)]
+pub struct LoginThrottle {
+    failures: Mutex<HashMap<Key, Failures>>,
+}
+
+impl LoginThrottle {
+    /// Counts an attempt of the client at `ip` to log in as `username`, or returns how long it has
+    /// to wait before it can try
+    pub fn reserve(&self, ip: Option<IpAddr>, username: &str) -> Result<(), Duration> {
+        self.reserve_at(ip, username, Instant::now())
+    }
+
+    pub(crate) fn reserve_at(
+        &self,
+        ip: Option<IpAddr>,
+        username: &str,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, failures| now.duration_since(failures.last) < FORGET_AFTER);
+
+        let wait = keys(ip, username)
+            .filter_map(|key| failures.get(&key))
+            .map(|failures| failures.retry_at().saturating_duration_since(now))
+            .max()
+            .unwrap_or_default();
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for key in keys(ip, username) {
+            let failures = failures.entry(key).or_insert(Failures {
+                count: 0,
+                last: now,
+            });
+            failures.count += 1;
+            failures.last = now;
+        }
+
+        Ok(())
+    }
+
+    /// Starts the wait of a client whose attempt failed once it has been checked
+    pub fn failed(&self, ip: Option<IpAddr>, username: &str) {
+        self.failed_at(ip, username, Instant::now())
+    }
+
+    fn failed_at(&self, ip: Option<IpAddr>, username: &str, now: Instant) {
+        let mut failures = self.failures.lock().unwrap();
+        for key in keys(ip, username) {
+            if let Some(failures) = failures.get_mut(&key) {
+                failures.last = failures.last.max(now);
+            }
+        }
+    }
+
+    /// Forgets the attempts of a client that logged in
+    pub fn succeeded(&self, ip: Option<IpAddr>, username: &str) {
+        let mut failures = self.failures.lock().unwrap();
+        for key in keys(ip, username) {
+            failures.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_backoff() {
+        let throttle = LoginThrottle::default();
+        let ip = Some("203.0.113.5".parse().unwrap());
+        let other = Some("198.51.100.1".parse().unwrap());
+        let start = Instant::now();
+
+        for _ in 0..=FREE_ATTEMPTS {
+            assert_eq!(throttle.reserve_at(ip, "alex", start), Ok(()));
+        }
+
+        // Each attempt past the free ones doubles the wait, waiting isn't an attempt
+        assert_eq!(throttle.reserve_at(ip, "alex", start), Err(BASE_DELAY));
+        assert_eq!(throttle.reserve_at(ip, "alex", start), Err(BASE_DELAY));
+        let later = start + BASE_DELAY;
+        assert_eq!(throttle.reserve_at(ip, "alex", later), Ok(()));
+        assert_eq!(throttle.reserve_at(ip, "alex", later), Err(BASE_DELAY * 2));
+
+        // Both the address and the username are throttled
+        assert!(throttle.reserve_at(ip, "sam", later).is_err());
+        assert!(throttle.reserve_at(other, "alex", later).is_err());
+        assert_eq!(throttle.reserve_at(other, "sam", later), Ok(()));
+
+        // The wait runs from when the attempt failed, however long checking it took
+        let slow = later + BASE_DELAY * 3;
+        throttle.failed_at(ip, "alex", slow);
+        assert_eq!(throttle.reserve_at(ip, "alex", slow), Err(BASE_DELAY * 2));
+
+        let mut now = slow;
+        for _ in 0..32 {
+            while let Err(wait) = throttle.reserve_at(ip, "alex", now) {
+                now += wait;
+            }
+        }
+        assert_eq!(throttle.reserve_at(ip, "alex", now), Err(MAX_DELAY));
+
+        // Quiet clients are forgotten as soon as anyone tries again
+        let forgotten = now + FORGET_AFTER;
+        assert_eq!(throttle.reserve_at(other, "jo", forgotten), Ok(()));
+        assert_eq!(throttle.failures.lock().unwrap().len(), 2);
+
+        throttle.reserve_at(ip, "alex", forgotten).unwrap();
+        throttle.succeeded(ip, "alex");
+        assert_eq!(throttle.failures.lock().unwrap().len(), 2);
+        assert_eq!(throttle.reserve_at(ip, "alex", forgotten), Ok(()));
+    }
+}
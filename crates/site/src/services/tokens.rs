@@ -0,0 +1,326 @@
+//! API tokens, for scripts to use the site without logging in.
+//!
+//! Tokens are issued by the admin from `/a/` on behalf of an account, and sent in an
+//! `Authorization: Bearer` header. Each is limited to the services of its scopes and may expire.
+//! They're saved to `tokens.json` in the data directory as SHA-256 hashes, the token itself is only
+//! shown once when it's created.
+//!
+//! Requests with a token get the [`Account`](super::Account) of its owner, with the role the
+//! account has now, so handlers don't need to tell them apart.
+
+use std::{collections::BTreeMap, fmt, io, path::PathBuf, sync::RwLock};
+
+use actix_web::error::{
+    ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized,
+};
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::services::store;
+
+/// Every token starts with this, so they're easy to spot in scripts and leaked secrets
+const PREFIX: &str = "mb";
+
+/// Length of the public part of a token, used to find it
+const ID_LENGTH: usize = 8;
+
+/// Length of the secret part of a token
+const SECRET_LENGTH: usize = 32;
+
+/// How stale the last use of a token can get before it's saved, so busy scripts don't write the
+/// file on every request
+const LAST_USED_PRECISION: Duration = Duration::minutes(1);
+
+/// A service a token can be used with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Uploading to `/f/`
+    Files,
+    /// Pixel art, including managing the cache from `/a/pixel/`
+    Pixel,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 2] = [Scope::Files, Scope::Pixel];
+
+    /// The paths of the service
+    fn prefixes(self) -> &'static [&'static str] {
+        match self {
+            Scope::Files => &["/f"],
+            Scope::Pixel => &["/pixel", "/a/pixel"],
+        }
+    }
+
+    /// Whether `path` belongs to the service
+    pub fn allows(self, path: &str) -> bool {
+        self.prefixes().iter().any(|prefix| {
+            path.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Files => write!(f, "files"),
+            Scope::Pixel => write!(f, "pixel"),
+        }
+    }
+}
+
+/// A token as saved in `tokens.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    /// What the token is for
+    pub name: String,
+    /// The account the token acts as
+    pub username: String,
+    pub scopes: Vec<Scope>,
+    /// The hex SHA-256 hash of the secret part
+    hash: String,
+    pub created: DateTime<Utc>,
+    pub expires: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    pub fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// Every token, keyed by ID
+pub struct ApiTokens {
+    path: PathBuf,
+    tokens: RwLock<BTreeMap<String, ApiToken>>,
+}
+
+fn random(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+/// Hashing once is enough, unlike passwords the secrets are long and random
+fn hash(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+impl ApiTokens {
+    /// Loads the tokens saved at `path`, starting without any if the file doesn't exist yet
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        ApiTokens {
+            tokens: RwLock::new(store::load(&path)),
+            path,
+        }
+    }
+
+    /// Writes the tokens to disk, replacing the file at once so a crash can't truncate it
+    fn save(&self, tokens: &BTreeMap<String, ApiToken>) -> io::Result<()> {
+        store::save(&self.path, tokens)
+    }
+
+    /// Issues a token acting as `username`, returning the token to hand over
+    pub fn create(
+        &self,
+        name: &str,
+        username: &str,
+        scopes: Vec<Scope>,
+        expires: Option<DateTime<Utc>>,
+    ) -> actix_web::Result<String> {
+        if name.is_empty() {
+            return Err(ErrorBadRequest("Tokens need a name"));
+        }
+        if scopes.is_empty() {
+            return Err(ErrorBadRequest("Tokens need at least one scope"));
+        }
+
+        let mut tokens = self.tokens.write().unwrap();
+        let id = loop {
+            let id = random(ID_LENGTH);
+            if !tokens.contains_key(&id) {
+                break id;
+            }
+        };
+        let secret = random(SECRET_LENGTH);
+
+        tokens.insert(
+            id.clone(),
+            ApiToken {
+                name: name.to_string(),
+                username: username.to_string(),
+                scopes,
+                hash: hash(&secret),
+                created: Utc::now(),
+                expires,
+                last_used: None,
+            },
+        );
+        self.save(&tokens).map_err(ErrorInternalServerError)?;
+
+        Ok(format!("{}_{}_{}", PREFIX, id, secret))
+    }
+
+    /// Deletes a token, returning false if it didn't exist
+    pub fn revoke(&self, id: &str) -> io::Result<bool> {
+        let mut tokens = self.tokens.write().unwrap();
+        if tokens.remove(id).is_none() {
+            return Ok(false);
+        }
+
+        self.save(&tokens).map(|_| true)
+    }
+
+    /// Deletes every token acting as `username`, returning how many there were
+    pub fn revoke_all(&self, username: &str) -> io::Result<usize> {
+        let mut tokens = self.tokens.write().unwrap();
+        let before = tokens.len();
+        tokens.retain(|_, token| token.username != username);
+        let revoked = before - tokens.len();
+        if revoked == 0 {
+            return Ok(0);
+        }
+
+        self.save(&tokens).map(|_| revoked)
+    }
+
+    /// Every token, sorted by ID
+    pub fn list(&self) -> Vec<(String, ApiToken)> {
+        self.tokens
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(id, token)| (id.clone(), token.clone()))
+            .collect()
+    }
+
+    /// Checks `token` can be used for `path`, returning the username it acts as. Unknown and
+    /// expired tokens get a 401 and ones for other services a 403.
+    pub fn authenticate(&self, token: &str, path: &str) -> actix_web::Result<String> {
+        let invalid = || ErrorUnauthorized("Invalid API token");
+        let mut parts = token.splitn(3, '_');
+        let (Some(PREFIX), Some(id), Some(secret)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+
+        let now = Utc::now();
+        let (username, stale) = {
+            let tokens = self.tokens.read().unwrap();
+            let api_token = tokens.get(id).ok_or_else(invalid)?;
+            // Timing can only give away the hash, which doesn't help find the secret
+            if hash(secret) != api_token.hash {
+                return Err(invalid());
+            }
+            if api_token.expired(now) {
+                return Err(ErrorUnauthorized("API token expired"));
+            }
+            if !api_token.scopes.iter().any(|scope| scope.allows(path)) {
+                return Err(ErrorForbidden("API token not valid for this service"));
+            }
+
+            let stale = match api_token.last_used {
+                Some(last_used) => now - last_used >= LAST_USED_PRECISION,
+                None => true,
+            };
+            (api_token.username.clone(), stale)
+        };
+
+        // Most requests don't need to write anything, only those that make `last_used` stale
+        if stale {
+            let mut tokens = self.tokens.write().unwrap();
+            if let Some(api_token) = tokens.get_mut(id) {
+                api_token.last_used = Some(now);
+                // The request can go ahead without it
+                if let Err(e) = self.save(&tokens) {
+                    warn!("Failed to save {}: {}", self.path.display(), e);
+                }
+            }
+        }
+
+        Ok(username)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data/tokens.json");
+        let tokens = ApiTokens::load(&path);
+
+        let token = tokens
+            .create("backup", "alex", vec![Scope::Files], None)
+            .unwrap();
+        assert!(token.starts_with("mb_"));
+        assert!(tokens.create("", "alex", vec![Scope::Files], None).is_err());
+        assert!(tokens.create("nothing", "alex", vec![], None).is_err());
+        let expired = tokens
+            .create(
+                "old",
+                "alex",
+                vec![Scope::Pixel],
+                Some(Utc::now() - Duration::days(1)),
+            )
+            .unwrap();
+
+        // Only the hash is saved
+        let json = fs::read_to_string(&path).unwrap();
+        let secret = token.rsplit('_').next().unwrap();
+        assert!(json.contains(&hash(secret)) && !json.contains(secret));
+
+        // Tokens remember when they were used
+        assert_eq!(tokens.authenticate(&token, "/f/").unwrap(), "alex");
+        assert_eq!(tokens.authenticate(&token, "/f").unwrap(), "alex");
+        let status = |res: actix_web::Result<String>| {
+            res.unwrap_err().as_response_error().status_code().as_u16()
+        };
+        assert_eq!(status(tokens.authenticate(&token, "/files")), 403);
+        assert_eq!(status(tokens.authenticate(&token, "/a/")), 403);
+        assert_eq!(status(tokens.authenticate(&expired, "/pixel/")), 401);
+        assert_eq!(
+            status(tokens.authenticate(&format!("{}x", token), "/f/")),
+            401
+        );
+        assert_eq!(status(tokens.authenticate("hunter2", "/f/")), 401);
+
+        let (id, backup) = tokens
+            .list()
+            .into_iter()
+            .find(|(_, token)| token.name == "backup")
+            .unwrap();
+        assert!(backup.last_used.is_some());
+
+        assert!(tokens.revoke(&id).unwrap());
+        assert!(!tokens.revoke(&id).unwrap());
+        assert_eq!(status(tokens.authenticate(&token, "/f/")), 401);
+
+        tokens
+            .create("sync", "sam", vec![Scope::Files], None)
+            .unwrap();
+        assert_eq!(tokens.revoke_all("alex").unwrap(), 1);
+        assert_eq!(tokens.list().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_scopes() {
+        assert!(Scope::Pixel.allows("/pixel/upload"));
+        assert!(Scope::Pixel.allows("/a/pixel/purge"));
+        assert!(!Scope::Pixel.allows("/a/files/delete"));
+        assert!(!Scope::Files.allows("/"));
+    }
+}
@@ -0,0 +1,167 @@
+//! Two-factor authentication with time-based one-time passwords (RFC 6238).
+//!
+//! Users enrol from `/u/2fa` by scanning a QR code into an authenticator app and typing back a
+//! code. They also get a handful of recovery codes, each good for one login, in case they lose
+//! their phone. From then on logging in asks for a code after the password.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use qrcode::{render::svg, QrCode};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+/// Seconds each code is valid for
+const STEP: u64 = 30;
+
+/// How many recovery codes users get
+const RECOVERY_CODES: usize = 10;
+
+/// The second factor of an account, as saved in `users.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TwoFactor {
+    /// The base32 encoded TOTP secret
+    pub(crate) secret: String,
+    /// The Argon2 hashes of the unused recovery codes
+    #[serde(default)]
+    pub(crate) recovery_codes: Vec<String>,
+    /// The time step of the last code used, so a code can't be used twice
+    #[serde(default)]
+    pub(crate) last_step: u64,
+}
+
+/// A new random secret, base32 encoded
+pub fn new_secret() -> String {
+    let bytes: [u8; 20] = rand::thread_rng().gen();
+    match Secret::Raw(bytes.to_vec()).to_encoded() {
+        Secret::Encoded(secret) => secret,
+        Secret::Raw(_) => unreachable!(),
+    }
+}
+
+/// New recovery codes, to show the user once
+pub fn new_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let code = rand::thread_rng()
+                .sample_iter(Alphanumeric)
+                .take(10)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect::<String>();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are compared without the dash or case, which are easy to get wrong
+pub(crate) fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// The issuer authenticator apps list the account under, the host of the site's URL. The otpauth
+/// label is `issuer:username`, so it can't hold a port.
+fn issuer(site_url: &str) -> &str {
+    let url = site_url
+        .split_once("://")
+        .map_or(site_url, |(_, rest)| rest);
+    url.split(['/', ':']).next().unwrap_or_default()
+}
+
+fn totp(secret: &str, site_url: &str, username: &str) -> Option<TOTP> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+    let issuer = issuer(site_url);
+
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        0,
+        STEP,
+        secret,
+        Some(issuer.to_string()),
+        username.to_string(),
+    )
+    .ok()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+/// An SVG QR code of the `otpauth://` URL authenticator apps scan to add the account
+pub fn qr_code(secret: &str, site_url: &str, username: &str) -> Option<String> {
+    let url = totp(secret, site_url, username)?.get_url();
+    let code = QrCode::new(url).ok()?;
+
+    Some(
+        code.render::<svg::Color>()
+            .min_dimensions(200, 200)
+            .quiet_zone(true)
+            .build(),
+    )
+}
+
+/// The time step of `code` if it's valid now, allowing for a step of clock drift either way
+pub(crate) fn check_code(secret: &str, code: &str) -> Option<u64> {
+    check_code_at(secret, code, now())
+}
+
+fn check_code_at(secret: &str, code: &str, time: u64) -> Option<u64> {
+    let totp = totp(secret, "", "")?;
+    let code = code.trim();
+
+    [time.saturating_sub(STEP), time, time + STEP]
+        .into_iter()
+        .find(|&time| totp.check(code, time))
+        .map(|time| time / STEP)
+}
+
+/// The code for `secret` right now
+#[cfg(test)]
+pub(crate) fn current_code(secret: &str) -> String {
+    totp(secret, "", "").unwrap().generate(now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_codes() {
+        let secret = new_secret();
+        let totp = totp(&secret, "https://example.com:8080/", "alex").unwrap();
+        assert!(totp
+            .get_url()
+            .starts_with("otpauth://totp/example.com:alex?secret="));
+
+        let time = 1_000_000_020;
+        let step = time / STEP;
+        assert_eq!(
+            check_code_at(&secret, &totp.generate(time), time),
+            Some(step)
+        );
+        assert_eq!(
+            check_code_at(&secret, &totp.generate(time - STEP), time),
+            Some(step - 1)
+        );
+        assert_eq!(
+            check_code_at(&secret, &totp.generate(time - 2 * STEP), time),
+            None
+        );
+        assert_eq!(check_code_at(&secret, "", time), None);
+        assert_eq!(check_code_at("not base32!", "123456", time), None);
+
+        assert_eq!(issuer("example.com"), "example.com");
+        let svg = qr_code(&secret, "https://example.com", "alex").unwrap();
+        assert!(svg.contains("<svg"));
+
+        let codes = new_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert_eq!(normalize_recovery_code(&codes[0].to_uppercase()).len(), 10);
+    }
+}
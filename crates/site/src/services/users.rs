@@ -1,31 +1,84 @@
 use std::sync::Arc;
 
 use actix_identity::Identity;
+use actix_session::Session;
 use actix_web::{
     dev::HttpServiceFactory,
-    error::ErrorInternalServerError,
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorUnauthorized},
+    middleware::from_fn,
     post,
     web::{self, redirect},
-    HttpMessage, HttpRequest, HttpResponse, Responder,
+    Either, HttpMessage, HttpRequest, HttpResponse, Responder,
 };
-use maud::{html, DOCTYPE};
+use chrono::Utc;
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+use serde::{Deserialize, Serialize};
 
-use crate::config::Config;
+use crate::{
+    config::Config,
+    services::{
+        csrf,
+        two_factor::{self, check_code},
+        who::client_ip,
+        Account, AuditEvent, AuditLog, LoginThrottle, Role, SecondFactor, Users,
+    },
+};
+
+/// Where a login waiting for its second factor is kept in the session
+const PENDING_LOGIN: &str = "pending_login";
+
+/// Seconds to type the code in after the password
+const PENDING_TIMEOUT: i64 = 5 * 60;
+
+/// Where the secret being set up is kept in the session, until the user proves they've saved it
+const TWO_FACTOR_SETUP: &str = "two_factor_setup";
 
 pub fn user_service() -> impl HttpServiceFactory {
     web::scope("/u")
+        .wrap(from_fn(csrf::protect))
         .service(web::resource("/login").route(web::post().to(login)))
+        .service(
+            web::resource("/code")
+                .route(web::get().to(code_page))
+                .route(web::post().to(login_code)),
+        )
         .service(logout)
+        .service(web::resource("/2fa").route(web::get().to(two_factor_page)))
+        .service(enable_two_factor)
+        .service(disable_two_factor)
         .service(web::resource("/").route(web::get().to(index)))
         .service(redirect("", "/u/"))
 }
 
-async fn index(user: Option<Identity>, query: web::Query<RedirectQuery>) -> impl Responder {
-    if let Some(user) = user {
+async fn index(
+    user: Option<Account>,
+    session: Session,
+    users: web::Data<Arc<Users>>,
+    query: web::Query<RedirectQuery>,
+) -> actix_web::Result<Markup> {
+    let token = csrf::token(&session)?;
+
+    Ok(if let Some(user) = user {
+        let has_two_factor = users
+            .get(&user.username)
+            .is_some_and(|user| user.has_two_factor());
+
         html! {
             (DOCTYPE)
-            h1 { "Welcome, " (user.id().unwrap()) "!" }
+            h1 { "Welcome, " (user.username) "!" }
+            p { "You're logged in as " (user.role) "." }
+            @if has_two_factor {
+                p { "Two-factor authentication is on." }
+                form method="post" action="/u/2fa/disable" {
+                    (csrf::field(&token))
+                    input type="text" name="code" placeholder="Code" autocomplete="one-time-code" required;
+                    button { "Turn off" }
+                }
+            } @else {
+                p { a href="/u/2fa" { "Set up two-factor authentication" } }
+            }
             form method="post" action="/u/logout" {
+                (csrf::field(&token))
                 button { "Logout" }
             }
         }
@@ -34,12 +87,13 @@ async fn index(user: Option<Identity>, query: web::Query<RedirectQuery>) -> impl
             (DOCTYPE)
             h1 { "Please login" }
             form method="post" action=(format!("/u/login?redirect={}", query.redirect())) {
+                (csrf::field(&token))
                 input type="text" name="username" placeholder="Username";
                 input type="password" name="password" placeholder="Password";
                 button { "Login" }
             }
         }
-    }
+    })
 }
 
 #[derive(serde::Deserialize)]
@@ -48,36 +102,328 @@ struct LoginReq {
     password: String,
 }
 
+/// A login with the right password, waiting for the code of an account with two-factor
+/// authentication
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingLogin {
+    username: String,
+    redirect: String,
+    /// Unix time after which the password has to be entered again
+    expires: i64,
+}
+
+fn too_many_attempts(wait: std::time::Duration) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .append_header(("retry-after", wait.as_secs().max(1).to_string()))
+        .body("Too many failed logins, try again later")
+}
+
 /// The login endpoint is a POST request that takes a username and password as json in the request body.
 ///
 /// A query parameter is also accepted, including a redirect URL to send the user to after a successful login.
 ///
 /// If the username and password are correct, then the user's identity is attached to the active session.
+/// Accounts with two-factor authentication are sent to enter their code first.
+/// Clients that keep getting it wrong have to wait longer and longer between attempts.
 async fn login(
     request: HttpRequest,
-    config: web::Data<Arc<Config>>,
+    session: Session,
+    users: web::Data<Arc<Users>>,
+    throttle: web::Data<Arc<LoginThrottle>>,
+    audit: web::Data<Arc<AuditLog>>,
     login: web::Form<LoginReq>,
     query: web::Query<RedirectQuery>,
 ) -> actix_web::Result<impl Responder> {
-    // user sends a username and password as json
-    if config.check_admin(&login.username, &login.password) {
+    let ip = client_ip(&request);
+    let username = login.username.clone();
+    if let Err(wait) = throttle.reserve(ip, &username) {
+        audit.record(AuditEvent::Throttled, &username, ip);
+        return Ok(too_many_attempts(wait));
+    }
+
+    // Hashing is slow on purpose, keep it off the worker
+    let login = login.into_inner();
+    let user = web::block(move || users.verify(&login.username, &login.password))
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let Some(user) = user else {
+        throttle.failed(ip, &username);
+        audit.record(AuditEvent::LoginFailed, &username, ip);
         return Ok(HttpResponse::Unauthorized().finish());
+    };
+
+    if user.has_two_factor() {
+        // Attempts are only forgotten once the code is right too
+        let pending = PendingLogin {
+            username: user.username,
+            redirect: query.redirect(),
+            expires: Utc::now().timestamp() + PENDING_TIMEOUT,
+        };
+        session
+            .insert(PENDING_LOGIN, pending)
+            .map_err(ErrorInternalServerError)?;
+
+        return Ok(HttpResponse::Found()
+            .append_header(("location", "/u/code"))
+            .finish());
     }
 
+    throttle.succeeded(ip, &username);
+    audit.record(AuditEvent::Login, &username, ip);
+
     // attach a verified user identity to the active session
-    Identity::login(&request.extensions(), "admin".into()).map_err(ErrorInternalServerError)?;
+    Identity::login(&request.extensions(), user.username).map_err(ErrorInternalServerError)?;
 
     Ok(HttpResponse::Found()
         .append_header(("location", query.redirect()))
         .finish())
 }
 
+/// The login waiting for a code, if it hasn't expired
+fn pending_login(session: &Session) -> actix_web::Result<Option<PendingLogin>> {
+    let pending = session
+        .get::<PendingLogin>(PENDING_LOGIN)
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(pending.filter(|pending| pending.expires > Utc::now().timestamp()))
+}
+
+async fn code_page(session: Session) -> actix_web::Result<Either<HttpResponse, Markup>> {
+    if pending_login(&session)?.is_none() {
+        return Ok(Either::Left(
+            HttpResponse::Found()
+                .append_header(("location", "/u/"))
+                .finish(),
+        ));
+    }
+    let token = csrf::token(&session)?;
+
+    Ok(Either::Right(html! {
+        (DOCTYPE)
+        h1 { "Two-factor authentication" }
+        p { "Enter the code from your authenticator app, or one of your recovery codes." }
+        form method="post" action="/u/code" {
+            (csrf::field(&token))
+            input type="text" name="code" placeholder="Code" autocomplete="one-time-code" autofocus required;
+            button { "Login" }
+        }
+    }))
+}
+
+#[derive(serde::Deserialize)]
+struct CodeReq {
+    code: String,
+}
+
+/// The second step of logging in to an account with two-factor authentication
+async fn login_code(
+    request: HttpRequest,
+    session: Session,
+    users: web::Data<Arc<Users>>,
+    throttle: web::Data<Arc<LoginThrottle>>,
+    audit: web::Data<Arc<AuditLog>>,
+    form: web::Form<CodeReq>,
+) -> actix_web::Result<impl Responder> {
+    let Some(pending) = pending_login(&session)? else {
+        session.remove(PENDING_LOGIN);
+        return Err(ErrorUnauthorized(
+            "The login expired, enter your password again",
+        ));
+    };
+
+    let ip = client_ip(&request);
+    let username = pending.username.clone();
+    if let Err(wait) = throttle.reserve(ip, &username) {
+        audit.record(AuditEvent::Throttled, &username, ip);
+        return Ok(too_many_attempts(wait));
+    }
+
+    // Recovery codes are hashed like passwords
+    let code = form.into_inner().code;
+    let factor = web::block(move || users.verify_code(&pending.username, &code))
+        .await
+        .map_err(ErrorInternalServerError)?
+        .map_err(ErrorInternalServerError)?;
+    let Some(factor) = factor else {
+        throttle.failed(ip, &username);
+        audit.record(AuditEvent::SecondFactorFailed, &username, ip);
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
+
+    session.remove(PENDING_LOGIN);
+    throttle.succeeded(ip, &username);
+    if factor == SecondFactor::RecoveryCode {
+        audit.record(AuditEvent::RecoveryCodeUsed, &username, ip);
+    }
+    audit.record(AuditEvent::Login, &username, ip);
+
+    Identity::login(&request.extensions(), username).map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Found()
+        .append_header(("location", pending.redirect))
+        .finish())
+}
+
 #[post("/logout")]
-async fn logout(user: Identity) -> impl Responder {
+async fn logout(
+    request: HttpRequest,
+    user: Identity,
+    audit: web::Data<Arc<AuditLog>>,
+) -> actix_web::Result<impl Responder> {
+    audit.record(
+        AuditEvent::Logout,
+        &user.id().unwrap_or_default(),
+        client_ip(&request),
+    );
     user.logout();
-    HttpResponse::Found()
+
+    Ok(HttpResponse::Found()
+        .append_header(("location", "/u/"))
+        .finish())
+}
+
+/// Enrolment in two-factor authentication, showing the QR code to scan
+async fn two_factor_page(
+    config: web::Data<Arc<Config>>,
+    user: Option<Account>,
+    session: Session,
+    users: web::Data<Arc<Users>>,
+) -> actix_web::Result<Either<HttpResponse, Markup>> {
+    // 302 redirect to login if not authenticated
+    let user = match Account::require_page(user, Role::Viewer, "/u/2fa") {
+        Ok(user) => user,
+        Err(res) => return Ok(Either::Left(res)),
+    };
+    if users
+        .get(&user.username)
+        .is_some_and(|user| user.has_two_factor())
+    {
+        return Ok(Either::Left(
+            HttpResponse::Found()
+                .append_header(("location", "/u/"))
+                .finish(),
+        ));
+    }
+
+    // Keep the same secret across reloads, in case it's been scanned already
+    let secret = match session
+        .get::<String>(TWO_FACTOR_SETUP)
+        .map_err(ErrorInternalServerError)?
+    {
+        Some(secret) => secret,
+        None => {
+            let secret = two_factor::new_secret();
+            session
+                .insert(TWO_FACTOR_SETUP, &secret)
+                .map_err(ErrorInternalServerError)?;
+            secret
+        }
+    };
+    // Named after the site rather than whichever host the request was sent to
+    let qr_code = two_factor::qr_code(&secret, &config.site_url, &user.username)
+        .ok_or_else(|| ErrorInternalServerError("Failed to make the QR code"))?;
+    let token = csrf::token(&session)?;
+
+    Ok(Either::Right(html! {
+        (DOCTYPE)
+        h1 { "Two-factor authentication" }
+        p { "Scan this QR code with your authenticator app, or enter the key by hand." }
+        (PreEscaped(qr_code))
+        p { code { (secret) } }
+        p { "Then enter the code it shows to turn it on." }
+        form method="post" action="/u/2fa/enable" {
+            (csrf::field(&token))
+            input type="text" name="code" placeholder="Code" autocomplete="one-time-code" required;
+            button { "Turn on" }
+        }
+    }))
+}
+
+#[post("/2fa/enable")]
+async fn enable_two_factor(
+    request: HttpRequest,
+    user: Option<Account>,
+    session: Session,
+    users: web::Data<Arc<Users>>,
+    audit: web::Data<Arc<AuditLog>>,
+    form: web::Form<CodeReq>,
+) -> actix_web::Result<Markup> {
+    let user = Account::require(user, Role::Viewer)?;
+
+    let secret = session
+        .get::<String>(TWO_FACTOR_SETUP)
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorBadRequest("Start setting up two-factor authentication again"))?;
+    if check_code(&secret, &form.code).is_none() {
+        return Err(ErrorBadRequest("Wrong code, check the time on your phone"));
+    }
+
+    let recovery_codes = two_factor::new_recovery_codes();
+    let enabled = {
+        let username = user.username.clone();
+        let recovery_codes = recovery_codes.clone();
+        web::block(move || users.enable_two_factor(&username, &secret, &recovery_codes))
+            .await
+            .map_err(ErrorInternalServerError)?
+            .map_err(ErrorInternalServerError)?
+    };
+    if !enabled {
+        return Err(ErrorUnauthorized("Unknown account"));
+    }
+    session.remove(TWO_FACTOR_SETUP);
+    audit.record(
+        AuditEvent::TwoFactorEnabled,
+        &user.username,
+        client_ip(&request),
+    );
+
+    Ok(html! {
+        (DOCTYPE)
+        h1 { "Two-factor authentication is on" }
+        p { "Keep these recovery codes somewhere safe. Each one can log you in once if you lose your phone, and they won't be shown again." }
+        ul {
+            @for code in recovery_codes {
+                li { code { (code) } }
+            }
+        }
+        p { a href="/u/" { "Done" } }
+    })
+}
+
+#[post("/2fa/disable")]
+async fn disable_two_factor(
+    request: HttpRequest,
+    user: Option<Account>,
+    users: web::Data<Arc<Users>>,
+    audit: web::Data<Arc<AuditLog>>,
+    form: web::Form<CodeReq>,
+) -> actix_web::Result<impl Responder> {
+    let user = Account::require(user, Role::Viewer)?;
+
+    // Someone at an unlocked computer shouldn't be able to turn it off
+    let disabled = {
+        let username = user.username.clone();
+        let code = form.into_inner().code;
+        web::block(move || match users.verify_code(&username, &code)? {
+            Some(_) => users.disable_two_factor(&username),
+            None => Ok(false),
+        })
+        .await
+        .map_err(ErrorInternalServerError)?
+        .map_err(ErrorInternalServerError)?
+    };
+    if !disabled {
+        return Err(ErrorBadRequest("Wrong code"));
+    }
+    audit.record(
+        AuditEvent::TwoFactorDisabled,
+        &user.username,
+        client_ip(&request),
+    );
+
+    Ok(HttpResponse::SeeOther()
         .append_header(("location", "/u/"))
-        .finish()
+        .finish())
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -86,7 +432,275 @@ struct RedirectQuery {
 }
 
 impl RedirectQuery {
+    /// Where to go after logging in. Only paths on this site are allowed, so links to the login
+    /// page can't send people elsewhere.
     fn redirect(&self) -> String {
-        self.redirect.clone().unwrap_or_else(|| "/u/".to_string())
+        match self.redirect.as_deref() {
+            Some(path) if is_local(path) => path.to_string(),
+            _ => "/u/".to_string(),
+        }
+    }
+}
+
+/// Returns true if `path` can only lead to this site. Browsers read `//host` as another site, and
+/// `/\host` too as they treat backslashes like slashes.
+pub(crate) fn is_local(path: &str) -> bool {
+    path.starts_with('/')
+        && !path.starts_with("//")
+        && !path.contains('\\')
+        && !path.chars().any(char::is_control)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use actix_web::{cookie::Key, test, App};
+
+    use super::*;
+    use crate::{config::SessionConfig, services::throttle::BASE_DELAY};
+
+    /// The value of the hidden CSRF field on a page
+    fn csrf_token(body: &str) -> String {
+        body.split(r#"name="csrf" value=""#)
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap()
+            .to_string()
+    }
+
+    #[actix_web::test]
+    async fn test_is_local() {
+        for path in ["/", "/a/", "/m/home.md?x=1#top"] {
+            assert!(is_local(path), "{}", path);
+        }
+        for path in [
+            "",
+            "https://example.com",
+            "//example.com",
+            "/\\example.com",
+            "/\t/example.com",
+            "javascript:alert(1)",
+        ] {
+            assert!(!is_local(path), "{}", path);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_login_hardening() {
+        let dir = tempfile::tempdir().unwrap();
+        let users = Users::load(dir.path().join("users.toml"));
+        users.add("alex", "hunter2", Role::Admin).unwrap();
+        let audit = Arc::new(AuditLog::new(dir.path().join("audit.log")));
+        let throttle = Arc::new(LoginThrottle::default());
+        let config = SessionConfig::default();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(users)))
+                .app_data(web::Data::new(throttle.clone()))
+                .app_data(web::Data::new(audit.clone()))
+                .wrap(config.identity_middleware())
+                .wrap(config.session_middleware(Key::generate()))
+                .service(user_service()),
+        )
+        .await;
+
+        // The login page hands out the token along with the session
+        let req = test::TestRequest::get()
+            .uri("/u/?redirect=//example.com")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+        let body = test::read_body(res).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains(r#"action="/u/login?redirect=/u/""#));
+        let token = csrf_token(&body);
+
+        let login = |password: &str, token: &str| {
+            test::TestRequest::post()
+                .uri("/u/login?redirect=https://example.com")
+                .cookie(cookie.clone())
+                .peer_addr("203.0.113.5:1234".parse().unwrap())
+                .set_form([
+                    ("username", "alex"),
+                    ("password", password),
+                    ("csrf", token),
+                ])
+                .to_request()
+        };
+
+        let res = test::call_service(&app, login("hunter2", "forged")).await;
+        assert_eq!(res.status(), 403);
+
+        for _ in 0..6 {
+            let res = test::call_service(&app, login("wrong", &token)).await;
+            assert_eq!(res.status(), 401);
+        }
+        let res = test::call_service(&app, login("hunter2", &token)).await;
+        assert_eq!(res.status(), 429);
+        assert_eq!(res.headers().get("retry-after").unwrap(), "1");
+
+        // The wait is over a second after the last failure, and logging in forgets it
+        let ip = Some("203.0.113.5".parse().unwrap());
+        let later = Instant::now() + BASE_DELAY;
+        assert_eq!(throttle.reserve_at(ip, "alex", later), Ok(()));
+        throttle.succeeded(ip, "alex");
+        let res = test::call_service(&app, login("hunter2", &token)).await;
+        assert_eq!(res.status(), 302);
+        assert_eq!(res.headers().get("location").unwrap(), "/u/");
+
+        let events = audit
+            .recent(3)
+            .into_iter()
+            .map(|entry| entry.event)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                AuditEvent::Login,
+                AuditEvent::Throttled,
+                AuditEvent::LoginFailed
+            ]
+        );
+        assert_eq!(audit.recent(1)[0].ip, Some("203.0.113.5".parse().unwrap()));
+    }
+
+    #[actix_web::test]
+    async fn test_two_factor() {
+        let dir = tempfile::tempdir().unwrap();
+        let users = Arc::new(Users::load(dir.path().join("users.toml")));
+        users.add("alex", "hunter2", Role::Admin).unwrap();
+        let audit = Arc::new(AuditLog::new(dir.path().join("audit.log")));
+        let config = SessionConfig::default();
+        let site: Config = toml::from_str(r#"site_url = "https://example.com""#).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(site)))
+                .app_data(web::Data::new(users.clone()))
+                .app_data(web::Data::new(Arc::new(LoginThrottle::default())))
+                .app_data(web::Data::new(audit.clone()))
+                .wrap(config.identity_middleware())
+                .wrap(config.session_middleware(Key::generate()))
+                .service(user_service()),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/u/").to_request();
+        let res = test::call_service(&app, req).await;
+        let mut cookie = res.response().cookies().next().unwrap().into_owned();
+        let token = csrf_token(&String::from_utf8_lossy(&test::read_body(res).await));
+
+        // Each response may come with an updated session
+        macro_rules! call {
+            ($req:expr) => {{
+                let res = test::call_service(&app, $req.cookie(cookie.clone()).to_request()).await;
+                if let Some(updated) = res.response().cookies().next() {
+                    cookie = updated.into_owned();
+                }
+                res
+            }};
+        }
+
+        let res = call!(test::TestRequest::post()
+            .uri("/u/login?redirect=/a/")
+            .set_form([
+                ("username", "alex"),
+                ("password", "hunter2"),
+                ("csrf", &token)
+            ]));
+        assert_eq!(res.status(), 302);
+        assert_eq!(res.headers().get("location").unwrap(), "/a/");
+
+        // Enrolment takes a code from the scanned secret
+        let res = call!(test::TestRequest::get().uri("/u/2fa"));
+        let body = String::from_utf8_lossy(&test::read_body(res).await).to_string();
+        assert!(body.contains("<svg"));
+        let secret = body
+            .split("<code>")
+            .nth(1)
+            .and_then(|rest| rest.split('<').next())
+            .unwrap()
+            .to_string();
+
+        let enable = |code: &str| {
+            test::TestRequest::post()
+                .uri("/u/2fa/enable")
+                .set_form([("code", code), ("csrf", &token)])
+        };
+        let res = call!(enable("000000x"));
+        assert_eq!(res.status(), 400);
+        let res = call!(enable(&two_factor::current_code(&secret)));
+        assert_eq!(res.status(), 200);
+        let body = String::from_utf8_lossy(&test::read_body(res).await).to_string();
+        let recovery_code = body
+            .split("<li><code>")
+            .nth(1)
+            .and_then(|rest| rest.split('<').next())
+            .unwrap()
+            .to_string();
+        assert!(users.get("alex").unwrap().has_two_factor());
+
+        let res = call!(test::TestRequest::post()
+            .uri("/u/logout")
+            .set_form([("csrf", &token)]));
+        assert_eq!(res.status(), 302);
+
+        // Logging out ends the session, token and all
+        let res = call!(test::TestRequest::get().uri("/u/"));
+        let token = csrf_token(&String::from_utf8_lossy(&test::read_body(res).await));
+
+        // The password alone isn't enough any more
+        let res = call!(test::TestRequest::post()
+            .uri("/u/login?redirect=/a/")
+            .set_form([
+                ("username", "alex"),
+                ("password", "hunter2"),
+                ("csrf", &token)
+            ]));
+        assert_eq!(res.headers().get("location").unwrap(), "/u/code");
+        let res = call!(test::TestRequest::get().uri("/u/"));
+        let body = String::from_utf8_lossy(&test::read_body(res).await).to_string();
+        assert!(body.contains("Please login"));
+
+        let res = call!(test::TestRequest::get().uri("/u/code"));
+        assert_eq!(res.status(), 200);
+        let code = |code: &str| {
+            test::TestRequest::post()
+                .uri("/u/code")
+                .set_form([("code", code), ("csrf", &token)])
+        };
+        let res = call!(code("nope"));
+        assert_eq!(res.status(), 401);
+        let res = call!(code(&recovery_code));
+        assert_eq!(res.status(), 302);
+        assert_eq!(res.headers().get("location").unwrap(), "/a/");
+
+        let res = call!(test::TestRequest::get().uri("/u/"));
+        let body = String::from_utf8_lossy(&test::read_body(res).await).to_string();
+        assert!(body.contains("Two-factor authentication is on"));
+
+        // Nothing is left pending once logged in
+        let req = test::TestRequest::get()
+            .uri("/u/code")
+            .cookie(cookie)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 302);
+
+        let events = audit
+            .recent(4)
+            .into_iter()
+            .map(|entry| entry.event)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                AuditEvent::Login,
+                AuditEvent::RecoveryCodeUsed,
+                AuditEvent::SecondFactorFailed,
+                AuditEvent::Logout
+            ]
+        );
     }
 }
@@ -0,0 +1,530 @@
+//! "Who", tells visitors what the site sees of them.
+//!
+//! `/w/` reflects the client's address, location, user agent and headers as HTML, JSON or plain
+//! text depending on the `Accept` header (or `?format=`), and `/w/ip` is just the address for
+//! scripts. Recent requests to the site are kept in memory and shown to the admin at `/w/log`.
+//!
+//! Behind a reverse proxy the address comes from `Forwarded` or `X-Forwarded-For`, but only when
+//! the request was made by one of the configured trusted proxies.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, RwLock},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{HttpServiceFactory, ServiceRequest, ServiceResponse},
+    get,
+    http::header::{self, HeaderMap},
+    middleware::Next,
+    web::{self, redirect},
+    Either, HttpRequest, HttpResponse, Responder,
+};
+use chrono::{DateTime, Utc};
+use ipnetwork::IpNetwork;
+use maud::{html, Markup, DOCTYPE};
+use maxminddb::{geoip2, Reader};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::WhoConfig,
+    services::{Account, Role},
+};
+
+/// Where an address is, according to the geolocation database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Location {
+    pub country: Option<String>,
+    pub city: Option<String>,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.city, &self.country) {
+            (Some(city), Some(country)) => write!(f, "{}, {}", city, country),
+            (None, Some(place)) | (Some(place), None) => write!(f, "{}", place),
+            (None, None) => write!(f, "Unknown"),
+        }
+    }
+}
+
+/// A request made to the site
+#[derive(Debug, Clone)]
+pub struct Visit {
+    pub time: DateTime<Utc>,
+    pub ip: Option<IpAddr>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub user_agent: Option<String>,
+}
+
+/// Client address resolution, geolocation and the log of recent requests
+pub struct Who {
+    trusted_proxies: Vec<IpNetwork>,
+    geoip: Option<Reader<Vec<u8>>>,
+    log_size: usize,
+    visits: RwLock<VecDeque<Visit>>,
+}
+
+impl Who {
+    /// Opens the geolocation database, if one is configured
+    pub fn new(config: &WhoConfig) -> Result<Self, String> {
+        let geoip = config
+            .geoip_database
+            .as_ref()
+            .map(|path| {
+                Reader::open_readfile(path)
+                    .map_err(|e| format!("Could not open {}: {}", path.display(), e))
+            })
+            .transpose()?;
+
+        Ok(Who {
+            trusted_proxies: config.trusted_proxies.clone(),
+            geoip,
+            log_size: config.log_size,
+            visits: RwLock::new(VecDeque::new()),
+        })
+    }
+
+    fn trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|proxy| proxy.contains(ip))
+    }
+
+    /// The address of the client, taken from the forwarding headers if the request came through
+    /// trusted proxies.
+    ///
+    /// The forwarded addresses are read from the closest proxy outwards, the first one that isn't
+    /// a trusted proxy is the client. Anything before it could have been made up by the client.
+    pub fn client_ip(&self, peer: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let mut client = peer?.ip();
+        if !self.trusted(client) {
+            return Some(client);
+        }
+
+        for forwarded in forwarded_for(headers).into_iter().rev() {
+            match forwarded {
+                Some(ip) => client = ip,
+                // Obfuscated or unknown addresses can't be followed any further
+                None => break,
+            }
+            if !self.trusted(client) {
+                break;
+            }
+        }
+
+        Some(client)
+    }
+
+    /// Looks up where an address is, if there's a geolocation database
+    pub fn locate(&self, ip: IpAddr) -> Option<Location> {
+        let city: geoip2::City = self.geoip.as_ref()?.lookup(ip).ok()?;
+        let english = |names: Option<BTreeMap<&str, &str>>| {
+            names.and_then(|names| names.get("en").map(|name| name.to_string()))
+        };
+
+        Some(Location {
+            country: city.country.and_then(|country| english(country.names)),
+            city: city.city.and_then(|city| english(city.names)),
+        })
+    }
+
+    /// Adds a request to the log, forgetting the oldest one if it's full
+    pub fn record(&self, visit: Visit) {
+        if self.log_size == 0 {
+            return;
+        }
+
+        let mut visits = self.visits.write().unwrap();
+        if visits.len() >= self.log_size {
+            visits.pop_front();
+        }
+        visits.push_back(visit);
+    }
+
+    /// The logged requests, newest first
+    pub fn visits(&self) -> Vec<Visit> {
+        self.visits.read().unwrap().iter().rev().cloned().collect()
+    }
+}
+
+/// The address of the client making `req`, through the trusted proxies if the "who" service is set
+/// up
+pub(crate) fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    match req.app_data::<web::Data<Arc<Who>>>() {
+        Some(who) => who.client_ip(req.peer_addr(), req.headers()),
+        None => req.peer_addr().map(|addr| addr.ip()),
+    }
+}
+
+/// The `for` addresses of the `Forwarded` header, or of `X-Forwarded-For` without it, from the
+/// client to the closest proxy. Addresses that can't be parsed are `None`.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>()
+    };
+
+    let forwarded = values(header::FORWARDED);
+    if !forwarded.is_empty() {
+        return forwarded
+            .into_iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(key, _)| key.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, value)| parse_ip(value.trim_matches('"')))
+            })
+            .collect();
+    }
+
+    values(header::X_FORWARDED_FOR)
+        .into_iter()
+        .map(parse_ip)
+        .collect()
+}
+
+/// Parses an address that may have a port, `1.2.3.4`, `1.2.3.4:80`, `::1` or `[::1]:80`
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            value
+                .strip_prefix('[')
+                .and_then(|v| v.strip_suffix(']'))
+                .and_then(|v| v.parse().ok())
+        })
+}
+
+/// Middleware adding every request, except for static files, to the log
+pub async fn log_visits(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> actix_web::Result<ServiceResponse<impl MessageBody>> {
+    let Some(who) = req.app_data::<web::Data<Arc<Who>>>().cloned() else {
+        return next.call(req).await;
+    };
+    if req.path().starts_with("/s/") {
+        return next.call(req).await;
+    }
+
+    let mut visit = Visit {
+        time: Utc::now(),
+        ip: who.client_ip(req.peer_addr(), req.headers()),
+        method: req.method().to_string(),
+        path: req.path().to_string(),
+        status: 0,
+        user_agent: user_agent(req.headers()),
+    };
+
+    let res = next.call(req).await?;
+    visit.status = res.status().as_u16();
+    who.record(visit);
+
+    Ok(res)
+}
+
+fn user_agent(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Reflects the client's address, user agent and headers
+pub fn who_service() -> impl HttpServiceFactory {
+    web::scope("/w")
+        .service(redirect("", "/w/"))
+        .service(index)
+        .service(ip_handler)
+        .service(log)
+}
+
+/// What the site sees of a request
+#[derive(Debug, Serialize)]
+struct Reflection {
+    ip: Option<IpAddr>,
+    location: Option<Location>,
+    user_agent: Option<String>,
+    headers: BTreeMap<String, String>,
+}
+
+impl Reflection {
+    fn new(who: &Who, req: &HttpRequest) -> Self {
+        let ip = who.client_ip(req.peer_addr(), req.headers());
+
+        // Credentials aren't echoed back, they'd only end up in someone's terminal history
+        let headers = req
+            .headers()
+            .iter()
+            .filter(|(name, _)| *name != header::COOKIE && *name != header::AUTHORIZATION)
+            .map(|(name, value)| {
+                let value = String::from_utf8_lossy(value.as_bytes()).to_string();
+                (name.to_string(), value)
+            })
+            .collect();
+
+        Reflection {
+            ip,
+            location: ip.and_then(|ip| who.locate(ip)),
+            user_agent: user_agent(req.headers()),
+            headers,
+        }
+    }
+
+    fn text(&self) -> String {
+        let mut text = String::new();
+        let unknown = || "unknown".to_string();
+
+        text.push_str(&format!(
+            "ip: {}\n",
+            self.ip.map(|ip| ip.to_string()).unwrap_or_else(unknown)
+        ));
+        if let Some(location) = &self.location {
+            text.push_str(&format!("location: {}\n", location));
+        }
+        text.push_str(&format!(
+            "user-agent: {}\n\n",
+            self.user_agent.clone().unwrap_or_else(unknown)
+        ));
+        for (name, value) in &self.headers {
+            text.push_str(&format!("{}: {}\n", name, value));
+        }
+
+        text
+    }
+
+    fn html(&self) -> Markup {
+        html! {
+            (DOCTYPE)
+            (blog::header("Who am I?"))
+            main {
+                h1 { "Who am I?" }
+                dl {
+                    dt { "IP address" }
+                    dd { code { @match self.ip { Some(ip) => (ip), None => "Unknown" } } }
+                    @if let Some(location) = &self.location {
+                        dt { "Location" }
+                        dd { (location) }
+                    }
+                    dt { "User agent" }
+                    dd { (self.user_agent.as_deref().unwrap_or("Unknown")) }
+                }
+                h2 { "Headers" }
+                table {
+                    @for (name, value) in &self.headers {
+                        tr { td { code { (name) } } td { (value) } }
+                    }
+                }
+                p { "Also available as " a href="/w/?format=json" { "JSON" } " and " a href="/w/?format=text" { "plain text" } "." }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FormatQuery {
+    format: Option<String>,
+}
+
+#[get("/")]
+async fn index(
+    req: HttpRequest,
+    who: web::Data<Arc<Who>>,
+    query: web::Query<FormatQuery>,
+) -> impl Responder {
+    let reflection = Reflection::new(&who, &req);
+
+    // `curl` and friends accept anything, they get plain text
+    let accept = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let format = match query.format.as_deref() {
+        Some(format) => format,
+        None if accept.contains("text/html") => "html",
+        None if accept.contains("application/json") => "json",
+        None => "text",
+    };
+
+    match format {
+        "html" => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(reflection.html().into_string()),
+        "json" => HttpResponse::Ok().json(reflection),
+        _ => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(reflection.text()),
+    }
+}
+
+/// Just the address, `curl https://mahoney.best/w/ip`
+#[get("/ip")]
+async fn ip_handler(req: HttpRequest, who: web::Data<Arc<Who>>) -> impl Responder {
+    match who.client_ip(req.peer_addr(), req.headers()) {
+        Some(ip) => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(format!("{}\n", ip)),
+        None => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// The recent requests, for the admin
+#[get("/log")]
+async fn log(user: Option<Account>, who: web::Data<Arc<Who>>) -> Either<HttpResponse, Markup> {
+    // 302 redirect to login if not authenticated
+    if let Err(res) = Account::require_page(user, Role::Admin, "/w/log") {
+        return Either::Left(res);
+    }
+
+    Either::Right(html! {
+        (DOCTYPE)
+        (blog::header("Visitors"))
+        main {
+            h1 { "Recent visitors" }
+            table {
+                thead {
+                    tr { th { "Time" } th { "IP" } th { "Location" } th { "Request" } th { "Status" } th { "User agent" } }
+                }
+                tbody {
+                    @for visit in who.visits() {
+                        tr {
+                            td { (visit.time.format("%Y-%m-%d %H:%M:%S")) }
+                            td { @if let Some(ip) = visit.ip { (ip) } }
+                            td { @if let Some(location) = visit.ip.and_then(|ip| who.locate(ip)) { (location) } }
+                            td { (visit.method) " " (visit.path) }
+                            td { (visit.status) }
+                            td { (visit.user_agent.as_deref().unwrap_or_default()) }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_identity::IdentityMiddleware;
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::{cookie::Key, middleware::from_fn, test, App};
+
+    use super::*;
+
+    fn who() -> Who {
+        let config = WhoConfig {
+            trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+            ..WhoConfig::default()
+        };
+        Who::new(&config).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_client_ip() {
+        let who = who();
+        let client_ip = |peer: &str, headers: &[(&str, &str)]| {
+            let mut req = test::TestRequest::default().peer_addr(peer.parse().unwrap());
+            for header in headers {
+                req = req.insert_header(*header);
+            }
+            let req = req.to_http_request();
+            who.client_ip(req.peer_addr(), req.headers())
+                .unwrap()
+                .to_string()
+        };
+
+        // Untrusted clients can't pretend to be someone else
+        let spoofed = [("x-forwarded-for", "203.0.113.5")];
+        assert_eq!(client_ip("198.51.100.1:1234", &spoofed), "198.51.100.1");
+
+        let forwarded = [("x-forwarded-for", "192.0.2.1, 203.0.113.5, 10.0.0.2")];
+        assert_eq!(client_ip("10.0.0.1:1234", &forwarded), "203.0.113.5");
+
+        let forwarded = [
+            (
+                "forwarded",
+                r#"for="[2001:db8::1]:4711";proto=https, for=10.0.0.3"#,
+            ),
+            ("x-forwarded-for", "203.0.113.5"),
+        ];
+        assert_eq!(client_ip("10.0.0.1:1234", &forwarded), "2001:db8::1");
+
+        let obfuscated = [("forwarded", "for=_hidden, for=10.0.0.3")];
+        assert_eq!(client_ip("10.0.0.1:1234", &obfuscated), "10.0.0.3");
+    }
+
+    #[actix_web::test]
+    async fn test_who_service() {
+        let who = Arc::new(who());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(who.clone()))
+                .wrap(from_fn(log_visits))
+                .wrap(IdentityMiddleware::default())
+                .wrap(SessionMiddleware::new(
+                    CookieSessionStore::default(),
+                    Key::generate(),
+                ))
+                .service(who_service()),
+        )
+        .await;
+
+        let request = |accept: &str| {
+            test::TestRequest::get()
+                .uri("/w/")
+                .peer_addr("198.51.100.1:1234".parse().unwrap())
+                .insert_header(("accept", accept))
+                .insert_header(("user-agent", "curl/8.0"))
+                .insert_header(("cookie", "secret=1"))
+                .to_request()
+        };
+
+        let body = test::call_and_read_body(&app, request("*/*")).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.starts_with("ip: 198.51.100.1\nuser-agent: curl/8.0\n"));
+        assert!(!body.contains("secret"));
+
+        let json: serde_json::Value =
+            test::call_and_read_body_json(&app, request("application/json")).await;
+        assert_eq!(json["ip"], "198.51.100.1");
+        assert_eq!(json["headers"]["user-agent"], "curl/8.0");
+
+        let body = test::call_and_read_body(&app, request("text/html")).await;
+        assert!(String::from_utf8_lossy(&body).contains("<code>198.51.100.1</code>"));
+
+        let req = test::TestRequest::get()
+            .uri("/w/ip")
+            .peer_addr("198.51.100.1:1234".parse().unwrap())
+            .to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "198.51.100.1\n");
+
+        // The log is for the admin only, but records everyone
+        let req = test::TestRequest::get().uri("/w/log").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 302);
+
+        let visits = who.visits();
+        assert_eq!(visits.len(), 5);
+        assert_eq!(visits[0].path, "/w/log");
+        assert_eq!(visits[1].ip, Some("198.51.100.1".parse().unwrap()));
+        assert_eq!(visits[4].user_agent.as_deref(), Some("curl/8.0"));
+
+        // The log can be turned off
+        let config = WhoConfig {
+            log_size: 0,
+            ..WhoConfig::default()
+        };
+        let who = Who::new(&config).unwrap();
+        who.record(visits[0].clone());
+        assert!(who.visits().is_empty());
+    }
+}
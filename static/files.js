@@ -8,13 +8,18 @@ async function uploadFiles() {
         formData.append('file', files[i]);
     }
 
-    let resp = await fetch('/f/', {
+    const csrf = document.querySelector('input[name="csrf"]').value;
+
+    // Upload into the directory being browsed
+    let resp = await fetch('/f/' + location.search, {
         method: 'POST',
+        headers: { 'X-CSRF-Token': csrf },
         body: formData,
     });
 
     if (resp.ok) {
         console.log('Files uploaded successfully!');
+        location.reload();
     } else {
         console.error('Failed to upload files!', resp.text());
     }